@@ -123,13 +123,19 @@ pub fn missing_atlas(assets: &AssetServer) -> Handle<Image> {
     assets.load(asset_path!("missing_atlas.png"))
 }
 
+pub const ATLAS_TILING_SHADER: &str = asset_path!("shaders/atlas_tiling.wgsl");
+
 pub fn missing_skybox(assets: &AssetServer) -> Handle<Image> {
     assets.load(asset_path!("missing_skybox.ktx2"))
 }
 
 pub fn player(assets: &AssetServer, position: Vec3) -> impl Bundle {
+    (PlayerMarker, player_sprite(assets, position))
+}
+
+/// The player model without [`PlayerMarker`], for showing the player's size elsewhere
+pub fn player_sprite(assets: &AssetServer, position: Vec3) -> impl Bundle {
     (
-        PlayerMarker,
         Mesh3d(assets.add(Plane3d::new(Vec3::Z, Vec2::new(0.6, 0.75) / 2.0).into())),
         MeshMaterial3d(assets.add(StandardMaterial {
             base_color_texture: Some(assets.load(asset_path!("player.png"))),
@@ -0,0 +1,32 @@
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+
+struct AtlasTiling {
+    cell_size: vec2<f32>,
+}
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100)
+var<uniform> atlas_tiling: AtlasTiling;
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var tiled = in;
+#ifdef VERTEX_UVS_B
+    // Merged quads have their UVs counted in tiles, with the corner of their atlas cell in the
+    // second channel. Everything else has a negative corner and keeps its UVs.
+    if in.uv_b.x >= 0.0 {
+        tiled.uv = in.uv_b + fract(in.uv) * atlas_tiling.cell_size;
+    }
+#endif
+
+    var pbr_input = pbr_input_from_standard_material(tiled, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,155 @@
+use crate::Directories;
+use crate::load_file::{FileLoaded, LoadedFile, get_snapshot_data};
+use crate::settings::{EditorSettings, SaveSettings};
+use crate::sync::MapEdited;
+use bevy::prelude::*;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const AUTOSAVE_DIR: &str = "autosaves";
+
+pub struct AutosavePlugin;
+
+impl Plugin for AutosavePlugin {
+    fn build(&self, app: &mut App) {
+        let snapshots = app
+            .world()
+            .get_resource::<Directories>()
+            .map(|dirs| list_snapshots(&dirs.data.join(AUTOSAVE_DIR)))
+            .unwrap_or_default();
+        app.insert_resource(Autosaves {
+            snapshots,
+            pending: false,
+        })
+        .add_observer(on_map_edited)
+        .add_observer(on_file_loaded)
+        .add_systems(Update, autosave);
+    }
+}
+
+/// The autosave snapshots in the data directory, newest first
+#[derive(Resource)]
+pub struct Autosaves {
+    pub snapshots: Vec<AutosaveSnapshot>,
+    /// Whether the open file was edited since the last snapshot
+    pending: bool,
+}
+
+pub struct AutosaveSnapshot {
+    pub path: PathBuf,
+    /// The name of the file that was autosaved, without its extension
+    pub name: String,
+    pub time: SystemTime,
+}
+
+impl AutosaveSnapshot {
+    pub fn label(&self) -> String {
+        let minutes = SystemTime::now()
+            .duration_since(self.time)
+            .map_or(0, |x| x.as_secs() / 60);
+        let age = match minutes {
+            0 => "just now".to_string(),
+            1..60 => format!("{minutes} min ago"),
+            60..2880 => format!("{} h ago", minutes / 60),
+            _ => format!("{} days ago", minutes / 1440),
+        };
+        format!("{} ({age})", self.name)
+    }
+}
+
+/// Snapshots are named `<file name>-<seconds since the Unix epoch>.json`
+fn list_snapshots(dir: &Path) -> Vec<AutosaveSnapshot> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let (name, time) = path.file_stem()?.to_str()?.rsplit_once('-')?;
+            Some(AutosaveSnapshot {
+                name: name.to_string(),
+                time: UNIX_EPOCH + Duration::from_secs(time.parse().ok()?),
+                path: path.clone(),
+            })
+        })
+        .sorted_by_key(|x| Reverse(x.time))
+        .collect()
+}
+
+fn on_map_edited(_: On<MapEdited>, mut autosaves: ResMut<Autosaves>) {
+    autosaves.pending = true;
+}
+
+fn on_file_loaded(_: On<FileLoaded>, mut autosaves: ResMut<Autosaves>) {
+    autosaves.pending = false;
+}
+
+fn autosave(
+    time: Res<Time>,
+    mut elapsed: Local<f32>,
+    settings: Res<EditorSettings>,
+    file: Res<LoadedFile>,
+    dirs: Option<Res<Directories>>,
+    mut autosaves: ResMut<Autosaves>,
+) {
+    let autosave_settings = &settings.autosave;
+    if !autosave_settings.enabled {
+        *elapsed = 0.0;
+        return;
+    }
+    *elapsed += time.delta_secs();
+    if *elapsed < autosave_settings.interval_minutes as f32 * 60.0 {
+        return;
+    }
+    *elapsed = 0.0;
+    let Some(dirs) = dirs else {
+        return;
+    };
+    if !autosaves.pending || !file.dirty {
+        return;
+    }
+    autosaves.pending = false;
+
+    let dir = dirs.data.join(AUTOSAVE_DIR);
+    let name = file
+        .path
+        .as_ref()
+        .and_then(|x| x.file_stem())
+        .map_or_else(|| "Untitled".into(), |x| x.to_string_lossy());
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let path = dir.join(format!("{name}-{secs}.json"));
+    if let Err(err) = write_snapshot(&file, &dir, &path, &settings.save) {
+        warn!("Failed to autosave to {}: {err}", path.display());
+        return;
+    }
+    info!("Autosaved to {}", path.display());
+
+    autosaves.snapshots = list_snapshots(&dir);
+    let keep = autosave_settings.keep as usize;
+    if autosaves.snapshots.len() > keep {
+        for old in autosaves.snapshots.drain(keep..) {
+            if let Err(err) = fs::remove_file(&old.path) {
+                warn!(
+                    "Failed to remove old autosave {}: {err}",
+                    old.path.display()
+                );
+            }
+        }
+    }
+}
+
+fn write_snapshot(
+    file: &LoadedFile,
+    dir: &Path,
+    path: &Path,
+    settings: &SaveSettings,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(path, get_snapshot_data(file, dir, settings)?)?;
+    Ok(())
+}
@@ -70,6 +70,28 @@ pub trait UiDocking {
             DockNode { id }
         }
     }
+
+    /// Status bar along the bottom of the main viewport
+    #[doc(alias = "BeginViewportSideBar")]
+    fn viewport_status_bar(&self, f: impl FnOnce()) {
+        let flags = sys::ImGuiWindowFlags_NoScrollbar
+            | sys::ImGuiWindowFlags_NoSavedSettings
+            | sys::ImGuiWindowFlags_MenuBar;
+        unsafe {
+            let open = sys::igBeginViewportSideBar(
+                c"##Status bar".as_ptr(),
+                sys::igGetMainViewport(),
+                sys::ImGuiDir_Down as sys::ImGuiDir,
+                sys::igGetFrameHeight(),
+                flags as sys::ImGuiWindowFlags,
+            );
+            if open && sys::igBeginMenuBar() {
+                f();
+                sys::igEndMenuBar();
+            }
+            sys::igEnd();
+        }
+    }
 }
 
 impl UiDocking for Ui {}
@@ -0,0 +1,1027 @@
+use crate::TITLE;
+use crate::format_schema::map_schema;
+use crate::load_file::{LoadedFile, file_error, relative_texture_path};
+use crate::mesh::{MapModel, ModelMesh};
+use crate::report::{BoardSummary, ReportFormat};
+use crate::schema::{MpsMaterial, MpsVec2};
+use crate::settings::EditorSettings;
+use crate::tile_range::TileRange;
+use crate::viewport::ViewportTarget;
+use bevy::camera::ScalingMode;
+use bevy::core_pipeline::Skybox;
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+use bevy::render::view::screenshot::{Screenshot, ScreenshotCaptured};
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{AsyncComputeTaskPool, Task, block_on};
+use bevy_file_dialog::prelude::*;
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::{fs, io, mem};
+use transform_gizmo_bevy::prelude::GizmoCamera;
+
+const PIXELS_PER_TILE: f32 = 64.0;
+const MAX_IMAGE_SIZE: f32 = 8192.0;
+/// The largest side of the top-down image included with board reports
+const MAX_THUMBNAIL_SIZE: f32 = 1024.0;
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ImageExportState>()
+            .init_resource::<AtlasExportState>()
+            .init_resource::<ModelExportState>()
+            .init_resource::<ReportExportState>()
+            .init_resource::<ScreenshotExportState>()
+            .init_resource::<SchemaExportState>()
+            .init_resource::<ExportTasks>()
+            .add_message::<ExportFinished>()
+            .add_observer(on_export_image)
+            .add_observer(on_export_atlas_layout)
+            .add_observer(on_export_model)
+            .add_observer(on_export_report)
+            .add_observer(on_export_screenshot)
+            .add_observer(on_export_schema)
+            .add_systems(
+                Update,
+                (
+                    start_image_export,
+                    start_screenshot_export,
+                    capture_image_export,
+                    start_atlas_layout_export,
+                    start_model_export,
+                    start_report_export,
+                    start_schema_export,
+                    poll_export_tasks,
+                    report_finished_exports,
+                )
+                    .chain(),
+            );
+    }
+}
+
+pub struct ImageExportDialog;
+
+/// Renders a top-down orthographic image of the map to a PNG chosen by the user
+#[derive(Event, Copy, Clone, Debug)]
+pub struct ExportImage {
+    /// The tiles to frame, or the whole map if `None`
+    pub range: Option<TileRange>,
+}
+
+pub struct AtlasExportDialog;
+
+/// Saves the atlas with a grid over its cells, optionally labeled with how many tiles use them
+#[derive(Event, Copy, Clone, Debug)]
+pub struct ExportAtlasLayout {
+    pub show_counts: bool,
+}
+
+/// Whether to show counts in the atlas layout whose path is being picked
+#[derive(Resource, Default)]
+struct AtlasExportState(Option<bool>);
+
+pub struct ModelExportDialog;
+
+/// Saves the map mesh to a file that can be opened in other 3D tools
+#[derive(Event, Copy, Clone, Debug)]
+pub struct ExportModel(pub ModelFormat);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModelFormat {
+    /// Binary glTF, with the atlas referenced by a relative path
+    Gltf,
+    /// Wavefront OBJ, with the materials in an MTL file next to it
+    Obj,
+}
+
+/// The format of the model export whose path is being picked
+#[derive(Resource, Default)]
+struct ModelExportState(Option<ModelFormat>);
+
+pub struct SchemaExportDialog;
+
+/// Saves a JSON Schema of the map format, for validating maps in other tools
+#[derive(Event, Copy, Clone, Debug)]
+pub struct ExportSchema;
+
+/// Whether the path of a schema export is being picked
+#[derive(Resource, Default)]
+struct SchemaExportState(bool);
+
+pub struct ReportExportDialog;
+
+/// Saves a summary of the board along with a top-down thumbnail next to it
+#[derive(Event, Copy, Clone, Debug)]
+pub struct ExportReport(pub ReportFormat);
+
+/// The format of the report whose path is being picked
+#[derive(Resource, Default)]
+struct ReportExportState(Option<ReportFormat>);
+
+pub struct ScreenshotExportDialog;
+
+/// Saves what the viewport is showing, rendered at `scale` times its size
+#[derive(Event, Copy, Clone, Debug)]
+pub struct ExportScreenshot {
+    pub scale: u32,
+}
+
+/// The scale of the screenshot whose path is being picked
+#[derive(Resource, Default)]
+struct ScreenshotExportState(Option<u32>);
+
+/// Sent once an export has been written to disk, or has failed to be
+#[derive(Message)]
+pub struct ExportFinished {
+    pub result: Result<(), String>,
+    pub path: PathBuf,
+}
+
+/// Exports currently being written on the [`AsyncComputeTaskPool`]
+#[derive(Resource, Default)]
+pub struct ExportTasks(Vec<(PathBuf, Task<Result<(), String>>)>);
+
+impl ExportTasks {
+    pub fn is_running(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.0.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Runs `write` off the main thread. If it fails, whatever it managed to write is removed so
+    /// that no truncated file is left behind.
+    pub fn spawn(
+        &mut self,
+        path: PathBuf,
+        write: impl FnOnce(&Path) -> Result<(), String> + Send + 'static,
+    ) {
+        let task_path = path.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let result = write(&task_path);
+            if result.is_err() {
+                let _ = fs::remove_file(&task_path);
+            }
+            result
+        });
+        self.0.push((path, task));
+    }
+}
+
+#[derive(Resource, Default)]
+enum ImageExportState {
+    #[default]
+    None,
+    PickingPath(Option<TileRange>),
+    Rendering {
+        camera: Entity,
+        image: Handle<Image>,
+        path: PathBuf,
+        /// A report to write along with the image, which it's the thumbnail of
+        report: Option<(PathBuf, String)>,
+    },
+}
+
+fn on_export_image(
+    on: On<ExportImage>,
+    mut commands: Commands,
+    mut state: ResMut<ImageExportState>,
+) {
+    if !matches!(*state, ImageExportState::None) {
+        return;
+    }
+    *state = ImageExportState::PickingPath(on.range);
+    commands
+        .dialog()
+        .set_title("Export image")
+        .add_filter("PNG images", &["png"])
+        .save_file(vec![], ImageExportDialog);
+}
+
+fn start_image_export(
+    mut saved_reader: MessageReader<DialogFileSaved<ImageExportDialog>>,
+    mut canceled_reader: MessageReader<DialogFileSaveCanceled<ImageExportDialog>>,
+    mut commands: Commands,
+    mut state: ResMut<ImageExportState>,
+    mut images: ResMut<Assets<Image>>,
+    file: Res<LoadedFile>,
+) {
+    if canceled_reader.read().count() > 0 {
+        *state = ImageExportState::None;
+    }
+    for saved in saved_reader.read() {
+        let ImageExportState::PickingPath(range) = *state else {
+            continue;
+        };
+        if let Err(err) = &saved.result {
+            error!("Failed to create {}: {err}", saved.path.display());
+            *state = ImageExportState::None;
+            continue;
+        }
+        let (camera, image) =
+            spawn_top_down_camera(&mut commands, &mut images, &file, range, MAX_IMAGE_SIZE);
+        *state = ImageExportState::Rendering {
+            camera,
+            image,
+            path: saved.path.clone(),
+            report: None,
+        };
+    }
+}
+
+/// Spawns an orthographic camera looking straight down at `range`, or the whole map if `None`,
+/// rendering to an image with no side longer than `max_size`
+fn spawn_top_down_camera(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    file: &LoadedFile,
+    range: Option<TileRange>,
+    max_size: f32,
+) -> (Entity, Handle<Image>) {
+    let range = range.unwrap_or(TileRange {
+        start: MpsVec2::ZERO,
+        end: file.file.map_size().unwrap_or(MpsVec2::ONE) - MpsVec2::ONE,
+    });
+    let world_size = Vec2::new(
+        (range.end.x - range.start.x + 1) as f32,
+        (range.end.y - range.start.y + 1) as f32,
+    );
+    let scale = PIXELS_PER_TILE.min(max_size / world_size.max_element());
+    let image_size = (world_size * scale).round().max(Vec2::ONE).as_uvec2();
+    let image = images.add(Image::new_target_texture(
+        image_size.x,
+        image_size.y,
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+
+    let center = Vec2::new(
+        (range.start.x + range.end.x) as f32 / 2.0,
+        (range.start.y + range.end.y) as f32 / 2.0,
+    );
+    let top = file
+        .file
+        .data
+        .iter()
+        .map(|tile| tile.height.max_height())
+        .fold(0.0, f64::max) as f32
+        + 10.0;
+    let camera = commands
+        .spawn((
+            Camera3d::default(),
+            Camera {
+                target: image.clone().into(),
+                order: 1,
+                ..Default::default()
+            },
+            Projection::Orthographic(OrthographicProjection {
+                scaling_mode: ScalingMode::Fixed {
+                    width: world_size.x,
+                    height: world_size.y,
+                },
+                ..OrthographicProjection::default_3d()
+            }),
+            Transform::from_xyz(center.x, top, center.y)
+                .looking_at(Vec3::new(center.x, 0.0, center.y), Vec3::NEG_Z),
+        ))
+        .id();
+    (camera, image)
+}
+
+fn on_export_screenshot(
+    on: On<ExportScreenshot>,
+    mut commands: Commands,
+    mut state: ResMut<ScreenshotExportState>,
+) {
+    if state.0.is_some() {
+        return;
+    }
+    state.0 = Some(on.scale);
+    commands
+        .dialog()
+        .set_title("Export screenshot")
+        .add_filter("PNG images", &["png"])
+        .save_file(vec![], ScreenshotExportDialog);
+}
+
+/// Renders the view of the viewport camera again at the chosen scale, which is then captured
+/// the same way as a top-down image
+#[allow(clippy::too_many_arguments)]
+fn start_screenshot_export(
+    mut saved_reader: MessageReader<DialogFileSaved<ScreenshotExportDialog>>,
+    mut canceled_reader: MessageReader<DialogFileSaveCanceled<ScreenshotExportDialog>>,
+    mut commands: Commands,
+    mut state: ResMut<ScreenshotExportState>,
+    mut image_state: ResMut<ImageExportState>,
+    mut images: ResMut<Assets<Image>>,
+    viewport_target: Res<ViewportTarget>,
+    viewport_camera: Query<(&Camera, &Transform, &Projection, Option<&Skybox>), With<GizmoCamera>>,
+) {
+    if canceled_reader.read().count() > 0 {
+        state.0 = None;
+    }
+    for saved in saved_reader.read() {
+        let Some(scale) = state.0.take() else {
+            continue;
+        };
+        if let Err(err) = &saved.result {
+            error!("Failed to create {}: {err}", saved.path.display());
+            continue;
+        }
+        let Ok((viewport, transform, projection, skybox)) = viewport_camera.single() else {
+            file_error("export", &"The viewport camera couldn't be found");
+            continue;
+        };
+        if !matches!(*image_state, ImageExportState::None) {
+            file_error("export", &"Another image is still being exported");
+            continue;
+        }
+
+        let viewport_size = images
+            .get(&viewport_target.texture)
+            .map_or(UVec2::ONE, Image::size)
+            .as_vec2();
+        // Both sides are scaled by the same amount, so the screenshot keeps the viewport's shape
+        let scale = (scale as f32).min(MAX_IMAGE_SIZE / viewport_size.max_element());
+        let size = (viewport_size * scale).max(Vec2::ONE).as_uvec2();
+        let image = images.add(Image::new_target_texture(
+            size.x,
+            size.y,
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        let mut camera = commands.spawn((
+            Camera3d::default(),
+            Camera {
+                target: image.clone().into(),
+                order: 1,
+                clear_color: viewport.clear_color.clone(),
+                ..Default::default()
+            },
+            projection.clone(),
+            *transform,
+        ));
+        if let Some(skybox) = skybox {
+            camera.insert(skybox.clone());
+        }
+        *image_state = ImageExportState::Rendering {
+            camera: camera.id(),
+            image,
+            path: saved.path.clone(),
+            report: None,
+        };
+    }
+}
+
+/// Takes the screenshot one frame after the export camera is spawned, so that it has rendered
+/// something by then
+fn capture_image_export(
+    mut commands: Commands,
+    mut state: ResMut<ImageExportState>,
+    mut waited: Local<bool>,
+) {
+    let ImageExportState::Rendering { .. } = *state else {
+        return;
+    };
+    if !*waited {
+        *waited = true;
+        return;
+    }
+    *waited = false;
+
+    let ImageExportState::Rendering {
+        camera,
+        image,
+        path,
+        report,
+    } = mem::take(&mut *state)
+    else {
+        unreachable!();
+    };
+    commands.spawn(Screenshot::image(image)).observe(
+        move |on: On<ScreenshotCaptured>,
+              mut commands: Commands,
+              mut tasks: ResMut<ExportTasks>| {
+            commands.entity(camera).despawn();
+            let image = on.image.clone();
+            tasks.spawn(path.clone(), move |path| write_png(image, path));
+            if let Some((report_path, contents)) = report.clone() {
+                tasks.spawn(report_path, move |path| {
+                    fs::write(path, contents).map_err(|e| e.to_string())
+                });
+            }
+        },
+    );
+}
+
+fn write_png(image: Image, path: &Path) -> Result<(), String> {
+    image
+        .try_into_dynamic()
+        .map_err(|e| e.to_string())?
+        .to_rgba8()
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| e.to_string())
+}
+
+fn poll_export_tasks(
+    mut tasks: ResMut<ExportTasks>,
+    mut finished_writer: MessageWriter<ExportFinished>,
+) {
+    tasks.0.retain_mut(|(path, task)| {
+        let Some(result) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+        finished_writer.write(ExportFinished {
+            result,
+            path: path.clone(),
+        });
+        false
+    });
+}
+
+fn report_finished_exports(mut finished_reader: MessageReader<ExportFinished>) {
+    for finished in finished_reader.read() {
+        match &finished.result {
+            Ok(()) => info!("Exported {}", finished.path.display()),
+            Err(err) => file_error("export", err),
+        }
+    }
+}
+
+fn on_export_atlas_layout(
+    on: On<ExportAtlasLayout>,
+    mut commands: Commands,
+    mut state: ResMut<AtlasExportState>,
+) {
+    if state.0.is_some() {
+        return;
+    }
+    state.0 = Some(on.show_counts);
+    commands
+        .dialog()
+        .set_title("Export atlas layout")
+        .add_filter("PNG images", &["png"])
+        .save_file(vec![], AtlasExportDialog);
+}
+
+fn start_atlas_layout_export(
+    mut saved_reader: MessageReader<DialogFileSaved<AtlasExportDialog>>,
+    mut canceled_reader: MessageReader<DialogFileSaveCanceled<AtlasExportDialog>>,
+    mut state: ResMut<AtlasExportState>,
+    mut tasks: ResMut<ExportTasks>,
+    images: Res<Assets<Image>>,
+    file: Res<LoadedFile>,
+) {
+    if canceled_reader.read().count() > 0 {
+        state.0 = None;
+    }
+    for saved in saved_reader.read() {
+        let Some(show_counts) = state.0.take() else {
+            continue;
+        };
+        if let Err(err) = &saved.result {
+            error!("Failed to create {}: {err}", saved.path.display());
+            continue;
+        }
+        let Some(atlas) = images.get(&file.loaded_textures.atlas.image) else {
+            file_error("export", &"The atlas hasn't been loaded");
+            continue;
+        };
+        let atlas = atlas.clone();
+        let counts = show_counts.then(|| file.file.material_usage());
+        tasks.spawn(saved.path.clone(), move |path| {
+            write_atlas_layout(atlas, counts, path)
+        });
+    }
+}
+
+fn on_export_model(
+    on: On<ExportModel>,
+    mut commands: Commands,
+    mut state: ResMut<ModelExportState>,
+) {
+    if state.0.is_some() {
+        return;
+    }
+    state.0 = Some(on.0);
+    let (title, filter, extension) = match on.0 {
+        ModelFormat::Gltf => ("Export glTF", "glTF binary", "glb"),
+        ModelFormat::Obj => ("Export OBJ", "Wavefront OBJ", "obj"),
+    };
+    commands
+        .dialog()
+        .set_title(title)
+        .add_filter(filter, &[extension])
+        .save_file(vec![], ModelExportDialog);
+}
+
+fn start_model_export(
+    mut saved_reader: MessageReader<DialogFileSaved<ModelExportDialog>>,
+    mut canceled_reader: MessageReader<DialogFileSaveCanceled<ModelExportDialog>>,
+    mut state: ResMut<ModelExportState>,
+    mut tasks: ResMut<ExportTasks>,
+    file: Res<LoadedFile>,
+    settings: Res<EditorSettings>,
+) {
+    if canceled_reader.read().count() > 0 {
+        state.0 = None;
+    }
+    for saved in saved_reader.read() {
+        let Some(format) = state.0.take() else {
+            continue;
+        };
+        if let Err(err) = &saved.result {
+            error!("Failed to create {}: {err}", saved.path.display());
+            continue;
+        }
+        let mut model = MapModel::new(
+            &file.file.data,
+            &settings.mesh,
+            settings.export.include_floor,
+        );
+        if settings.export.flip_winding {
+            model.flip_winding();
+        }
+        let export_dir = saved
+            .path
+            .parent()
+            .expect("Saved file should have a parent directory");
+        // Relative paths keep working when the map and the export are moved together
+        let atlas_path = relative_texture_path(&file.loaded_textures.atlas, export_dir)
+            .inspect_err(|err| warn!("Exporting without the atlas texture: {err}"))
+            .ok()
+            .map(|path| path.into_string());
+        let colors = [settings.mesh.block_color, settings.mesh.trim_color];
+        match format {
+            ModelFormat::Gltf => {
+                let atlas_uri = atlas_path.map(|path| path.replace('%', "%25").replace(' ', "%20"));
+                tasks.spawn(saved.path.clone(), move |path| {
+                    write_glb(&model, atlas_uri, colors, path)
+                });
+            }
+            ModelFormat::Obj => tasks.spawn(saved.path.clone(), move |path| {
+                write_obj(&model, atlas_path, colors, path).map_err(|e| e.to_string())
+            }),
+        }
+    }
+}
+
+fn on_export_report(
+    on: On<ExportReport>,
+    mut commands: Commands,
+    mut state: ResMut<ReportExportState>,
+) {
+    if state.0.is_some() {
+        return;
+    }
+    state.0 = Some(on.0);
+    let (title, filter) = match on.0 {
+        ReportFormat::Markdown => ("Export Markdown report", "Markdown"),
+        ReportFormat::Html => ("Export HTML report", "HTML pages"),
+    };
+    commands
+        .dialog()
+        .set_title(title)
+        .add_filter(filter, &[on.0.extension()])
+        .save_file(vec![], ReportExportDialog);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn start_report_export(
+    mut saved_reader: MessageReader<DialogFileSaved<ReportExportDialog>>,
+    mut canceled_reader: MessageReader<DialogFileSaveCanceled<ReportExportDialog>>,
+    mut commands: Commands,
+    mut state: ResMut<ReportExportState>,
+    mut image_state: ResMut<ImageExportState>,
+    mut tasks: ResMut<ExportTasks>,
+    mut images: ResMut<Assets<Image>>,
+    file: Res<LoadedFile>,
+    settings: Res<EditorSettings>,
+) {
+    if canceled_reader.read().count() > 0 {
+        state.0 = None;
+    }
+    for saved in saved_reader.read() {
+        let Some(format) = state.0.take() else {
+            continue;
+        };
+        if let Err(err) = &saved.result {
+            error!("Failed to create {}: {err}", saved.path.display());
+            continue;
+        }
+        let title = file.display_name().to_string();
+        let summary = BoardSummary::new(&file.file, title, settings.grid_origin);
+        // Only one top-down image can be rendered at a time
+        if !matches!(*image_state, ImageExportState::None) {
+            warn!("Exporting the report without a thumbnail, since an image is being exported");
+            let contents = summary.render(format, None);
+            tasks.spawn(saved.path.clone(), move |path| {
+                fs::write(path, contents).map_err(|e| e.to_string())
+            });
+            continue;
+        }
+
+        let stem = saved.path.file_stem().unwrap_or_default().to_string_lossy();
+        let thumbnail_name = format!("{stem}-thumbnail.png");
+        let contents = summary.render(format, Some(&thumbnail_name));
+        let (camera, image) =
+            spawn_top_down_camera(&mut commands, &mut images, &file, None, MAX_THUMBNAIL_SIZE);
+        *image_state = ImageExportState::Rendering {
+            camera,
+            image,
+            path: saved.path.with_file_name(thumbnail_name),
+            report: Some((saved.path.clone(), contents)),
+        };
+    }
+}
+
+fn on_export_schema(
+    _: On<ExportSchema>,
+    mut commands: Commands,
+    mut state: ResMut<SchemaExportState>,
+) {
+    if mem::replace(&mut state.0, true) {
+        return;
+    }
+    commands
+        .dialog()
+        .set_title("Export map schema")
+        .add_filter("JSON Schema", &["json"])
+        .save_file(vec![], SchemaExportDialog);
+}
+
+fn start_schema_export(
+    mut saved_reader: MessageReader<DialogFileSaved<SchemaExportDialog>>,
+    mut canceled_reader: MessageReader<DialogFileSaveCanceled<SchemaExportDialog>>,
+    mut state: ResMut<SchemaExportState>,
+    mut tasks: ResMut<ExportTasks>,
+) {
+    if canceled_reader.read().count() > 0 {
+        state.0 = false;
+    }
+    for saved in saved_reader.read() {
+        if !mem::take(&mut state.0) {
+            continue;
+        }
+        if let Err(err) = &saved.result {
+            error!("Failed to create {}: {err}", saved.path.display());
+            continue;
+        }
+        tasks.spawn(saved.path.clone(), |path| {
+            let contents =
+                serde_json::to_string_pretty(&map_schema()).map_err(|e| e.to_string())?;
+            fs::write(path, contents).map_err(|e| e.to_string())
+        });
+    }
+}
+
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// The binary chunk of a glTF file and the views and accessors into it
+#[derive(Default)]
+struct GltfBuffer {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl GltfBuffer {
+    /// Adds an accessor over `values`, returning its index
+    fn push<const N: usize, T: Copy>(
+        &mut self,
+        values: &[[T; N]],
+        to_bytes: fn(T) -> [u8; 4],
+        component_type: u32,
+        target: u32,
+        extra: Value,
+    ) -> usize {
+        let offset = self.data.len();
+        for &value in values.iter().flatten() {
+            self.data.extend(to_bytes(value));
+        }
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.data.len() - offset,
+            "target": target,
+        }));
+        let mut accessor = json!({
+            "bufferView": self.views.len() - 1,
+            "componentType": component_type,
+            "count": values.len(),
+            "type": match N {
+                1 => "SCALAR",
+                2 => "VEC2",
+                _ => "VEC3",
+            },
+        });
+        if let (Value::Object(accessor), Value::Object(extra)) = (&mut accessor, extra) {
+            accessor.extend(extra);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_mesh(&mut self, mesh: &ModelMesh, material: usize) -> Value {
+        let (min, max) = mesh.positions.iter().fold(
+            ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+            |(min, max), position| {
+                (
+                    [0, 1, 2].map(|i| min[i].min(position[i])),
+                    [0, 1, 2].map(|i| max[i].max(position[i])),
+                )
+            },
+        );
+        let position = self.push(
+            &mesh.positions,
+            f32::to_le_bytes,
+            GLTF_FLOAT,
+            GLTF_ARRAY_BUFFER,
+            json!({ "min": min, "max": max }),
+        );
+        let normal = self.push(
+            &mesh.normals,
+            f32::to_le_bytes,
+            GLTF_FLOAT,
+            GLTF_ARRAY_BUFFER,
+            json!({}),
+        );
+        let uv = self.push(
+            &mesh.uvs,
+            f32::to_le_bytes,
+            GLTF_FLOAT,
+            GLTF_ARRAY_BUFFER,
+            json!({}),
+        );
+        let indices = self.push(
+            &mesh
+                .indices
+                .iter()
+                .map(|&index| [index])
+                .collect::<Vec<_>>(),
+            u32::to_le_bytes,
+            GLTF_UNSIGNED_INT,
+            GLTF_ELEMENT_ARRAY_BUFFER,
+            json!({}),
+        );
+        json!({
+            "attributes": { "POSITION": position, "NORMAL": normal, "TEXCOORD_0": uv },
+            "indices": indices,
+            "material": material,
+        })
+    }
+}
+
+/// Writes `model` as a binary glTF file with a node for each part of the map. The atlas is
+/// referenced rather than embedded, so the exported model picks up changes to it.
+fn write_glb(
+    model: &MapModel,
+    atlas_uri: Option<String>,
+    [block_color, trim_color]: [[u8; 3]; 2],
+    path: &Path,
+) -> Result<(), String> {
+    let color_material = |name: &str, [r, g, b]: [u8; 3]| {
+        let color = Srgba::rgb_u8(r, g, b).to_linear();
+        json!({
+            "name": name,
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [color.red, color.green, color.blue, 1.0],
+                "metallicFactor": 0.0,
+            },
+        })
+    };
+    let mut terrain_material = json!({
+        "name": "Atlas",
+        "pbrMetallicRoughness": { "metallicFactor": 0.0 },
+    });
+    if atlas_uri.is_some() {
+        terrain_material["pbrMetallicRoughness"]["baseColorTexture"] = json!({ "index": 0 });
+    }
+    let materials = [
+        terrain_material,
+        color_material("Blocks", block_color),
+        color_material("Trims", trim_color),
+        json!({
+            "name": "Floor",
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [1.0, 1.0, 1.0, 0.25],
+                "metallicFactor": 0.0,
+            },
+            "alphaMode": "BLEND",
+            "doubleSided": true,
+        }),
+    ];
+
+    let mut buffer = GltfBuffer::default();
+    let mut meshes = vec![];
+    let mut nodes = vec![];
+    for (material, (name, mesh)) in model.parts().into_iter().enumerate() {
+        let Some(mesh) = mesh.filter(|mesh| !mesh.is_empty()) else {
+            continue;
+        };
+        let primitive = buffer.push_mesh(mesh, material);
+        meshes.push(json!({ "name": name, "primitives": [primitive] }));
+        nodes.push(json!({ "name": name, "mesh": meshes.len() - 1 }));
+    }
+
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": TITLE },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": buffer.accessors,
+        "bufferViews": buffer.views,
+        "buffers": [{ "byteLength": buffer.data.len() }],
+    });
+    if let Some(uri) = atlas_uri {
+        // Nearest filtering keeps the pixel art sharp, like in the editor
+        gltf["samplers"] = json!([{ "magFilter": 9728, "minFilter": 9728 }]);
+        gltf["images"] = json!([{ "uri": uri }]);
+        gltf["textures"] = json!([{ "sampler": 0, "source": 0 }]);
+    }
+
+    let mut json = serde_json::to_vec(&gltf).map_err(|e| e.to_string())?;
+    json.resize(json.len().next_multiple_of(4), b' ');
+    let mut data = buffer.data;
+    data.resize(data.len().next_multiple_of(4), 0);
+
+    let mut glb = Vec::with_capacity(28 + json.len() + data.len());
+    glb.extend(b"glTF");
+    glb.extend(2u32.to_le_bytes());
+    glb.extend(((28 + json.len() + data.len()) as u32).to_le_bytes());
+    glb.extend((json.len() as u32).to_le_bytes());
+    glb.extend(b"JSON");
+    glb.extend(json);
+    glb.extend((data.len() as u32).to_le_bytes());
+    glb.extend(b"BIN\0");
+    glb.extend(data);
+    fs::write(path, glb).map_err(|e| e.to_string())
+}
+
+/// Writes `model` as a Wavefront OBJ file with a group for each part of the map, and its materials
+/// to an MTL file with the same name
+fn write_obj(
+    model: &MapModel,
+    atlas_path: Option<String>,
+    [block_color, trim_color]: [[u8; 3]; 2],
+    path: &Path,
+) -> io::Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    let color = |[r, g, b]: [u8; 3]| {
+        format!(
+            "{} {} {}",
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0
+        )
+    };
+    writeln!(mtl, "# {TITLE}")?;
+    writeln!(mtl, "newmtl Terrain")?;
+    writeln!(mtl, "Kd 1 1 1")?;
+    if let Some(atlas) = atlas_path {
+        writeln!(mtl, "map_Kd {atlas}")?;
+    }
+    writeln!(mtl, "newmtl Blocks")?;
+    writeln!(mtl, "Kd {}", color(block_color))?;
+    writeln!(mtl, "newmtl Trims")?;
+    writeln!(mtl, "Kd {}", color(trim_color))?;
+    writeln!(mtl, "newmtl Floor")?;
+    writeln!(mtl, "Kd 1 1 1")?;
+    writeln!(mtl, "d 0.25")?;
+    mtl.flush()?;
+
+    let mut obj = BufWriter::new(File::create(path)?);
+    writeln!(obj, "# {TITLE}")?;
+    writeln!(
+        obj,
+        "mtllib {}",
+        mtl_path.file_name().unwrap_or_default().to_string_lossy()
+    )?;
+    // OBJ indices start at 1 and count every vertex before them in the file
+    let mut index_start = 1;
+    for (name, mesh) in model.parts() {
+        let Some(mesh) = mesh.filter(|mesh| !mesh.is_empty()) else {
+            continue;
+        };
+        writeln!(obj, "g {name}")?;
+        writeln!(obj, "usemtl {name}")?;
+        for [x, y, z] in &mesh.positions {
+            writeln!(obj, "v {x} {y} {z}")?;
+        }
+        // V goes up in OBJ and down in Bevy
+        for [u, v] in &mesh.uvs {
+            writeln!(obj, "vt {u} {}", 1.0 - v)?;
+        }
+        for [x, y, z] in &mesh.normals {
+            writeln!(obj, "vn {x} {y} {z}")?;
+        }
+        // The triangles keep the winding the mesh was built with, so the normals face the same way
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + index_start);
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        index_start += mesh.positions.len() as u32;
+    }
+    obj.flush()
+}
+
+/// Smallest size of an atlas cell in the exported image, so that the count labels fit
+const MIN_CELL_SIZE: u32 = 48;
+const GRID_COLOR: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+fn write_atlas_layout(atlas: Image, counts: Option<Vec<usize>>, path: &Path) -> Result<(), String> {
+    let atlas = atlas
+        .try_into_dynamic()
+        .map_err(|e| e.to_string())?
+        .to_rgba8();
+    let columns = MpsMaterial::TEXTURES_PER_ROW as u32;
+    let rows = (MpsMaterial::TEXTURES_COUNT / MpsMaterial::TEXTURES_PER_ROW) as u32;
+    let cell_width = (atlas.width() / columns).max(MIN_CELL_SIZE);
+    let cell_height = (atlas.height() / rows).max(MIN_CELL_SIZE);
+    let mut layout = image::imageops::resize(
+        &atlas,
+        cell_width * columns,
+        cell_height * rows,
+        image::imageops::FilterType::Nearest,
+    );
+
+    let grid_color = image::Rgba(GRID_COLOR);
+    for y in 0..layout.height() {
+        for x in 0..layout.width() {
+            if x % cell_width == 0 || y % cell_height == 0 {
+                layout.put_pixel(x, y, grid_color);
+            }
+        }
+    }
+
+    if let Some(counts) = counts {
+        let scale = (cell_width.min(cell_height) / 16).max(1);
+        for (index, &count) in counts.iter().enumerate() {
+            // The first row of materials is at the bottom of the atlas
+            let column = index as u32 % columns;
+            let row = rows - 1 - index as u32 / columns;
+            draw_number(
+                &mut layout,
+                count,
+                column * cell_width + 2,
+                row * cell_height + 2,
+                scale,
+            );
+        }
+    }
+
+    layout
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| e.to_string())
+}
+
+/// 3x5 bitmaps of the digits 0-9, one bit per pixel, starting from the top left
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+/// Draws white digits on a black box, so that they're readable over any texture
+fn draw_number(image: &mut image::RgbaImage, number: usize, x: u32, y: u32, scale: u32) {
+    let digits = number.to_string();
+    let width = (digits.len() as u32 * 4 + 1) * scale;
+    let height = 7 * scale;
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, image::Rgba([0, 0, 0, 0xFF]));
+        }
+    }
+    for (i, digit) in digits.bytes().enumerate() {
+        let bitmap = DIGITS[(digit - b'0') as usize];
+        let digit_x = x + (i as u32 * 4 + 1) * scale;
+        for bit in 0..15 {
+            if bitmap & (1 << (14 - bit)) == 0 {
+                continue;
+            }
+            let bit_x = digit_x + bit % 3 * scale;
+            let bit_y = y + (bit / 3 + 1) * scale;
+            for py in bit_y..(bit_y + scale).min(image.height()) {
+                for px in bit_x..(bit_x + scale).min(image.width()) {
+                    image.put_pixel(px, py, image::Rgba([0xFF; 4]));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::schema::{MpsMaterial, ShopItem, ShopNumber};
+use serde_json::{Value, json};
+use strum::VariantArray;
+
+const SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A JSON Schema describing the map format as [`MapFile`](crate::schema::MapFile) reads and
+/// writes it.
+///
+/// This is kept by hand next to the `serde` attributes in [`crate::schema`], so it spells out the
+/// shapes they produce: the `ramp` field choosing between a flat and a ramped height, connections
+/// that are either a bool or a condition, and wall materials that are one material or a list.
+pub fn map_schema() -> Value {
+    let shops = ShopNumber::VARIANTS
+        .iter()
+        .map(|&shop| (enum_name(shop), json!({"$ref": "#/$defs/shop"})))
+        .collect::<serde_json::Map<_, _>>();
+    let shop_items = ShopItem::VARIANTS
+        .iter()
+        .map(|&item| enum_name(item))
+        .collect::<Vec<_>>();
+    let popups = json!(["lucky-space", "star-1", "star-2", "star-steal"]);
+    let mut wall_visibility = sides(
+        json!({"enum": ["automatic", "hidden", "shown"], "default": "automatic"}),
+        false,
+    );
+    wall_visibility["description"] =
+        json!("Overrides for which walls the editor meshes, not read by the game");
+
+    json!({
+        "$schema": SCHEMA_DIALECT,
+        "title": "MSP map",
+        "type": "object",
+        "required": [
+            "startingTile",
+            "shopWarpTiles",
+            "starWarpTile",
+            "podiumPosition",
+            "resultsAnimCamPoses",
+            "tutorialStar",
+            "tutorialShop",
+            "skybox",
+            "atlas",
+            "shops",
+            "data",
+        ],
+        "properties": {
+            "startingTile": {"$ref": "#/$defs/vec2"},
+            "shopWarpTiles": {"type": "array", "items": {"$ref": "#/$defs/vec2"}},
+            "starWarpTile": {"$ref": "#/$defs/vec2"},
+            "podiumPosition": {"$ref": "#/$defs/vec2"},
+            "resultsAnimCamPoses": {"type": "array", "items": {"$ref": "#/$defs/vec3"}},
+            "tutorialStar": {"$ref": "#/$defs/transform"},
+            "tutorialShop": {"$ref": "#/$defs/transform"},
+            "skybox": {
+                "description": "Paths to the six faces of the skybox, relative to the map file, \
+                    or one path used for every face in older maps",
+                "oneOf": [
+                    {"type": "array", "items": {"type": "string"}, "minItems": 6, "maxItems": 6},
+                    {"type": "string"},
+                ],
+            },
+            "atlas": {
+                "description": "Path to the texture atlas, relative to the map file",
+                "type": "string",
+            },
+            "shops": {
+                "type": "object",
+                "required": shops.keys().collect::<Vec<_>>(),
+                "properties": shops,
+            },
+            "data": {
+                "description": "The rows of tiles, from north to south",
+                "type": "array",
+                "items": {"type": "array", "items": {"$ref": "#/$defs/tile"}},
+            },
+        },
+        "$defs": {
+            "vec2": {
+                "type": "object",
+                "required": ["x", "y"],
+                "properties": {
+                    "x": {"type": "integer"},
+                    "y": {"type": "integer"},
+                },
+            },
+            "vec3": {
+                "type": "object",
+                "required": ["x", "y", "z"],
+                "properties": {
+                    "x": {"type": "number"},
+                    "y": {"type": "number"},
+                    "z": {"type": "number"},
+                },
+            },
+            "transform": {
+                "type": "object",
+                "required": ["pos", "rot"],
+                "properties": {
+                    "pos": {"$ref": "#/$defs/vec3"},
+                    "rot": {
+                        "description": "Euler angles in radians",
+                        "$ref": "#/$defs/vec3",
+                    },
+                },
+            },
+            "shop": {
+                "type": "array",
+                "items": {"enum": shop_items},
+            },
+            "tile": {
+                "type": "object",
+                "required": [
+                    "ramp",
+                    "height",
+                    "connections",
+                    "material",
+                    "wallMaterial",
+                    "silverStarSpawnable",
+                ],
+                "properties": {
+                    "ramp": {"type": "boolean"},
+                    "height": true,
+                    "connections": sides(json!({"$ref": "#/$defs/connection"}), true),
+                    "material": {"$ref": "#/$defs/material"},
+                    "wallMaterial": sides(
+                        json!({
+                            "description": "One material, or one per layer from the top down",
+                            "oneOf": [
+                                {"$ref": "#/$defs/material"},
+                                {
+                                    "type": "array",
+                                    "items": {"$ref": "#/$defs/material"},
+                                    "minItems": 1,
+                                },
+                            ],
+                        }),
+                        true,
+                    ),
+                    "popup": {
+                        "oneOf": [
+                            {"enum": popups},
+                            {"enum": shops.keys().collect::<Vec<_>>()},
+                            {"type": "null"},
+                        ],
+                    },
+                    "coins": {"type": "integer", "default": 0},
+                    "walkOver": {"type": "boolean", "default": false},
+                    "silverStarSpawnable": {"type": "boolean"},
+                    "wallVisibility": wall_visibility,
+                },
+                "oneOf": [
+                    {
+                        "properties": {
+                            "ramp": {"const": false},
+                            "height": {"type": "number"},
+                        },
+                    },
+                    {
+                        "properties": {
+                            "ramp": {"const": true},
+                            "height": {"$ref": "#/$defs/ramp"},
+                        },
+                    },
+                ],
+            },
+            "ramp": {
+                "type": "object",
+                "required": ["dir", "pos", "neg"],
+                "properties": {
+                    "dir": {
+                        "description": "Whether the ramp slopes east-west (h) or north-south (v)",
+                        "enum": ["h", "v"],
+                    },
+                    "pos": {"description": "The height at the east or south end", "type": "number"},
+                    "neg": {"description": "The height at the west or north end", "type": "number"},
+                },
+            },
+            "connection": {
+                "description": "Whether the side can be crossed, or the condition for crossing it",
+                "oneOf": [
+                    {"type": "boolean"},
+                    {"enum": ["lock"]},
+                ],
+            },
+            "material": {
+                "description": "An index into the atlas, counted from the bottom-left cell and going row by row upwards",
+                "type": "integer",
+                "minimum": 0,
+                "maximum": MpsMaterial::TEXTURES_COUNT - 1,
+            },
+        },
+    })
+}
+
+/// An object with `n`, `e`, `s`, and `w` keys that all match `value`
+fn sides(value: Value, required: bool) -> Value {
+    let mut schema = json!({
+        "type": "object",
+        "properties": {"n": value.clone(), "e": value.clone(), "s": value.clone(), "w": value},
+    });
+    if required {
+        schema["required"] = json!(["n", "e", "s", "w"]);
+    }
+    schema
+}
+
+fn enum_name(value: impl serde::Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        _ => unreachable!("Unit variants should serialize to strings"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::map_schema;
+    use crate::schema::{
+        Connection, ConnectionCondition, MapFile, PopupType, TileData, TileHeight, TileRamp,
+        TileRampDirection, WallVisibility, WithEmptyFields,
+    };
+    use grid::Grid;
+    use monostate::MustBeBool;
+    use serde_json::Value;
+
+    /// Every key written by `MapFile` has to be described by the schema
+    #[test]
+    fn test_schema_covers_serialized_fields() {
+        let mut ramp = TileData {
+            height: TileHeight::Ramp {
+                ramp: MustBeBool,
+                height: TileRamp {
+                    dir: TileRampDirection::Vertical,
+                    pos: 2.0,
+                    neg: 1.0,
+                },
+            },
+            popup: Some(PopupType::StarSteal),
+            coins: 3,
+            ..Default::default()
+        };
+        ramp.connections.north = Connection::Conditional(ConnectionCondition::Lock);
+        ramp.wall_visibility.east = WallVisibility::Hidden;
+        let file = MapFile {
+            data: Grid::from_vec(vec![TileData::default(), ramp], 2),
+            ..Default::default()
+        };
+        let file = serde_json::to_value(WithEmptyFields(&file)).unwrap();
+
+        let schema = map_schema();
+        let file_keys = &schema["properties"];
+        for key in file.as_object().unwrap().keys() {
+            assert!(file_keys.get(key).is_some(), "{key} isn't in the schema");
+        }
+        let tile_keys = &schema["$defs"]["tile"]["properties"];
+        for tile in file["data"][0].as_array().unwrap() {
+            for key in tile.as_object().unwrap().keys() {
+                assert!(
+                    tile_keys.get(key).is_some(),
+                    "{key} isn't in the tile schema"
+                );
+            }
+        }
+        assert_eq!(file["data"][0][1]["connections"]["n"], "lock");
+        assert_eq!(file["data"][0][1]["ramp"], Value::Bool(true));
+    }
+}
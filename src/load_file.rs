@@ -1,22 +1,47 @@
-use crate::TITLE;
-use crate::schema::{MapFile, MpsVec2, Textures, TileData, TileHeight};
+use crate::persistence::{load_json, save_json};
+use crate::schema::{
+    Connection, MapFile, MpsMaterial, MpsVec2, Textures, TileData, TileHeight, TileRamp,
+    TileRampDirection, WithEmptyFields,
+};
+use crate::settings::{EditorSettings, GridOrigin, LoadSettings, SaveSettings};
 use crate::sync::{CameraId, Direction, ListEdit, MapEdit, MapEdited};
-use crate::tile_range::TileRange;
+use crate::tile_range::{Symmetry, TileRange};
 use crate::ui::UiState;
+use crate::utils::strip_json_extensions;
+use crate::validation::{ValidationIssue, validate};
+use crate::{Directories, TITLE};
 use bevy::image::{ImageFormatSetting, ImageLoaderSettings, ImageSampler};
 use bevy::prelude::*;
-use bevy::tasks::AsyncComputeTaskPool;
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{AsyncComputeTaskPool, Task, block_on};
 use bevy::window::PrimaryWindow;
 use bevy_file_dialog::DialogFileLoaded;
 use bevy_file_dialog::prelude::*;
+use itertools::Itertools;
+use monostate::MustBeBool;
 use native_dialog::MessageLevel;
 use relative_path::{PathExt, RelativePathBuf};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Serializer;
 use serde_json::ser::PrettyFormatter;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
-use std::{env, fs, io, mem, path};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, io, mem, path, slice};
+
+/// Options affecting how tile edits made from the editor spread to other tiles
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TileEditMode {
+    pub symmetry: Symmetry,
+    /// Closes connections toward empty tiles and opens them between raised tiles whenever a
+    /// height edit changes which tiles are raised
+    pub smart_connections: bool,
+    /// Sets the facing side of the neighboring tile along with each connection edit, since a
+    /// locked gate is only built when both sides have it
+    pub symmetric_connections: bool,
+}
 
 #[derive(Resource, Default)]
 pub struct LoadedFile {
@@ -24,8 +49,22 @@ pub struct LoadedFile {
     pub dirty: bool,
     pub file: MapFile,
     pub loaded_textures: Textures<LoadedTexture>,
+    /// Whether texture paths are saved as they are instead of relative to the file. This isn't
+    /// stored on its own, but read back from whether the saved texture paths are absolute.
+    absolute_texture_paths: bool,
     history: HistoryTracker,
     pub selected_range: Option<TileRange>,
+    save_confirmation: Option<SaveConfirmation>,
+}
+
+/// A prompt asking whether to save anyway, after warning about issues found while saving
+struct SaveConfirmation {
+    answer: Task<bool>,
+    /// The issues the user was warned about, which are checked again once they answer since the
+    /// map can still be edited in the meantime
+    issues: Vec<ValidationIssue>,
+    /// Whether to ask where to save the file once the user agrees
+    save_as: bool,
 }
 
 impl LoadedFile {
@@ -37,17 +76,450 @@ impl LoadedFile {
         }
     }
 
+    pub fn absolute_texture_paths(&self) -> bool {
+        self.absolute_texture_paths
+    }
+
+    pub fn set_absolute_texture_paths(&mut self, commands: &mut Commands, absolute: bool) {
+        if absolute == self.absolute_texture_paths {
+            return;
+        }
+        self.absolute_texture_paths = absolute;
+        if !self.dirty {
+            self.dirty = true;
+            commands.write_message(UpdateHeader);
+        }
+    }
+
     pub fn change_heights(
         &mut self,
         commands: &mut Commands,
         range: TileRange,
+        mode: TileEditMode,
         editor: impl Fn(TileHeight) -> TileHeight,
     ) -> bool {
         let new_heights = range
             .into_iter()
             .map(|x| editor(self.file[x].height))
             .collect();
-        self.edit_map(commands, MapEdit::ChangeHeight(range, new_heights))
+        self.edit_tiles(commands, MapEdit::ChangeHeight(range, new_heights), mode)
+    }
+
+    /// Turns a single row or column of tiles into a slope between the outer heights of its two
+    /// end tiles. With a `step`, the slope is built from flat stairs instead of ramps.
+    pub fn apply_ramp_profile(
+        &mut self,
+        commands: &mut Commands,
+        range: TileRange,
+        step: Option<f64>,
+        mode: TileEditMode,
+    ) -> bool {
+        let dir = if range.start.y == range.end.y {
+            TileRampDirection::Horizontal
+        } else if range.start.x == range.end.x {
+            TileRampDirection::Vertical
+        } else {
+            return false;
+        };
+        let tiles = range.area();
+        if tiles < 2 {
+            return false;
+        }
+
+        let start = self.file[range.start].height.neg_height();
+        let end = self.file[range.end].height.pos_height();
+        let lerp = |t: f64| start + (end - start) * t;
+        let heights = (0..tiles)
+            .map(|i| match step {
+                None => TileHeight::Ramp {
+                    ramp: MustBeBool,
+                    height: TileRamp {
+                        dir,
+                        neg: lerp(i as f64 / tiles as f64),
+                        pos: lerp((i + 1) as f64 / tiles as f64),
+                    },
+                },
+                Some(step) => TileHeight::Flat {
+                    ramp: MustBeBool,
+                    height: if i == tiles - 1 {
+                        end
+                    } else {
+                        start
+                            + ((lerp(i as f64 / (tiles - 1) as f64) - start) / step).round() * step
+                    },
+                },
+            })
+            .collect();
+        self.edit_tiles(commands, MapEdit::ChangeHeight(range, heights), mode)
+    }
+
+    /// Moves the ends of each ramp in `range` to meet the edges of the tiles before and after it
+    /// along its slope, as a single edit. Ramps next to an empty tile or the edge of the map are
+    /// skipped. Returns how many ramps were changed.
+    pub fn snap_ramps(
+        &mut self,
+        commands: &mut Commands,
+        range: TileRange,
+        mode: TileEditMode,
+    ) -> usize {
+        let mut snapped = 0;
+        let heights = range
+            .into_iter()
+            .map(|pos| {
+                let height = self.file[pos].height;
+                let TileHeight::Ramp { height: ramp, .. } = height else {
+                    return height;
+                };
+                let (neg_dir, pos_dir) = match ramp.dir {
+                    TileRampDirection::Horizontal => (Direction::West, Direction::East),
+                    TileRampDirection::Vertical => (Direction::North, Direction::South),
+                };
+                let neighbor = |dir: Direction| {
+                    let neighbor = pos + dir.offset();
+                    if self.in_bounds(neighbor) != neighbor {
+                        return None;
+                    }
+                    let height = self.file[neighbor].height;
+                    (height != TileHeight::default()).then_some(height)
+                };
+                let (Some(neg_neighbor), Some(pos_neighbor)) =
+                    (neighbor(neg_dir), neighbor(pos_dir))
+                else {
+                    return height;
+                };
+                // Ramps going the other way don't have a level edge to meet
+                let edge = |neighbor: TileHeight, pos_end: bool| match neighbor {
+                    TileHeight::Flat { height, .. } => Some(height),
+                    TileHeight::Ramp { height: other, .. } if other.dir == ramp.dir => {
+                        Some(if pos_end { other.pos } else { other.neg })
+                    }
+                    TileHeight::Ramp { .. } => None,
+                };
+                let neg = edge(neg_neighbor, true).unwrap_or(ramp.neg);
+                let pos = edge(pos_neighbor, false).unwrap_or(ramp.pos);
+                if neg == ramp.neg && pos == ramp.pos {
+                    return height;
+                }
+                snapped += 1;
+                TileHeight::Ramp {
+                    ramp: MustBeBool,
+                    height: TileRamp {
+                        dir: ramp.dir,
+                        pos,
+                        neg,
+                    },
+                }
+            })
+            .collect();
+        if snapped > 0 {
+            self.edit_tiles(commands, MapEdit::ChangeHeight(range, heights), mode);
+        }
+        snapped
+    }
+
+    /// Turns every tile in `range` into a ramp going up toward `uphill` by `rise` per tile,
+    /// starting at `start_height` on the downhill edge of the selection, so that the whole
+    /// selection forms one even slope
+    pub fn apply_shared_slope(
+        &mut self,
+        commands: &mut Commands,
+        range: TileRange,
+        uphill: Direction,
+        rise: f64,
+        start_height: f64,
+        mode: TileEditMode,
+    ) -> bool {
+        let heights = range
+            .into_iter()
+            .map(|pos| {
+                let (dir, index) = match uphill {
+                    Direction::West => (TileRampDirection::Horizontal, range.end.x - pos.x),
+                    Direction::East => (TileRampDirection::Horizontal, pos.x - range.start.x),
+                    Direction::North => (TileRampDirection::Vertical, range.end.y - pos.y),
+                    Direction::South => (TileRampDirection::Vertical, pos.y - range.start.y),
+                };
+                let low = start_height + rise * index as f64;
+                let high = low + rise;
+                // The positive end of a ramp is always on its east or south side
+                let (neg, pos) = match uphill {
+                    Direction::East | Direction::South => (low, high),
+                    Direction::West | Direction::North => (high, low),
+                };
+                TileHeight::Ramp {
+                    ramp: MustBeBool,
+                    height: TileRamp { dir, pos, neg },
+                }
+            })
+            .collect();
+        self.edit_tiles(commands, MapEdit::ChangeHeight(range, heights), mode)
+    }
+
+    /// Changes the top material of `start` and every tile connected to it through built tiles with
+    /// the same top material, as a single edit. Returns how many tiles were filled.
+    pub fn flood_fill_material(
+        &mut self,
+        commands: &mut Commands,
+        start: MpsVec2,
+        material: MpsMaterial,
+    ) -> usize {
+        let target = self.file[start].materials.material;
+        if target == material || self.file[start].height == TileHeight::default() {
+            return 0;
+        }
+        let mut filled = vec![false; self.file.data.rows() * self.file.data.cols()];
+        filled[self.file.tile_index(start)] = true;
+        let mut count = 1;
+        let mut range = TileRange { start, end: start };
+        let mut pending = vec![start];
+        while let Some(pos) = pending.pop() {
+            for dir in Direction::ALL_CLOCKWISE {
+                let neighbor = pos + dir.offset();
+                if self.in_bounds(neighbor) != neighbor
+                    || filled[self.file.tile_index(neighbor)]
+                    || self.file[neighbor].materials.material != target
+                    // Empty tiles aren't drawn, so filling through them would be surprising
+                    || self.file[neighbor].height == TileHeight::default()
+                {
+                    continue;
+                }
+                filled[self.file.tile_index(neighbor)] = true;
+                count += 1;
+                range = range.bounding(TileRange {
+                    start: neighbor,
+                    end: neighbor,
+                });
+                pending.push(neighbor);
+            }
+        }
+
+        // Tiles in the bounding range that weren't reached keep their material
+        let edits = range
+            .into_iter()
+            .map(|pos| {
+                ListEdit::Set(if filled[self.file.tile_index(pos)] {
+                    material
+                } else {
+                    self.file[pos].materials.material
+                })
+            })
+            .collect();
+        self.edit_map(commands, MapEdit::ChangeMaterial(range, None, edits));
+        count
+    }
+
+    /// Applies a tile edit along with its mirror images and connection fixes. Every edit is
+    /// queued in the same frame, so they are undone together.
+    pub fn edit_tiles(
+        &mut self,
+        commands: &mut Commands,
+        edit: MapEdit,
+        mode: TileEditMode,
+    ) -> bool {
+        let fix_connections = mode.smart_connections
+            && matches!(
+                edit,
+                MapEdit::AdjustHeight(_, _) | MapEdit::ChangeHeight(_, _)
+            );
+        let mut edits = vec![edit];
+        edits.extend(self.mirrored_edits(&edits[0], mode.symmetry));
+        if mode.symmetric_connections {
+            let opposite = edits
+                .iter()
+                .filter_map(|edit| self.opposite_connection_edit(edit))
+                .collect_vec();
+            edits.extend(opposite);
+        }
+
+        let raised_before: Vec<_> = if fix_connections {
+            edits
+                .iter()
+                .filter_map(MapEdit::tile_range)
+                .flatten()
+                .map(|pos| (pos, Self::raised(&self.file[pos])))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let mut changed = false;
+        for edit in edits {
+            changed |= self.edit_map(commands, edit);
+        }
+        if changed && fix_connections {
+            for edit in self.connection_fixes(&raised_before) {
+                self.edit_map(commands, edit);
+            }
+        }
+        changed
+    }
+
+    fn raised(tile: &TileData) -> bool {
+        tile.height.max_height() > 0.0
+    }
+
+    /// Connection edits for the neighborhoods of tiles that became raised or empty
+    fn connection_fixes(&self, raised_before: &[(MpsVec2, bool)]) -> Vec<MapEdit> {
+        let Some(map_size) = self.file.map_size() else {
+            return vec![];
+        };
+        let in_map = |pos: MpsVec2| pos.clamp(MpsVec2::ZERO, map_size - MpsVec2::ONE) == pos;
+        let flipped = raised_before
+            .iter()
+            .filter(|(pos, before)| Self::raised(&self.file[*pos]) != *before)
+            .map(|(pos, _)| *pos)
+            .collect_vec();
+        let Some(bounds) = flipped
+            .iter()
+            .map(|&pos| TileRange {
+                start: pos,
+                end: pos,
+            })
+            .reduce(TileRange::bounding)
+        else {
+            return vec![];
+        };
+        let bounds = TileRange {
+            start: (bounds.start - MpsVec2::ONE).max(MpsVec2::ZERO),
+            end: (bounds.end + MpsVec2::ONE).min(map_size - MpsVec2::ONE),
+        };
+
+        let mut result = vec![];
+        for &dir in Direction::ALL_CLOCKWISE {
+            let mut any_changed = false;
+            let connections = bounds
+                .into_iter()
+                .map(|pos| {
+                    let current = self.file[pos].connections[dir];
+                    let neighbor = pos + dir.offset();
+                    if !in_map(neighbor)
+                        || !Self::raised(&self.file[pos])
+                        || !(flipped.contains(&pos) || flipped.contains(&neighbor))
+                    {
+                        return current;
+                    }
+                    let new = if Self::raised(&self.file[neighbor]) {
+                        match current {
+                            Connection::Unconditional(false) => Connection::Unconditional(true),
+                            other => other,
+                        }
+                    } else {
+                        Connection::Unconditional(false)
+                    };
+                    any_changed |= new != current;
+                    new
+                })
+                .collect();
+            if any_changed {
+                result.push(MapEdit::ChangeConnection(bounds, dir, connections));
+            }
+        }
+        result
+    }
+
+    /// Sets the side of each neighbor facing the tiles of a connection edit to the same values.
+    /// Neighbors past the edge of the map are left out.
+    fn opposite_connection_edit(&self, edit: &MapEdit) -> Option<MapEdit> {
+        let MapEdit::ChangeConnection(range, dir, connections) = edit else {
+            return None;
+        };
+        let map_size = self.file.map_size()?;
+        let neighbors = TileRange {
+            start: range.start + dir.offset(),
+            end: range.end + dir.offset(),
+        }
+        .intersection(TileRange {
+            start: MpsVec2::ZERO,
+            end: map_size - MpsVec2::ONE,
+        })?;
+        Some(MapEdit::ChangeConnection(
+            neighbors,
+            dir.opposite(),
+            neighbors
+                .into_iter()
+                .map(|pos| connections[range.index_of(pos - dir.offset())])
+                .collect(),
+        ))
+    }
+
+    fn mirrored_edits(&self, edit: &MapEdit, symmetry: Symmetry) -> Vec<MapEdit> {
+        let (Some(map_size), Some(range)) = (self.file.map_size(), edit.tile_range()) else {
+            return vec![];
+        };
+
+        // Tiles on the center row/column mirror onto already-edited tiles, so only the parts of
+        // each mirrored range that haven't been covered yet are edited.
+        let mut covered = vec![range];
+        let mut result = vec![];
+        for &(mirror_x, mirror_y) in symmetry.mirrors() {
+            let mut pieces = vec![range.mirrored(map_size, mirror_x, mirror_y)];
+            for &done in &covered {
+                pieces = pieces
+                    .into_iter()
+                    .flat_map(|piece| piece.difference(done))
+                    .collect();
+            }
+
+            for piece in pieces {
+                covered.push(piece);
+                let source =
+                    |pos: MpsVec2| range.index_of(pos.mirrored(map_size, mirror_x, mirror_y));
+                macro_rules! remap {
+                    ($values:expr) => {
+                        piece.into_iter().map(|pos| $values[source(pos)]).collect()
+                    };
+                }
+
+                let mirrored = match edit {
+                    MapEdit::AdjustHeight(_, change) => MapEdit::AdjustHeight(piece, *change),
+                    MapEdit::ChangeHeight(_, heights) => MapEdit::ChangeHeight(
+                        piece,
+                        piece
+                            .into_iter()
+                            .map(|pos| heights[source(pos)].mirrored(mirror_x, mirror_y))
+                            .collect(),
+                    ),
+                    MapEdit::ChangeConnection(_, dir, connections) => MapEdit::ChangeConnection(
+                        piece,
+                        dir.mirrored(mirror_x, mirror_y),
+                        remap!(connections),
+                    ),
+                    MapEdit::ChangeMaterial(_, location, edits) => {
+                        // Reordering wall segments doesn't translate to tiles with different walls
+                        if !edits.iter().all(|x| matches!(x, ListEdit::Set(_))) {
+                            continue;
+                        }
+                        let location = location
+                            .map(|(side, index)| (side.mirrored(mirror_x, mirror_y), index));
+                        if let Some((side, index)) = location
+                            && piece.into_iter().any(|pos| {
+                                self.file[pos].materials.wall_material[side].len() <= index
+                            })
+                        {
+                            continue;
+                        }
+                        MapEdit::ChangeMaterial(piece, location, remap!(edits))
+                    }
+                    MapEdit::ChangePopupType(_, values) => {
+                        MapEdit::ChangePopupType(piece, remap!(values))
+                    }
+                    MapEdit::ChangeCoins(_, values) => MapEdit::ChangeCoins(piece, remap!(values)),
+                    MapEdit::ChangeWalkOver(_, values) => {
+                        MapEdit::ChangeWalkOver(piece, remap!(values))
+                    }
+                    MapEdit::ChangeSilverStarSpawnable(_, values) => {
+                        MapEdit::ChangeSilverStarSpawnable(piece, remap!(values))
+                    }
+                    MapEdit::ChangeWallVisibility(_, dir, values) => MapEdit::ChangeWallVisibility(
+                        piece,
+                        dir.mirrored(mirror_x, mirror_y),
+                        remap!(values),
+                    ),
+                    _ => continue,
+                };
+                result.push(mirrored);
+            }
+        }
+        result
     }
 
     pub fn edit_map(&mut self, commands: &mut Commands, edit: MapEdit) -> bool {
@@ -60,6 +532,9 @@ impl LoadedFile {
             {
                 true
             }
+            MapEdit::TransformTiles(range, transform) => {
+                transform.needs_square() && !range.is_square()
+            }
             _ => false,
         };
         if invalid {
@@ -82,25 +557,7 @@ impl LoadedFile {
             }
             MapEdit::Atlas(_) => MapEdit::Atlas(self.loaded_textures.atlas.clone()),
             MapEdit::ExpandMap(side, _) => MapEdit::ShrinkMap(*side),
-            MapEdit::ShrinkMap(side) => MapEdit::ExpandMap(
-                *side,
-                Some(match side {
-                    Direction::West => self.file.data.iter_col(0).cloned().collect(),
-                    Direction::East => self
-                        .file
-                        .data
-                        .iter_col(self.file.data.cols() - 1)
-                        .cloned()
-                        .collect(),
-                    Direction::North => self.file.data.iter_row(0).cloned().collect(),
-                    Direction::South => self
-                        .file
-                        .data
-                        .iter_row(self.file.data.rows() - 1)
-                        .cloned()
-                        .collect(),
-                }),
-            ),
+            MapEdit::ShrinkMap(side) => MapEdit::ExpandMap(*side, self.edge_tiles(*side)),
             MapEdit::ChangeCameraPos(camera, _) => MapEdit::ChangeCameraPos(
                 *camera,
                 match camera {
@@ -164,6 +621,24 @@ impl LoadedFile {
                     .map(|pos| self.file[pos].silver_star_spawnable)
                     .collect(),
             ),
+            MapEdit::ChangeWallVisibility(range, direction, _) => MapEdit::ChangeWallVisibility(
+                *range,
+                *direction,
+                range
+                    .into_iter()
+                    .map(|pos| self.file[pos].wall_visibility[*direction])
+                    .collect(),
+            ),
+            MapEdit::ReplaceTiles(range, _) => MapEdit::ReplaceTiles(
+                *range,
+                range
+                    .into_iter()
+                    .map(|pos| self.file[pos].clone())
+                    .collect(),
+            ),
+            MapEdit::TransformTiles(range, transform) => {
+                MapEdit::TransformTiles(*range, transform.reverse())
+            }
         };
         if edit == reversed {
             let is_equal_reverse = match &reversed {
@@ -173,6 +648,7 @@ impl LoadedFile {
                 MapEdit::ChangeMaterial(_, _, edits) => {
                     edits.iter().all(ListEdit::is_self_opposite)
                 }
+                MapEdit::TransformTiles(_, _) => true,
                 _ => false,
             };
             if !is_equal_reverse {
@@ -189,6 +665,145 @@ impl LoadedFile {
         true
     }
 
+    /// Adds `count` rows/columns along `side`, either blank or copies of the tiles along that edge
+    pub fn expand_map(
+        &mut self,
+        commands: &mut Commands,
+        side: Direction,
+        count: usize,
+        duplicate_edge: bool,
+    ) -> bool {
+        let mut changed = false;
+        for _ in 0..count {
+            let data = if duplicate_edge {
+                self.edge_tiles(side)
+            } else {
+                None
+            };
+            changed |= self.edit_map(commands, MapEdit::ExpandMap(side, data));
+        }
+        changed
+    }
+
+    /// Resets every tile to blank as a single edit, keeping the map size and everything that isn't
+    /// a tile
+    pub fn clear_tiles(&mut self, commands: &mut Commands) -> bool {
+        let Some(map_size) = self.file.map_size() else {
+            return false;
+        };
+        let range = TileRange {
+            start: MpsVec2::ZERO,
+            end: map_size - MpsVec2::ONE,
+        };
+        self.edit_map(
+            commands,
+            MapEdit::ReplaceTiles(range, vec![TileData::default(); range.area()]),
+        )
+    }
+
+    /// A copy of the row or column of tiles along `side`, or [`None`] if the map is empty
+    pub fn edge_tiles(&self, side: Direction) -> Option<Vec<TileData>> {
+        let data = &self.file.data;
+        if data.is_empty() {
+            return None;
+        }
+        Some(match side {
+            Direction::West => data.iter_col(0).cloned().collect(),
+            Direction::East => data.iter_col(data.cols() - 1).cloned().collect(),
+            Direction::North => data.iter_row(0).cloned().collect(),
+            Direction::South => data.iter_row(data.rows() - 1).cloned().collect(),
+        })
+    }
+
+    pub fn shrink_map(&mut self, commands: &mut Commands, side: Direction, count: usize) -> bool {
+        let mut changed = false;
+        for _ in 0..count {
+            changed |= self.edit_map(commands, MapEdit::ShrinkMap(side));
+        }
+        if changed {
+            self.clamp_special_positions(commands);
+        }
+        changed
+    }
+
+    /// Resizes the map to `size`, adding or removing rows/columns along `horizontal` and `vertical`
+    pub fn resize_map(
+        &mut self,
+        commands: &mut Commands,
+        size: MpsVec2,
+        horizontal: Direction,
+        vertical: Direction,
+        duplicate_edge: bool,
+    ) -> bool {
+        let mut changed = false;
+        for (side, current, target) in [
+            (horizontal, self.file.data.cols(), size.x.max(1) as usize),
+            (vertical, self.file.data.rows(), size.y.max(1) as usize),
+        ] {
+            changed |= match target.cmp(&current) {
+                Ordering::Greater => {
+                    self.expand_map(commands, side, target - current, duplicate_edge)
+                }
+                Ordering::Less => self.shrink_map(commands, side, current - target),
+                Ordering::Equal => false,
+            };
+        }
+        changed
+    }
+
+    /// Whether shrinking `count` rows/columns from `side` would remove built tiles or special
+    /// positions
+    pub fn shrink_loses_data(&self, side: Direction, count: usize) -> bool {
+        let data = &self.file.data;
+        let (rows, cols) = data.size();
+        let built = |tile: &TileData| *tile != TileData::default();
+        let loses_tiles = match side {
+            Direction::West => (0..count.min(cols)).any(|x| data.iter_col(x).any(built)),
+            Direction::East => {
+                (cols.saturating_sub(count)..cols).any(|x| data.iter_col(x).any(built))
+            }
+            Direction::North => (0..count.min(rows)).any(|y| data.iter_row(y).any(built)),
+            Direction::South => {
+                (rows.saturating_sub(count)..rows).any(|y| data.iter_row(y).any(built))
+            }
+        };
+        if loses_tiles {
+            return true;
+        }
+
+        let count = count as i32;
+        let removed = |pos: MpsVec2| match side {
+            Direction::West => pos.x < count,
+            Direction::East => pos.x >= cols as i32 - count,
+            Direction::North => pos.y < count,
+            Direction::South => pos.y >= rows as i32 - count,
+        };
+        let cameras = [self.file.tutorial_star.pos, self.file.tutorial_shop.pos]
+            .map(|pos| MpsVec2::new(pos.x.round() as i32, pos.z.round() as i32));
+        self.file
+            .special_positions()
+            .into_iter()
+            .map(|(_, pos)| pos)
+            .chain(cameras)
+            .any(removed)
+    }
+
+    fn clamp_special_positions(&mut self, commands: &mut Commands) {
+        let player_pos = self.in_bounds(self.file.starting_tile);
+        self.edit_map(commands, MapEdit::StartingTile(player_pos));
+
+        for index in 0..self.file.shop_warp_tiles.len() {
+            let tile = self.in_bounds(self.file.shop_warp_tiles[index]);
+            self.edit_map(commands, MapEdit::ShopWarpTile(index, ListEdit::Set(tile)));
+        }
+
+        let gold_pipe_pos = self.in_bounds(self.file.star_warp_tile);
+        self.edit_map(commands, MapEdit::StarWarpTile(gold_pipe_pos));
+
+        let podium_pos = self.in_bounds(self.file.podium_position);
+        self.edit_map(commands, MapEdit::PodiumPosition(podium_pos));
+    }
+
     pub fn undo(&mut self, commands: &mut Commands) {
         if self.history.index == 0 {
             return;
@@ -225,12 +840,24 @@ impl LoadedFile {
         self.history.index += 1;
     }
 
-    fn apply_queued_edits(&mut self) {
+    /// Turns the edits queued this frame into an undo step. Height edits arriving within
+    /// `coalesce_window` of the last step are folded into it if they change the same tiles.
+    fn apply_queued_edits(&mut self, coalesce_window: Duration) {
         let group = mem::take(&mut self.history.queued_items);
         if group.is_empty() {
             return;
         }
+        if self.history.coalesce(&group, coalesce_window) {
+            return;
+        }
+        self.history.last_pushed = Some(Instant::now());
+        if self.history.saved_index > self.history.index {
+            self.history.saved_discarded = true;
+        }
         self.history.items.truncate(self.history.index);
+        if group.len() > 1 {
+            self.history.merged_count = Some(group.len());
+        }
         self.history.items.push(if group.len() == 1 {
             HistoryItem::Simple(group.into_iter().next().unwrap())
         } else {
@@ -374,6 +1001,21 @@ impl LoadedFile {
                     self.file[pos].silver_star_spawnable = silver_star_spawnable;
                 }
             }
+            MapEdit::ChangeWallVisibility(range, direction, new) => {
+                check_edit_range!(range, new, ChangeWallVisibility);
+                for (pos, &visibility) in range.into_iter().zip(new) {
+                    self.file[pos].wall_visibility[*direction] = visibility;
+                }
+            }
+            MapEdit::ReplaceTiles(range, new) => {
+                check_edit_range!(range, new, ReplaceTiles);
+                for (pos, tile) in range.into_iter().zip(new) {
+                    self.file[pos] = tile.clone();
+                }
+            }
+            MapEdit::TransformTiles(range, transform) => {
+                self.file.transform_tiles(*range, *transform);
+            }
         }
 
         if !self.dirty {
@@ -397,6 +1039,91 @@ impl LoadedFile {
         self.file.tutorial_shop.pos += adjust.into();
     }
 
+    /// The file name shown in the title bar and tabs
+    pub fn display_name(&self) -> impl std::fmt::Display + '_ {
+        self.path
+            .as_ref()
+            .and_then(|x| x.file_name())
+            .map_or_else(|| OsStr::new("Untitled").display(), |x| x.display())
+    }
+
+    /// If several edits were just merged into one undo step, returns how many there were
+    pub fn take_merged_count(&mut self) -> Option<usize> {
+        self.history.merged_count.take()
+    }
+
+    /// Works out what changed since the file was last saved from the edits in the history. Returns
+    /// [`None`] if nothing changed, or if the saved state was discarded by undoing past it and
+    /// then editing.
+    pub fn changes_since_save(&self) -> Option<ChangeSummary> {
+        let history = &self.history;
+        if history.saved_discarded || history.saved_index == history.index {
+            return None;
+        }
+        let mut edits = vec![];
+        if history.saved_index < history.index {
+            for item in &history.items[history.saved_index..history.index] {
+                edits.extend(item.items().iter().map(|item| &item.forward));
+            }
+        } else {
+            for item in history.items[history.index..history.saved_index]
+                .iter()
+                .rev()
+            {
+                edits.extend(item.items().iter().rev().map(|item| &item.back));
+            }
+        }
+
+        let axis = |dir: Direction| {
+            let offset = dir.offset();
+            MpsVec2::new(offset.x.abs(), offset.y.abs())
+        };
+        let mut tiles = vec![];
+        let mut size_change = MpsVec2::ZERO;
+        let mut moved_positions = vec![];
+        let mut other_changes = vec![];
+        for edit in &edits {
+            if let Some(range) = edit.tile_range() {
+                tiles.extend(range);
+            }
+            let (list, name) = match edit {
+                MapEdit::StartingTile(_) => (&mut moved_positions, "Starting tile"),
+                MapEdit::ShopWarpTile(_, _) => (&mut moved_positions, "Shop hops"),
+                MapEdit::StarWarpTile(_) => (&mut moved_positions, "Star warp tile"),
+                MapEdit::PodiumPosition(_) => (&mut moved_positions, "Podium"),
+                MapEdit::ResultsCamera(_, _) => (&mut other_changes, "Results cameras"),
+                MapEdit::Skybox(_, _) => (&mut other_changes, "Skybox"),
+                MapEdit::Atlas(_) => (&mut other_changes, "Atlas"),
+                MapEdit::ChangeCameraPos(_, _) | MapEdit::ChangeCameraRot(_, _) => {
+                    (&mut other_changes, "Tutorial cameras")
+                }
+                MapEdit::EditShop(_, _, _) => (&mut other_changes, "Shops"),
+                MapEdit::ExpandMap(dir, _) => {
+                    size_change = size_change + axis(*dir);
+                    continue;
+                }
+                MapEdit::ShrinkMap(dir) => {
+                    size_change = size_change - axis(*dir);
+                    continue;
+                }
+                _ => continue,
+            };
+            if !list.contains(&name) {
+                list.push(name);
+            }
+        }
+
+        let new_size = self.file.map_size().unwrap_or_default();
+        Some(ChangeSummary {
+            edits: edits.len(),
+            tiles_modified: tiles.into_iter().sorted().dedup().count(),
+            old_size: new_size - size_change,
+            new_size,
+            moved_positions,
+            other_changes,
+        })
+    }
+
     pub fn can_undo(&self) -> bool {
         self.history.index > 0
     }
@@ -404,55 +1131,373 @@ impl LoadedFile {
     pub fn can_redo(&self) -> bool {
         self.history.index < self.history.items.len()
     }
-}
 
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct LoadedTexture {
-    pub path: PathBuf,
-    pub image: Handle<Image>,
-}
+    /// The tiles the next undo would change, if it changes any
+    pub fn undo_range(&self) -> Option<TileRange> {
+        let index = self.history.index.checked_sub(1)?;
+        self.history_item_range(index)
+    }
 
-#[derive(Default)]
-struct HistoryTracker {
-    items: Vec<HistoryItem>,
-    index: usize,
-    queued_items: Vec<SimpleHistoryItem>,
+    /// The tiles the next redo would change, if it changes any
+    pub fn redo_range(&self) -> Option<TileRange> {
+        self.history_item_range(self.history.index)
+    }
+
+    /// What the next undo would revert, if there's anything to undo
+    pub fn undo_description(&self) -> Option<&'static str> {
+        let index = self.history.index.checked_sub(1)?;
+        Some(self.history.items[index].description())
+    }
+
+    /// What the next redo would apply again, if there's anything to redo
+    pub fn redo_description(&self) -> Option<&'static str> {
+        Some(self.history.items.get(self.history.index)?.description())
+    }
+
+    fn history_item_range(&self, index: usize) -> Option<TileRange> {
+        let map_range = TileRange {
+            start: MpsVec2::ZERO,
+            end: self.file.map_size()? - MpsVec2::ONE,
+        };
+        self.history
+            .items
+            .get(index)?
+            .items()
+            .iter()
+            .filter_map(|item| item.forward.tile_range())
+            .reduce(TileRange::bounding)?
+            .intersection(map_range)
+    }
 }
 
-#[derive(Clone, Debug)]
-enum HistoryItem {
-    Simple(SimpleHistoryItem),
-    Group(Vec<SimpleHistoryItem>),
+/// Every open file in tab order. The active file lives in the [`LoadedFile`] resource so that the
+/// rest of the editor only ever deals with one file, and its slot here is left empty.
+#[derive(Resource)]
+pub struct OpenTabs {
+    tabs: Vec<Option<LoadedFile>>,
+    active: usize,
 }
 
-#[derive(Clone, Debug)]
-struct SimpleHistoryItem {
-    forward: MapEdit,
-    back: MapEdit,
+impl Default for OpenTabs {
+    fn default() -> Self {
+        Self {
+            tabs: vec![None],
+            active: 0,
+        }
+    }
 }
 
-pub struct LoadFilePlugin;
+impl OpenTabs {
+    pub fn count(&self) -> usize {
+        self.tabs.len()
+    }
 
-impl Plugin for LoadFilePlugin {
-    fn build(&self, app: &mut App) {
-        app.init_resource::<LoadedFile>()
-            .add_message::<FileSaved>()
-            .add_message::<UpdateHeader>()
-            .add_systems(PostStartup, initial_open_file)
-            .add_systems(Update, file_state_handler)
-            .add_systems(PostUpdate, |mut file: ResMut<LoadedFile>| {
-                file.apply_queued_edits()
-            });
+    pub fn active(&self) -> usize {
+        self.active
     }
-}
 
-#[derive(Event, Default)]
-pub struct FileLoaded;
+    pub fn get<'a>(&'a self, index: usize, active: &'a LoadedFile) -> &'a LoadedFile {
+        self.tabs[index].as_ref().unwrap_or(active)
+    }
 
-pub(super) struct MapFileDialog;
+    /// Adds an already loaded file as a background tab at the end
+    pub fn push(&mut self, file: LoadedFile) {
+        self.tabs.push(Some(file));
+    }
 
-pub fn new_file(ui_state: &mut UiState) {
-    ui_state.request_close_file(|commands, open_file| {
+    /// Opens an empty tab right after the active one and switches to it
+    pub fn new_tab(&mut self, commands: &mut Commands, active: &mut LoadedFile) {
+        active.apply_queued_edits(Duration::ZERO);
+        self.tabs[self.active] = Some(mem::take(active));
+        self.active += 1;
+        self.tabs.insert(self.active, None);
+        commands.write_message(UpdateHeader);
+        commands.trigger(FileLoaded);
+    }
+
+    pub fn find(&self, path: &Path, active: &LoadedFile) -> Option<usize> {
+        (0..self.tabs.len()).find(|&index| self.get(index, active).path.as_deref() == Some(path))
+    }
+
+    /// The first background tab with unsaved changes
+    pub fn dirty_tab(&self) -> Option<usize> {
+        self.tabs
+            .iter()
+            .position(|tab| tab.as_ref().is_some_and(|tab| tab.dirty))
+    }
+
+    /// Discards the active file and switches to the tab next to it. Does nothing if it's the last
+    /// tab.
+    pub fn close_active(&mut self, commands: &mut Commands, active: &mut LoadedFile) {
+        if self.tabs.len() <= 1 {
+            return;
+        }
+        self.tabs.remove(self.active);
+        self.active = self.active.min(self.tabs.len() - 1);
+        *active = self.tabs[self.active]
+            .take()
+            .expect("Only the active tab should be empty");
+        commands.write_message(UpdateHeader);
+        commands.trigger(FileLoaded);
+    }
+
+    pub fn switch_to(&mut self, commands: &mut Commands, active: &mut LoadedFile, index: usize) {
+        if index == self.active || index >= self.tabs.len() {
+            return;
+        }
+        active.apply_queued_edits(Duration::ZERO);
+        let file = self.tabs[index]
+            .take()
+            .expect("Only the active tab should be empty");
+        self.tabs[self.active] = Some(mem::replace(active, file));
+        self.active = index;
+        commands.write_message(UpdateHeader);
+        commands.trigger(FileLoaded);
+    }
+}
+
+const RECENT_FILES_FILE: &str = "recent_files.json";
+const MAX_RECENT_FILES: usize = 10;
+
+/// Files that were recently opened or saved, most recent first. The list is kept in the data
+/// directory between sessions.
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecentFiles(Vec<PathBuf>);
+
+impl RecentFiles {
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.0
+    }
+
+    /// The file that was open before the current one
+    pub fn previous(&self) -> Option<&Path> {
+        self.0.get(1).map(PathBuf::as_path)
+    }
+
+    pub fn has_missing(&self) -> bool {
+        self.0.iter().any(|path| !path.exists())
+    }
+
+    /// Forgets files that were moved or deleted since they were opened
+    pub fn prune_missing(&mut self) {
+        self.0.retain(|path| path.exists());
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    fn add(&mut self, path: &Path) {
+        self.0.retain(|x| x != path);
+        self.0.insert(0, path.to_owned());
+        self.0.truncate(MAX_RECENT_FILES);
+    }
+}
+
+/// Only the path is serialized, so a deserialized texture has to be loaded again
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LoadedTexture {
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub image: Handle<Image>,
+}
+
+/// What changed since the file was last saved, shown before saving
+pub struct ChangeSummary {
+    pub edits: usize,
+    pub tiles_modified: usize,
+    pub old_size: MpsVec2,
+    pub new_size: MpsVec2,
+    pub moved_positions: Vec<&'static str>,
+    pub other_changes: Vec<&'static str>,
+}
+
+#[derive(Default)]
+struct HistoryTracker {
+    items: Vec<HistoryItem>,
+    index: usize,
+    /// The index the file was last saved at
+    saved_index: usize,
+    /// Whether the items after `saved_index` were replaced after undoing past it, so the saved
+    /// state can't be reached from the history anymore
+    saved_discarded: bool,
+    queued_items: Vec<SimpleHistoryItem>,
+    /// Size of the last group of edits that was merged into a single history item
+    merged_count: Option<usize>,
+    /// When the last history item was pushed or had edits coalesced into it
+    last_pushed: Option<Instant>,
+}
+
+impl HistoryTracker {
+    /// Folds `group` into the last history item if it came within `window` of it and every edit
+    /// changes the heights of the same tiles as the edit in the same place in that item. The item
+    /// is dropped entirely if that cancels it out.
+    fn coalesce(&mut self, group: &[SimpleHistoryItem], window: Duration) -> bool {
+        let now = Instant::now();
+        if !self.last_pushed.is_some_and(|time| now - time < window)
+            || self.index == 0
+            || self.index != self.items.len()
+            // Changing the saved step would make the file look unchanged when it isn't
+            || self.saved_index == self.index
+        {
+            return false;
+        }
+        let last = self.items[self.index - 1].items();
+        if last.len() != group.len() {
+            return false;
+        }
+        let Some(merged) = last
+            .iter()
+            .zip(group)
+            .map(|(last, next)| last.coalesced(next))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return false;
+        };
+
+        self.items.pop();
+        if merged.iter().all(SimpleHistoryItem::is_no_op) {
+            self.index -= 1;
+            // The step before it was made separately, so nothing may be folded into it
+            self.last_pushed = None;
+        } else {
+            self.items.push(if merged.len() == 1 {
+                HistoryItem::Simple(merged.into_iter().next().unwrap())
+            } else {
+                HistoryItem::Group(merged)
+            });
+            self.last_pushed = Some(now);
+        }
+        true
+    }
+}
+
+#[derive(Clone, Debug)]
+enum HistoryItem {
+    Simple(SimpleHistoryItem),
+    Group(Vec<SimpleHistoryItem>),
+}
+
+impl HistoryItem {
+    fn items(&self) -> &[SimpleHistoryItem] {
+        match self {
+            Self::Simple(item) => slice::from_ref(item),
+            Self::Group(group) => group,
+        }
+    }
+
+    /// Describes the first edit of the step, which is the one the user made. Any others come
+    /// from symmetry or smart connections.
+    fn description(&self) -> &'static str {
+        self.items()[0].forward.description()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SimpleHistoryItem {
+    forward: MapEdit,
+    back: MapEdit,
+}
+
+impl SimpleHistoryItem {
+    /// This item followed by `next` as a single item, if they're height edits of the same tiles
+    fn coalesced(&self, next: &Self) -> Option<Self> {
+        match (&self.forward, &next.forward) {
+            (
+                MapEdit::AdjustHeight(range, change),
+                MapEdit::AdjustHeight(next_range, next_change),
+            ) if range == next_range => {
+                let change = change + next_change;
+                Some(Self {
+                    forward: MapEdit::AdjustHeight(*range, change),
+                    back: MapEdit::AdjustHeight(*range, -change),
+                })
+            }
+            (MapEdit::ChangeHeight(range, _), MapEdit::ChangeHeight(next_range, _))
+                if range == next_range =>
+            {
+                Some(Self {
+                    forward: next.forward.clone(),
+                    back: self.back.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn is_no_op(&self) -> bool {
+        match &self.forward {
+            MapEdit::AdjustHeight(_, change) => *change == 0.0,
+            forward => *forward == self.back,
+        }
+    }
+}
+
+pub struct LoadFilePlugin;
+
+impl Plugin for LoadFilePlugin {
+    fn build(&self, app: &mut App) {
+        let recent_files = app
+            .world()
+            .get_resource::<Directories>()
+            .and_then(|dirs| load_json::<RecentFiles>(&dirs.data.join(RECENT_FILES_FILE)))
+            .unwrap_or_default();
+        app.init_resource::<LoadedFile>()
+            .init_resource::<OpenTabs>()
+            .insert_resource(recent_files)
+            .add_message::<FileSaved>()
+            .add_message::<UpdateHeader>()
+            .add_message::<SaveAbandoned>()
+            .add_observer(on_file_loaded)
+            .add_systems(PostStartup, initial_open_file)
+            .add_systems(
+                Update,
+                (file_state_handler, confirm_save, reload_changed_textures),
+            )
+            .add_systems(
+                PostUpdate,
+                |mut file: ResMut<LoadedFile>, settings: Res<EditorSettings>| {
+                    file.apply_queued_edits(settings.history.coalesce_window())
+                },
+            )
+            .add_systems(Last, save_recent_files);
+    }
+}
+
+#[derive(Event, Default)]
+pub struct FileLoaded;
+
+/// Triggered when one of the file's textures is loaded again from disk, without the file itself
+/// being edited
+#[derive(Event, Copy, Clone, Debug)]
+pub enum TextureReloaded {
+    Skybox(usize),
+    Atlas,
+}
+
+fn on_file_loaded(_: On<FileLoaded>, file: Res<LoadedFile>, mut recent: ResMut<RecentFiles>) {
+    if let Some(path) = &file.path {
+        recent.add(path);
+    }
+}
+
+fn save_recent_files(recent: Res<RecentFiles>, dirs: Option<Res<Directories>>) {
+    if !recent.is_changed() || recent.is_added() {
+        return;
+    }
+    if let Some(dirs) = dirs {
+        save_json(&dirs.data.join(RECENT_FILES_FILE), &*recent);
+    }
+}
+
+pub(super) struct MapFileDialog;
+
+/// Opens a map with [`parse_map_file_lenient`] instead of refusing it over a few bad tiles
+pub(super) struct RecoverMapFileDialog;
+
+pub fn new_file(ui_state: &mut UiState) {
+    ui_state.request_close_file(|commands, open_file| {
         *open_file = LoadedFile::default();
         commands.write_message(UpdateHeader);
         commands.trigger(FileLoaded);
@@ -469,25 +1514,132 @@ pub fn open_file(ui_state: &mut UiState) {
     });
 }
 
-pub fn save_file(commands: &mut Commands, open_file: &mut LoadedFile) {
-    if let Some(file_path) = open_file.path.clone() {
-        match get_write_data(open_file) {
-            Ok(data) => {
-                commands.write_message(FileSaved {
-                    result: fs::write(&file_path, data),
-                    path: file_path,
-                });
-            }
-            Err(err) => {
-                file_error("save", &err);
-            }
+/// Opens a map whose unreadable tiles are replaced with empty ones, for recovering hand-edited
+/// files that fail to open normally
+pub fn recover_file(ui_state: &mut UiState) {
+    ui_state.request_close_file(|commands, _| {
+        commands
+            .dialog()
+            .set_title("Recover MSP map file")
+            .add_filter("MSP map files", &["json"])
+            .load_file(RecoverMapFileDialog);
+    });
+}
+
+/// Saves the file after checking it for issues, which the user is asked to confirm saving with
+pub fn save_file(
+    commands: &mut Commands,
+    open_file: &mut LoadedFile,
+    settings: &SaveSettings,
+    origin: GridOrigin,
+) {
+    check_before_saving(commands, open_file, settings, origin, false);
+}
+
+/// Like [`save_file`], but always asks where to save the file
+pub fn save_file_as(
+    commands: &mut Commands,
+    open_file: &mut LoadedFile,
+    settings: &SaveSettings,
+    origin: GridOrigin,
+) {
+    check_before_saving(commands, open_file, settings, origin, true);
+}
+
+/// Checks the file for issues before writing it. With `save_as`, this happens before asking where
+/// to save, since the dialog empties the file it's given right away, which would be left behind
+/// if the user then backed out over the issues.
+fn check_before_saving(
+    commands: &mut Commands,
+    open_file: &mut LoadedFile,
+    settings: &SaveSettings,
+    origin: GridOrigin,
+    save_as: bool,
+) {
+    if open_file.save_confirmation.is_some() {
+        return;
+    }
+    let issues = validate(&open_file.file);
+    if issues.is_empty() {
+        write_file(commands, open_file, settings, save_as);
+        return;
+    }
+
+    let map_size = open_file.file.map_size().unwrap_or_default();
+    let descriptions = issues
+        .iter()
+        .map(|issue| issue.describe(origin, map_size))
+        .collect_vec();
+    for description in &descriptions {
+        warn!("{description}");
+    }
+    const MAX_LISTED: usize = 10;
+    let mut text = "This map has issues that will cause problems in game:\n".to_string();
+    for description in descriptions.iter().take(MAX_LISTED) {
+        text.push_str(&format!("\n- {description}"));
+    }
+    if descriptions.len() > MAX_LISTED {
+        text.push_str(&format!(
+            "\n...and {} more",
+            descriptions.len() - MAX_LISTED
+        ));
+    }
+    text.push_str("\n\nSave anyway?");
+    let answer = AsyncComputeTaskPool::get().spawn(async move {
+        native_dialog::MessageDialogBuilder::default()
+            .set_title(TITLE)
+            .set_text(text)
+            .set_level(MessageLevel::Warning)
+            .confirm()
+            .spawn()
+            .await
+            .unwrap_or(false)
+    });
+    open_file.save_confirmation = Some(SaveConfirmation {
+        answer,
+        issues,
+        save_as,
+    });
+}
+
+fn write_file(
+    commands: &mut Commands,
+    open_file: &mut LoadedFile,
+    settings: &SaveSettings,
+    save_as: bool,
+) {
+    match open_file.path.clone() {
+        Some(path) if !save_as => write_file_to(commands, open_file, settings, path),
+        _ => pick_save_path(commands),
+    }
+}
+
+/// Writes the file to `path`, which only becomes the file's path once the write succeeds
+fn write_file_to(
+    commands: &mut Commands,
+    open_file: &mut LoadedFile,
+    settings: &SaveSettings,
+    path: PathBuf,
+) {
+    // Texture paths are written relative to where the file is going
+    let previous_path = open_file.path.replace(path.clone());
+    let data = get_write_data(open_file, settings);
+    open_file.path = previous_path;
+    match data {
+        Ok(data) => {
+            commands.write_message(FileSaved {
+                result: fs::write(&path, data),
+                path,
+            });
+        }
+        Err(err) => {
+            file_error("save", &err);
+            commands.write_message(SaveAbandoned);
         }
-    } else {
-        save_file_as(commands);
     }
 }
 
-pub fn save_file_as(commands: &mut Commands) {
+fn pick_save_path(commands: &mut Commands) {
     commands
         .dialog()
         .set_title("Save MSP map file")
@@ -504,50 +1656,259 @@ struct FileSaved {
 #[derive(Message, Default)]
 struct UpdateHeader;
 
+/// Sent when a save doesn't go through, because writing the file failed or the user backed out
+/// of it
+#[derive(Message)]
+pub struct SaveAbandoned;
+
+/// Opens the files passed on the command line, each in its own tab
 fn initial_open_file(
+    mut ui_state: ResMut<UiState>,
+    mut tabs: ResMut<OpenTabs>,
+    assets: Res<AssetServer>,
+    settings: Res<EditorSettings>,
+) {
+    let mut paths = env::args_os().skip(1).map(PathBuf::from);
+    let Some(first_path) = paths.next() else {
+        new_file(&mut ui_state);
+        return;
+    };
+    open_file_path(&mut ui_state, first_path, assets.clone(), settings.load);
+    for path in paths {
+        let mut file = LoadedFile::default();
+        if load_path(&mut file, path, &assets, settings.load) {
+            tabs.push(file);
+        }
+    }
+}
+
+/// Opens the file at `path` without going through the file dialog. If it can't be loaded, an
+/// error is shown and a new file is opened instead, since the previous file has already been
+/// closed by that point.
+pub fn open_file_path(
+    ui_state: &mut UiState,
+    path: PathBuf,
+    assets: AssetServer,
+    settings: LoadSettings,
+) {
+    ui_state.request_close_file(move |commands, open_file| {
+        if !load_path(open_file, path, &assets, settings) {
+            *open_file = LoadedFile::default();
+        }
+        commands.write_message(UpdateHeader);
+        commands.trigger(FileLoaded);
+    });
+}
+
+/// Opens a copy of a file that was written somewhere else, such as an autosave. The copy is
+/// opened as an unsaved file, so saving it asks for a new path instead of overwriting the copy.
+pub fn open_file_copy(
+    ui_state: &mut UiState,
+    path: PathBuf,
+    assets: AssetServer,
+    settings: LoadSettings,
+) {
+    ui_state.request_close_file(move |commands, open_file| {
+        if load_path(open_file, path, &assets, settings) {
+            open_file.path = None;
+            open_file.dirty = true;
+        } else {
+            *open_file = LoadedFile::default();
+        }
+        commands.write_message(UpdateHeader);
+        commands.trigger(FileLoaded);
+    });
+}
+
+/// Opens map data that didn't come straight from a file, such as one reassembled from several
+/// files, as an unsaved file. Its texture paths are relative to the directory `path` is in.
+pub fn open_file_contents(
+    ui_state: &mut UiState,
+    contents: Vec<u8>,
+    path: PathBuf,
+    assets: AssetServer,
+    settings: LoadSettings,
+) {
+    ui_state.request_close_file(move |commands, open_file| {
+        if handle_load(open_file, &contents, path, &assets, false, settings) {
+            open_file.path = None;
+            open_file.dirty = true;
+        } else {
+            *open_file = LoadedFile::default();
+        }
+        commands.write_message(UpdateHeader);
+        commands.trigger(FileLoaded);
+    });
+}
+
+fn load_path(
+    open_file: &mut LoadedFile,
+    path: PathBuf,
+    assets: &AssetServer,
+    settings: LoadSettings,
+) -> bool {
+    match fs::read(&path) {
+        Ok(data) => handle_load(open_file, &data, path, assets, false, settings),
+        Err(err) => {
+            file_error("load", &err);
+            false
+        }
+    }
+}
+
+fn confirm_save(
+    mut commands: Commands,
     mut open_file: ResMut<LoadedFile>,
+    settings: Res<EditorSettings>,
+) {
+    let Some(confirmation) = &mut open_file.save_confirmation else {
+        return;
+    };
+    let Some(save_anyway) = block_on(future::poll_once(&mut confirmation.answer)) else {
+        return;
+    };
+    let Some(confirmation) = open_file.save_confirmation.take() else {
+        return;
+    };
+    if !save_anyway {
+        commands.write_message(SaveAbandoned);
+        return;
+    }
+    // Anything edited in while the prompt was open has to be agreed to as well
+    let issues = validate(&open_file.file);
+    if issues
+        .iter()
+        .all(|issue| confirmation.issues.contains(issue))
+    {
+        write_file(
+            &mut commands,
+            &mut open_file,
+            &settings.save,
+            confirmation.save_as,
+        );
+    } else {
+        check_before_saving(
+            &mut commands,
+            &mut open_file,
+            &settings.save,
+            settings.grid_origin,
+            confirmation.save_as,
+        );
+    }
+}
+
+/// How often the textures of the open file are checked for changes on disk
+const TEXTURE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+struct TextureWatcher {
+    timer: Timer,
+    files: HashMap<PathBuf, WatchedTexture>,
+}
+
+impl Default for TextureWatcher {
+    fn default() -> Self {
+        Self {
+            timer: Timer::new(TEXTURE_POLL_INTERVAL, TimerMode::Repeating),
+            files: HashMap::new(),
+        }
+    }
+}
+
+struct WatchedTexture {
+    modified: SystemTime,
+    /// The file changed, and is reloaded once it stops changing
+    pending: bool,
+}
+
+/// Reloads textures whose files were changed outside the editor, such as by an image editor.
+/// Modification times are polled rather than watched, and a texture is only reloaded once its
+/// file has been left alone for a whole poll, so that a save written in several steps is only
+/// picked up once it's done.
+fn reload_changed_textures(
     mut commands: Commands,
-    mut ui_state: ResMut<UiState>,
+    mut watcher: Local<TextureWatcher>,
+    time: Res<Time>,
+    open_file: Res<LoadedFile>,
     assets: Res<AssetServer>,
 ) {
-    if let Some(path) = env::args_os().nth(1) {
-        let path = PathBuf::from(path);
-        let data = match fs::read(&path) {
-            Ok(data) => data,
-            Err(err) => {
-                file_error("load", &err);
-                return;
-            }
+    watcher.timer.tick(time.delta());
+    if !watcher.timer.just_finished() {
+        return;
+    }
+
+    let textures = &open_file.loaded_textures;
+    let watched = textures
+        .skybox
+        .iter()
+        .enumerate()
+        .map(|(index, texture)| (texture, TextureReloaded::Skybox(index)))
+        .chain([(&textures.atlas, TextureReloaded::Atlas)])
+        .filter(|(texture, _)| texture.image != Handle::default())
+        .collect_vec();
+    watcher
+        .files
+        .retain(|path, _| watched.iter().any(|(texture, _)| texture.path == *path));
+
+    for (texture, reloaded) in watched {
+        // The file may be missing for a moment while it's being saved, in which case it's
+        // checked again on the next poll
+        let Ok(modified) = fs::metadata(&texture.path).and_then(|meta| meta.modified()) else {
+            continue;
         };
-        if handle_load(&mut open_file, &data, path, &assets) {
-            commands.write_message(UpdateHeader);
-            commands.trigger(FileLoaded);
+        let Some(entry) = watcher.files.get_mut(&texture.path) else {
+            watcher.files.insert(
+                texture.path.clone(),
+                WatchedTexture {
+                    modified,
+                    pending: false,
+                },
+            );
+            continue;
+        };
+        if entry.modified != modified {
+            entry.modified = modified;
+            entry.pending = true;
+        } else if entry.pending {
+            entry.pending = false;
+            info!("Reloading changed texture {}", texture.path.display());
+            assets.reload(texture.path.clone());
+            commands.trigger(reloaded);
         }
-    } else {
-        new_file(&mut ui_state);
     }
 }
 
 #[allow(clippy::too_many_arguments)]
 fn file_state_handler(
     mut loaded_reader: MessageReader<DialogFileLoaded<MapFileDialog>>,
+    mut recover_reader: MessageReader<DialogFileLoaded<RecoverMapFileDialog>>,
     mut saved_reader: MessageReader<FileSaved>,
     mut saved_as_reader: MessageReader<DialogFileSaved<MapFileDialog>>,
+    mut save_canceled_reader: MessageReader<DialogFileSaveCanceled<MapFileDialog>>,
     mut update_header_reader: MessageReader<UpdateHeader>,
     mut commands: Commands,
     mut open_file: ResMut<LoadedFile>,
     mut window_query: Query<&mut Window, With<PrimaryWindow>>,
     assets: Res<AssetServer>,
+    settings: Res<EditorSettings>,
+    mut recent: ResMut<RecentFiles>,
 ) {
     let mut update_header = update_header_reader.is_empty();
     update_header_reader.clear();
 
-    for loaded in loaded_reader.read() {
+    let loaded = loaded_reader
+        .read()
+        .map(|loaded| (&loaded.contents, &loaded.path, false));
+    let recovered = recover_reader
+        .read()
+        .map(|loaded| (&loaded.contents, &loaded.path, true));
+    for (contents, path, lenient) in loaded.chain(recovered) {
         if handle_load(
             &mut open_file,
-            &loaded.contents,
-            loaded.path.clone(),
+            contents,
+            path.clone(),
             &assets,
+            lenient,
+            settings.load,
         ) {
             update_header = true;
             commands.trigger(FileLoaded);
@@ -557,67 +1918,120 @@ fn file_state_handler(
     for saved in saved_reader.read() {
         if let Err(err) = &saved.result {
             file_error("save", err);
+            commands.write_message(SaveAbandoned);
             continue;
         }
         open_file.path = Some(saved.path.clone());
         open_file.dirty = false;
+        recent.add(&saved.path);
+        open_file.history.saved_index = open_file.history.index;
+        open_file.history.saved_discarded = false;
         update_header = true;
     }
 
     for saved in saved_as_reader.read() {
         if let Err(err) = &saved.result {
             file_error("save", err);
+            commands.write_message(SaveAbandoned);
             continue;
         }
-        open_file.path = Some(saved.path.clone());
-        save_file(&mut commands, &mut open_file);
+        let mut path = saved.path.clone();
+        if !path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("json"))
+        {
+            if settings.save.append_json_extension {
+                // The dialog already created the file it was given, which is replaced by the one
+                // with the extension
+                let _ = fs::remove_file(&path);
+                path.as_mut_os_string().push(".json");
+                // The dialog only asked about replacing the file without the extension
+                if path.exists() {
+                    file_warning(&format!(
+                        "{} already exists. Choose it in the dialog to replace it, or pick \
+                        another name.",
+                        path.display()
+                    ));
+                    pick_save_path(&mut commands);
+                    continue;
+                }
+            } else {
+                file_warning("The game only loads map files with a .json extension");
+            }
+        }
+        write_file_to(&mut commands, &mut open_file, &settings.save, path);
+    }
+    if save_canceled_reader.read().count() > 0 {
+        commands.write_message(SaveAbandoned);
     }
 
     if update_header && let Ok(mut window) = window_query.single_mut() {
         window.title = format!(
             "{TITLE} - {}{}",
-            open_file
-                .path
-                .as_ref()
-                .and_then(|x| x.file_name())
-                .map_or_else(|| OsStr::new("Untitled").display(), |x| x.display()),
+            open_file.display_name(),
             if open_file.dirty { "*" } else { "" }
         );
     }
 }
 
+/// Loads `data` into `open_file`, replacing tiles that can't be read with empty ones if `lenient`
+/// is set
 fn handle_load(
     open_file: &mut LoadedFile,
     data: &[u8],
     path: PathBuf,
     assets: &AssetServer,
+    lenient: bool,
+    settings: LoadSettings,
 ) -> bool {
     let root_dir = path
         .parent()
         .expect("File shouldn't have been loadable without a parent");
-    open_file.file = match serde_json::from_slice(data) {
-        Ok(data) => data,
+    let parsed = if lenient {
+        parse_map_file_lenient(data)
+    } else {
+        parse_map_file(data).map(|file| (file, vec![]))
+    };
+    let (file, replaced) = match parsed {
+        Ok(parsed) => parsed,
         Err(err) => {
             file_error("open", &err);
             return false;
         }
     };
-    open_file.dirty = false;
+    open_file.file = file;
+    let flattened = if settings.flatten_level_ramps {
+        open_file.file.flatten_level_ramps()
+    } else {
+        0
+    };
+    // The replaced tiles and flattened ramps are only fixed once the file is saved again
+    open_file.dirty = !replaced.is_empty() || flattened > 0;
+    if !replaced.is_empty() {
+        file_warning(&recovery_report(&replaced));
+    }
+    if flattened > 0 {
+        file_warning(&format!(
+            "{flattened} ramps whose ends were at the same height were flattened. Save the map to \
+            keep the change."
+        ));
+    }
 
     let load_texture = |path: &RelativePathBuf, sampler: ImageSampler| {
-        let path = path.to_path(root_dir);
-        LoadedTexture {
-            path: path.clone(),
-            image: assets.load_with_settings_override(path, move |settings| {
-                *settings = ImageLoaderSettings {
-                    format: ImageFormatSetting::Guess,
-                    sampler: sampler.clone(),
-                    ..Default::default()
-                };
-            }),
-        }
+        let path = match stored_absolute_path(path) {
+            Some(path) => path.to_path_buf(),
+            None => path.to_path(root_dir),
+        };
+        load_map_texture(assets, path, sampler)
     };
 
+    open_file.absolute_texture_paths = open_file
+        .file
+        .textures
+        .skybox
+        .iter()
+        .chain(slice::from_ref(&open_file.file.textures.atlas))
+        .any(|path| stored_absolute_path(path).is_some());
     open_file.loaded_textures = Textures {
         skybox: open_file
             .file
@@ -634,7 +2048,168 @@ fn handle_load(
     true
 }
 
-fn get_write_data(open_file: &mut LoadedFile) -> Result<Vec<u8>> {
+/// Strict parsing comes first, and the comments and trailing commas of hand-edited files are only
+/// stripped if that fails
+fn parse_map_file(data: &[u8]) -> Result<MapFile, String> {
+    let err = match serde_json::from_slice(data) {
+        Ok(file) => return Ok(file),
+        Err(err) => err,
+    };
+    let Some(stripped) = strip_json_extensions(data) else {
+        return Err(describe_parse_error(data, err));
+    };
+    let file =
+        serde_json::from_slice(&stripped).map_err(|err| describe_parse_error(&stripped, err))?;
+    file_warning(JSON_EXTENSIONS_WARNING);
+    Ok(file)
+}
+
+/// Loads one of the map's textures, with `sampler` in place of the default one
+pub fn load_map_texture(
+    assets: &AssetServer,
+    path: PathBuf,
+    sampler: ImageSampler,
+) -> LoadedTexture {
+    LoadedTexture {
+        path: path.clone(),
+        image: assets.load_with_settings_override(path, move |settings| {
+            *settings = ImageLoaderSettings {
+                format: ImageFormatSetting::Guess,
+                sampler: sampler.clone(),
+                ..Default::default()
+            };
+        }),
+    }
+}
+
+const JSON_EXTENSIONS_WARNING: &str =
+    "This file contains comments or trailing commas, and comments will be lost if it's saved";
+
+/// Like [`parse_map_file`], but tiles that can't be read are replaced with empty ones instead of
+/// failing the whole file. The JSON paths of the replaced tiles are returned with the file.
+fn parse_map_file_lenient(data: &[u8]) -> Result<(MapFile, Vec<String>), String> {
+    let mut value = match serde_json::from_slice::<serde_json::Value>(data) {
+        Ok(value) => value,
+        Err(err) => {
+            let stripped = strip_json_extensions(data).ok_or_else(|| err.to_string())?;
+            let value = serde_json::from_slice(&stripped).map_err(|err| err.to_string())?;
+            file_warning(JSON_EXTENSIONS_WARNING);
+            value
+        }
+    };
+    let mut replaced = vec![];
+    let rows = value.get_mut("data").and_then(|rows| rows.as_array_mut());
+    for (y, row) in rows.into_iter().flatten().enumerate() {
+        let Some(row) = row.as_array_mut() else {
+            continue;
+        };
+        for (x, tile) in row.iter_mut().enumerate() {
+            if TileData::deserialize(&*tile).is_err() {
+                *tile = serde_json::to_value(TileData::default()).unwrap();
+                replaced.push(format!("data[{y}][{x}]"));
+            }
+        }
+    }
+    let file = MapFile::deserialize(&value).map_err(|err| describe_value_error(&value, err))?;
+    Ok((file, replaced))
+}
+
+/// How many replaced tiles are listed by name in the report
+const MAX_REPORTED_TILES: usize = 20;
+
+fn recovery_report(replaced: &[String]) -> String {
+    let mut report = format!(
+        "{} tiles couldn't be read and were replaced with empty tiles: {}",
+        replaced.len(),
+        replaced.iter().take(MAX_REPORTED_TILES).join(", "),
+    );
+    if replaced.len() > MAX_REPORTED_TILES {
+        report += &format!(", and {} more", replaced.len() - MAX_REPORTED_TILES);
+    }
+    report
+}
+
+/// Points out where in `data` parsing failed. The flattened fields of [`MapFile`] and [`TileData`]
+/// make serde report the end of the file as the position of errors inside them, so the bad tile is
+/// found again from the parsed JSON.
+fn describe_parse_error(data: &[u8], err: serde_json::Error) -> String {
+    if !err.is_data() {
+        return err.to_string();
+    }
+    match serde_json::from_slice(data) {
+        Ok(value) => describe_value_error(&value, err),
+        Err(_) => err.to_string(),
+    }
+}
+
+/// Finds the first row or tile in `value` that causes `err`
+fn describe_value_error(value: &serde_json::Value, err: serde_json::Error) -> String {
+    let Some(rows) = value.get("data").and_then(|rows| rows.as_array()) else {
+        return err.to_string();
+    };
+    let columns = rows
+        .first()
+        .and_then(|row| row.as_array())
+        .map_or(0, Vec::len);
+    for (y, row) in rows.iter().enumerate() {
+        let Some(row) = row.as_array() else {
+            return format!("data[{y}] should be a list of tiles, but it's {row}");
+        };
+        if row.len() != columns {
+            return format!(
+                "data[{y}] has {} tiles, but every row needs as many as the first one, which has \
+                {columns}",
+                row.len(),
+            );
+        }
+        for (x, tile) in row.iter().enumerate() {
+            if let Err(err) = TileData::deserialize(tile) {
+                return format!(
+                    "Invalid tile at data[{y}][{x}]: {}",
+                    describe_tile_error(tile, err)
+                );
+            }
+        }
+    }
+    err.to_string()
+}
+
+/// Explains errors in the untagged parts of a tile, which serde only reports as not matching any
+/// variant
+fn describe_tile_error(tile: &serde_json::Value, err: serde_json::Error) -> String {
+    let Some(ramp) = tile.get("ramp") else {
+        return "missing field `ramp`".to_string();
+    };
+    let Some(height) = tile.get("height") else {
+        return "missing field `height`".to_string();
+    };
+    match ramp.as_bool() {
+        None => return format!("`ramp` should be true or false, but it's {ramp}"),
+        Some(false) if !height.is_number() => {
+            return format!("`height` should be a number when `ramp` is false, but it's {height}");
+        }
+        Some(true) => {
+            if let Err(err) = TileRamp::deserialize(height) {
+                return format!(
+                    "`height` should have a `dir` of \"h\" or \"v\" and numbers for `pos` and \
+                    `neg` when `ramp` is true: {err}"
+                );
+            }
+        }
+        Some(false) => {}
+    }
+    let connections = tile.get("connections").and_then(|sides| sides.as_object());
+    for (side, connection) in connections.into_iter().flatten() {
+        if Connection::deserialize(connection).is_err() {
+            return format!(
+                "`connections.{side}` should be true, false, or \"lock\", but it's {connection}"
+            );
+        }
+    }
+    err.to_string()
+}
+
+fn get_write_data(open_file: &mut LoadedFile, settings: &SaveSettings) -> Result<Vec<u8>> {
     let root_path = normalize_path(
         open_file
             .path
@@ -644,29 +2219,79 @@ fn get_write_data(open_file: &mut LoadedFile) -> Result<Vec<u8>> {
     let root_path = root_path
         .parent()
         .expect("get_write_data called with an invalid path");
+    write_map_data(
+        &mut open_file.file,
+        &open_file.loaded_textures,
+        root_path,
+        open_file.absolute_texture_paths,
+        settings,
+    )
+}
+
+/// Writes the file as if it were saved in `dir`, without changing the texture paths the file
+/// keeps for its own location
+pub fn get_snapshot_data(
+    open_file: &LoadedFile,
+    dir: &Path,
+    settings: &SaveSettings,
+) -> Result<Vec<u8>> {
+    let mut file = open_file.file.clone();
+    write_map_data(
+        &mut file,
+        &open_file.loaded_textures,
+        &normalize_path(dir)?,
+        open_file.absolute_texture_paths,
+        settings,
+    )
+}
+
+fn write_map_data(
+    file: &mut MapFile,
+    textures: &Textures<LoadedTexture>,
+    root_path: &Path,
+    absolute_paths: bool,
+    settings: &SaveSettings,
+) -> Result<Vec<u8>> {
     let convert_path = |from: &LoadedTexture, to: &mut RelativePathBuf| -> Result<()> {
-        *to = normalize_path(&from.path)?.relative_to(root_path)?;
+        let relative = relative_texture_path(from, root_path);
+        // Textures that were never set stay empty either way
+        let unset = matches!(&relative, Ok(path) if path.as_str().is_empty());
+        *to = if absolute_paths && !unset {
+            let path = path::absolute(&from.path)?;
+            let path = path
+                .to_str()
+                .ok_or_else(|| io::Error::other("Texture path isn't valid UTF-8"))?;
+            RelativePathBuf::from(path)
+        } else {
+            relative?
+        };
         Ok(())
     };
-    for (from, to) in open_file
-        .loaded_textures
-        .skybox
-        .iter()
-        .zip(open_file.file.textures.skybox.iter_mut())
-    {
+    for (from, to) in textures.skybox.iter().zip(file.textures.skybox.iter_mut()) {
         convert_path(from, to)?;
     }
-    convert_path(
-        &open_file.loaded_textures.atlas,
-        &mut open_file.file.textures.atlas,
-    )?;
+    convert_path(&textures.atlas, &mut file.textures.atlas)?;
 
     let mut serializer =
         Serializer::with_formatter(Vec::new(), PrettyFormatter::with_indent("\t".as_bytes()));
-    open_file.file.serialize(&mut serializer)?;
+    if settings.write_empty_fields {
+        WithEmptyFields(file).serialize(&mut serializer)?;
+    } else {
+        file.serialize(&mut serializer)?;
+    }
     Ok(serializer.into_inner())
 }
 
+/// The path of `texture` relative to `root_path`, as it's written to files in that directory
+pub fn relative_texture_path(texture: &LoadedTexture, root_path: &Path) -> Result<RelativePathBuf> {
+    Ok(normalize_path(&texture.path)?.relative_to(root_path)?)
+}
+
+/// The path written to a file for a texture, if it was saved as an absolute path
+fn stored_absolute_path(path: &RelativePathBuf) -> Option<&Path> {
+    Some(Path::new(path.as_str())).filter(|path| path.is_absolute())
+}
+
 fn normalize_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
     use path::Component;
     let mut result = PathBuf::new();
@@ -686,18 +2311,258 @@ fn normalize_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
     Ok(result)
 }
 
-fn file_error(what: &str, error: &impl std::fmt::Display) {
+pub fn file_error(what: &str, error: &impl std::fmt::Display) {
     let text = format!("Failed to {what} file: {error}");
     error!("{text}");
+    alert(text, MessageLevel::Error);
+}
+
+fn file_warning(text: &str) {
+    warn!("{text}");
+    alert(text.to_string(), MessageLevel::Warning);
+}
+
+fn alert(text: String, level: MessageLevel) {
     AsyncComputeTaskPool::get()
         .spawn(async move {
             let _ = native_dialog::MessageDialogBuilder::default()
                 .set_title(TITLE)
                 .set_text(text)
-                .set_level(MessageLevel::Error)
+                .set_level(level)
                 .alert()
                 .spawn()
                 .await;
         })
         .detach();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        HistoryItem, HistoryTracker, LoadedFile, LoadedTexture, SimpleHistoryItem, parse_map_file,
+        parse_map_file_lenient, write_map_data,
+    };
+    use crate::schema::{Connection, ConnectionCondition, MapFile, MpsVec2, Textures, TileData};
+    use crate::settings::SaveSettings;
+    use crate::sync::{Direction, MapEdit};
+    use crate::tile_range::TileRange;
+    use grid::Grid;
+    use serde_json::json;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn adjust(range: TileRange, change: f64) -> SimpleHistoryItem {
+        SimpleHistoryItem {
+            forward: MapEdit::AdjustHeight(range, change),
+            back: MapEdit::AdjustHeight(range, -change),
+        }
+    }
+
+    fn history_with(item: SimpleHistoryItem) -> HistoryTracker {
+        HistoryTracker {
+            items: vec![HistoryItem::Simple(item)],
+            index: 1,
+            last_pushed: Some(Instant::now()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_coalesce_adjust_height() {
+        let range = TileRange {
+            start: MpsVec2::new(1, 1),
+            end: MpsVec2::new(2, 2),
+        };
+        let mut history = history_with(adjust(range, 0.5));
+        assert!(history.coalesce(&[adjust(range, 1.0)], WINDOW));
+        assert_eq!(history.index, 1);
+        assert!(matches!(
+            history.items[0].items(),
+            [SimpleHistoryItem {
+                forward: MapEdit::AdjustHeight(_, 1.5),
+                back: MapEdit::AdjustHeight(_, -1.5),
+            }]
+        ));
+
+        // Cancelling it out drops the step
+        assert!(history.coalesce(&[adjust(range, -1.5)], WINDOW));
+        assert_eq!(history.index, 0);
+        assert!(history.items.is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_after_cancelling_out() {
+        let range = TileRange {
+            start: MpsVec2::ZERO,
+            end: MpsVec2::ONE,
+        };
+        let mut history = history_with(adjust(range, 1.0));
+        history.items.push(HistoryItem::Simple(adjust(range, 0.5)));
+        history.index = 2;
+        assert!(history.coalesce(&[adjust(range, -0.5)], WINDOW));
+        assert_eq!(history.index, 1);
+
+        // The earlier step stays as it was instead of picking up the next edit
+        assert!(!history.coalesce(&[adjust(range, 0.5)], WINDOW));
+        assert!(matches!(
+            history.items[0].items(),
+            [SimpleHistoryItem {
+                forward: MapEdit::AdjustHeight(_, 1.0),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_coalesce_rejected() {
+        let range = TileRange {
+            start: MpsVec2::ZERO,
+            end: MpsVec2::ZERO,
+        };
+        let other_range = TileRange {
+            start: MpsVec2::ONE,
+            end: MpsVec2::ONE,
+        };
+        let mut history = history_with(adjust(range, 0.5));
+        assert!(!history.coalesce(&[adjust(other_range, 0.5)], WINDOW));
+        assert!(!history.coalesce(&[adjust(range, 0.5)], Duration::ZERO));
+
+        history.saved_index = 1;
+        assert!(!history.coalesce(&[adjust(range, 0.5)], WINDOW));
+    }
+
+    #[test]
+    fn test_parse_error_locations() {
+        let parse_error = |edit: fn(&mut serde_json::Value)| {
+            let mut file = MapFile {
+                data: Grid::new(2, 2),
+                ..Default::default()
+            };
+            file.data[(1, 0)].connections.east = Connection::Conditional(ConnectionCondition::Lock);
+            let mut value = serde_json::to_value(&file).unwrap();
+            edit(&mut value);
+            parse_map_file(&serde_json::to_vec(&value).unwrap()).unwrap_err()
+        };
+
+        let err = parse_error(|value| value["data"][1][0]["ramp"] = json!(true));
+        assert!(err.starts_with("Invalid tile at data[1][0]: `height` should have a `dir`"));
+        let err = parse_error(|value| value["data"][0][1]["ramp"] = json!("yes"));
+        assert_eq!(
+            err,
+            "Invalid tile at data[0][1]: `ramp` should be true or false, but it's \"yes\""
+        );
+        let err = parse_error(|value| value["data"][1][1]["connections"]["w"] = json!("open"));
+        assert_eq!(
+            err,
+            "Invalid tile at data[1][1]: `connections.w` should be true, false, or \"lock\", but \
+            it's \"open\""
+        );
+        let err = parse_error(|value| {
+            let tile = value["data"][1][0].clone();
+            value["data"][1].as_array_mut().unwrap().push(tile);
+        });
+        assert_eq!(
+            err,
+            "data[1] has 3 tiles, but every row needs as many as the first one, which has 2"
+        );
+        let err = parse_error(|value| value["data"][0][0]["coins"] = json!("many"));
+        assert!(err.starts_with("Invalid tile at data[0][0]: invalid type: string \"many\""));
+    }
+
+    #[test]
+    fn test_lenient_parse() {
+        let mut file = MapFile {
+            data: Grid::new(2, 3),
+            ..Default::default()
+        };
+        file.data[(0, 0)].coins = 5;
+        let mut value = serde_json::to_value(&file).unwrap();
+        value["data"][0][2]["ramp"] = json!(true);
+        value["data"][1][1]["connections"] = json!(null);
+        let data = serde_json::to_vec(&value).unwrap();
+
+        assert!(parse_map_file(&data).is_err());
+        let (parsed, replaced) = parse_map_file_lenient(&data).unwrap();
+        assert_eq!(replaced, ["data[0][2]", "data[1][1]"]);
+        assert_eq!(parsed.data, file.data);
+
+        value["data"][1].as_array_mut().unwrap().pop();
+        let data = serde_json::to_vec(&value).unwrap();
+        assert!(parse_map_file_lenient(&data).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_absolute_texture_paths() {
+        let texture = |path: &str| LoadedTexture {
+            path: path.into(),
+            image: Default::default(),
+        };
+        let textures = Textures {
+            skybox: ["/maps/board/sky.png"; 6].map(texture),
+            atlas: texture("/maps/shared/atlas.png"),
+        };
+        let root = Path::new("/maps/board");
+        let write = |absolute| {
+            let data = write_map_data(
+                &mut MapFile::default(),
+                &textures,
+                root,
+                absolute,
+                &SaveSettings::default(),
+            )
+            .unwrap();
+            parse_map_file(&data).unwrap().textures
+        };
+
+        let relative = write(false);
+        assert_eq!(relative.atlas.as_str(), "../shared/atlas.png");
+        assert_eq!(relative.skybox[0].as_str(), "sky.png");
+
+        let absolute = write(true);
+        assert_eq!(absolute.atlas.as_str(), "/maps/shared/atlas.png");
+        assert_eq!(absolute.skybox[0].as_str(), "/maps/board/sky.png");
+    }
+
+    #[test]
+    fn test_opposite_connection_edit() {
+        let file = LoadedFile {
+            file: MapFile {
+                data: Grid::init(1, 3, TileData::default()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let lock = Connection::Conditional(ConnectionCondition::Lock);
+        let range = TileRange {
+            start: MpsVec2::new(0, 0),
+            end: MpsVec2::new(1, 0),
+        };
+        let edit = MapEdit::ChangeConnection(
+            range,
+            Direction::East,
+            vec![lock, Connection::Unconditional(true)],
+        );
+        let Some(MapEdit::ChangeConnection(neighbors, Direction::West, connections)) =
+            file.opposite_connection_edit(&edit)
+        else {
+            panic!("Expected a connection edit on the west side");
+        };
+        assert_eq!(neighbors.start, MpsVec2::new(1, 0));
+        assert_eq!(neighbors.end, MpsVec2::new(2, 0));
+        assert_eq!(connections, [lock, Connection::Unconditional(true)]);
+
+        // There's no neighbor past the edge of the map
+        let edge = MapEdit::ChangeConnection(
+            TileRange {
+                start: MpsVec2::new(2, 0),
+                end: MpsVec2::new(2, 0),
+            },
+            Direction::East,
+            vec![lock],
+        );
+        assert!(file.opposite_connection_edit(&edge).is_none());
+    }
+}
@@ -1,40 +1,67 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod assets;
+mod autosave;
 mod culling;
 mod docking;
+mod export;
+mod format_schema;
 mod load_file;
 mod mesh;
+mod minimap;
+mod overlay;
+mod persistence;
+mod report;
 mod schema;
+mod selection_history;
+mod session_log;
+mod settings;
 mod shortcuts;
+mod split_map;
 mod sync;
 mod tile_range;
+mod tools;
 mod ui;
 mod utils;
+mod validation;
 mod viewport;
 
 use crate::assets::EmbeddedAssetsPlugin;
+use crate::autosave::AutosavePlugin;
+use crate::export::ExportPlugin;
 use crate::load_file::LoadFilePlugin;
+use crate::minimap::MinimapPlugin;
+use crate::selection_history::SelectionHistoryPlugin;
+use crate::session_log::SessionLogPlugin;
+use crate::settings::SettingsPlugin;
+use crate::split_map::SplitMapPlugin;
+use crate::tools::ToolsPlugin;
 use crate::ui::MapEditorUi;
+use crate::validation::ValidationPlugin;
 use crate::viewport::ViewportPlugin;
 use bevy::asset::UnapprovedPathMode;
 use bevy::log::LogPlugin;
 use bevy::prelude::*;
+use bevy::render::RenderPlugin;
+use bevy::render::settings::{WgpuFeatures, WgpuSettings};
 use bevy_panic_handler::PanicHandler;
 use directories::ProjectDirs;
-use std::fs;
 use std::path::PathBuf;
+use std::{fs, io};
 
 pub const TITLE: &str = "MSP Map Editor";
 
+/// Where the editor keeps its own files. This resource is missing when the data directory
+/// can't be created, so systems should take it as `Option<Res<Directories>>` and keep their
+/// state in memory without it.
 #[derive(Resource)]
 pub struct Directories {
     pub data: PathBuf,
 }
 
 impl Directories {
-    fn create_dirs(&self) -> bool {
-        fs::create_dir_all(&self.data).is_ok()
+    fn create_dirs(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.data)
     }
 }
 
@@ -46,15 +73,32 @@ impl Plugin for MapEditor {
             let dirs_resource = Directories {
                 data: dirs.data_dir().to_owned(),
             };
-            if dirs_resource.create_dirs() {
-                app.insert_resource(dirs_resource);
+            match dirs_resource.create_dirs() {
+                Ok(()) => {
+                    app.insert_resource(dirs_resource);
+                }
+                Err(err) => warn!(
+                    "Failed to create data directory {}, editor settings won't be saved: {err}",
+                    dirs_resource.data.display()
+                ),
             }
+        } else {
+            warn!("No data directory available, editor settings won't be saved");
         }
 
         app.add_plugins((
             EmbeddedAssetsPlugin,
+            SettingsPlugin,
             LoadFilePlugin,
             ViewportPlugin,
+            ValidationPlugin,
+            ExportPlugin,
+            ToolsPlugin,
+            SelectionHistoryPlugin,
+            SessionLogPlugin,
+            AutosavePlugin,
+            SplitMapPlugin,
+            MinimapPlugin,
             MapEditorUi,
         ));
     }
@@ -77,6 +121,15 @@ fn main() {
                         .to_string(),
                     ..Default::default()
                 })
+                .set(RenderPlugin {
+                    // Needed to draw the wireframe view
+                    render_creation: WgpuSettings {
+                        features: WgpuFeatures::POLYGON_MODE_LINE,
+                        ..Default::default()
+                    }
+                    .into(),
+                    ..Default::default()
+                })
                 .set(AssetPlugin {
                     file_path: "".to_string(),
                     unapproved_path_mode: UnapprovedPathMode::Deny,
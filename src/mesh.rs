@@ -1,90 +1,342 @@
 use crate::assets;
 use crate::assets::key_gate;
 use crate::schema::{
-    Connection, ConnectionCondition, MpsMaterial, TileData, TileHeight, TileRampDirection,
+    Connection, ConnectionCondition, MpsMaterial, MpsVec2, TileData, TileHeight, TileRampDirection,
 };
+use crate::settings::MeshSettings;
 use crate::sync::Direction;
 use crate::tile_range::TileRange;
 use bevy::asset::RenderAssetUsages;
 use bevy::light::{NotShadowCaster, NotShadowReceiver};
-use bevy::mesh::{Indices, PrimitiveTopology};
+use bevy::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
 use bevy::prelude::*;
+use bevy::render::render_resource::AsBindGroup;
+use bevy::shader::ShaderRef;
 use grid::Grid;
+use rayon::prelude::*;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::f32::consts::{FRAC_PI_2, PI};
+use std::ops::{AddAssign, Range, SubAssign};
 
 #[derive(Component)]
 pub struct MapMeshMarker;
 
+/// The material the map's terrain is textured with
+pub type AtlasMaterial = ExtendedMaterial<StandardMaterial, AtlasTiling>;
+
+/// Repeats an atlas cell across the merged quads from [`MeshSettings::merge_flat_tops`], which
+/// have the corner of their cell in the second UV channel. Geometry with a negative corner is
+/// textured like it would be with a plain [`StandardMaterial`].
+#[derive(Asset, AsBindGroup, Reflect, Clone, Debug)]
+pub struct AtlasTiling {
+    #[uniform(100)]
+    cell_size: Vec2,
+}
+
+impl Default for AtlasTiling {
+    fn default() -> Self {
+        let (u1, v1, u2, v2) = MpsMaterial::default().to_uv_coords();
+        Self {
+            cell_size: Vec2::new(u2 - u1, v2 - v1),
+        }
+    }
+}
+
+impl MaterialExtension for AtlasTiling {
+    fn fragment_shader() -> ShaderRef {
+        assets::ATLAS_TILING_SHADER.into()
+    }
+}
+
+/// The size of the map mesh after the last remesh, for keeping maps within what the game and the
+/// editor can handle
+#[derive(Resource, Copy, Clone, Debug, Default)]
+pub struct MapMeshStats {
+    /// Tiles with anything on them
+    pub tiles: usize,
+    pub vertices: usize,
+    pub triangles: usize,
+    /// Entities making up the map mesh. Each key gate is counted once, even though its model has
+    /// several.
+    pub entities: usize,
+}
+
+impl MapMeshStats {
+    fn add_mesh(&mut self, mesh: &Mesh) {
+        self.vertices += mesh.count_vertices();
+        self.triangles += mesh.indices().map_or(0, |indices| indices.len() / 3);
+        self.entities += 1;
+    }
+}
+
+impl AddAssign for MapMeshStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.tiles += rhs.tiles;
+        self.vertices += rhs.vertices;
+        self.triangles += rhs.triangles;
+        self.entities += rhs.entities;
+    }
+}
+
+impl SubAssign for MapMeshStats {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.tiles -= rhs.tiles;
+        self.vertices -= rhs.vertices;
+        self.triangles -= rhs.triangles;
+        self.entities -= rhs.entities;
+    }
+}
+
+/// How many tiles along each side of the square chunks the map mesh is split into. Edits only
+/// remesh the chunks around the tiles they touch.
+const CHUNK_SIZE: i32 = 16;
+
+/// The entity every part of the map mesh is under, holding the materials shared by its chunks
+#[derive(Component)]
+pub struct MapMeshRoot {
+    atlas: Handle<AtlasMaterial>,
+    block_material: Handle<StandardMaterial>,
+    trim_material: Handle<StandardMaterial>,
+}
+
+/// A square piece of the map's terrain, with the blocks, trims, and key gates on its tiles as
+/// children
+#[derive(Component)]
+pub struct MapChunk {
+    pub range: TileRange,
+    pub stats: MapMeshStats,
+}
+
+#[derive(Bundle)]
+struct MeshObject<M: Material> {
+    mesh: Mesh3d,
+    material: MeshMaterial3d<M>,
+    transform: Transform,
+}
+
 pub fn mesh_map(
     map: &Grid<TileData>,
-    atlas: Handle<StandardMaterial>,
+    settings: &MeshSettings,
+    cache: &mut TileMeshCache,
+    atlas: Handle<AtlasMaterial>,
     assets: &AssetServer,
     materials: &mut Assets<StandardMaterial>,
     meshes: &mut Assets<Mesh>,
-) -> impl Bundle {
-    #[derive(Bundle)]
-    struct MeshObject {
-        mesh: Mesh3d,
-        material: MeshMaterial3d<StandardMaterial>,
-        transform: Transform,
+) -> (impl Bundle, MapMeshStats) {
+    // Trim sizes follow the ramps around them, so only the cuboids of the new mesh are kept
+    cache.shared.cuboids.clear();
+    cache
+        .shared
+        .colors
+        .retain(|&color, _| color == settings.block_color || color == settings.trim_color);
+    let root = MapMeshRoot {
+        atlas,
+        block_material: cache.shared.color_material(settings.block_color, materials),
+        trim_material: cache.shared.color_material(settings.trim_color, materials),
+    };
+
+    // Meshing the whole map at once lets the tiles be meshed in parallel
+    if let Some(range) = map_range(map) {
+        update_tile_cache(map, settings, cache, settings.merge_flat_tops, range);
     }
+    let mut stats = MapMeshStats::default();
+    let tiles = &*cache;
+    let geometry = map_chunks(map)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|range| ChunkGeometry::new(map, settings, tiles, range))
+        .collect::<Vec<_>>();
+    let chunks = geometry
+        .into_iter()
+        .map(|geometry| {
+            let (chunk, chunk_stats) =
+                mesh_chunk(map, geometry, &root, &mut cache.shared, assets, meshes);
+            stats += chunk_stats;
+            chunk
+        })
+        .collect::<Vec<_>>();
 
-    let mut state = State::new(map);
-    let mut block_children = vec![];
-    let mut key_gates = vec![];
+    let floor = mesh_floor(map);
+    stats.add_mesh(&floor);
 
-    let block_material = materials.add(StandardMaterial {
-        base_color: Srgba::rgb_u8(0x11, 0x11, 0x11).into(),
-        perceptual_roughness: 1.0,
-        ..Default::default()
-    });
-    let trim_material = materials.add(StandardMaterial {
-        base_color: Srgba::rgb_u8(0xAA, 0xAA, 0xAA).into(),
-        perceptual_roughness: 1.0,
+    let bundle = (
+        root,
+        Transform::default(),
+        Visibility::default(),
+        Children::spawn((
+            chunks,
+            Spawn((
+                MeshObject {
+                    mesh: Mesh3d(meshes.add(floor)),
+                    material: MeshMaterial3d(cache.shared.floor_material(assets, materials)),
+                    transform: Transform::default(),
+                },
+                NotShadowCaster,
+                NotShadowReceiver,
+            )),
+        )),
+    );
+    (bundle, stats)
+}
+
+/// Meshes a single chunk of the map again after its tiles or their neighbors changed, to replace
+/// the chunk under `root` with the same range
+pub fn remesh_chunk(
+    map: &Grid<TileData>,
+    settings: &MeshSettings,
+    cache: &mut TileMeshCache,
+    root: &MapMeshRoot,
+    range: TileRange,
+    assets: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+) -> (impl Bundle, MapMeshStats) {
+    update_tile_cache(map, settings, cache, settings.merge_flat_tops, range);
+    let geometry = ChunkGeometry::new(map, settings, cache, range);
+    mesh_chunk(map, geometry, root, &mut cache.shared, assets, meshes)
+}
+
+/// Everything in a chunk that can be built without touching the assets, so that the chunks of
+/// the whole map can be built in parallel
+struct ChunkGeometry {
+    range: TileRange,
+    terrain: Mesh,
+    details: MapDetails,
+}
+
+impl ChunkGeometry {
+    /// Collects the cached tile geometry, which has to be up to date for `range`
+    fn new(
+        map: &Grid<TileData>,
+        settings: &MeshSettings,
+        cache: &TileMeshCache,
+        range: TileRange,
+    ) -> Self {
+        Self {
+            range,
+            terrain: cached_terrain(map, cache, settings.merge_flat_tops, range).into_mesh(),
+            details: map_details(map, settings, range),
+        }
+    }
+}
+
+/// Adds the meshes of a chunk built by [`ChunkGeometry::new`] to the assets
+fn mesh_chunk(
+    map: &Grid<TileData>,
+    geometry: ChunkGeometry,
+    root: &MapMeshRoot,
+    shared: &mut SharedMeshAssets,
+    assets: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+) -> (impl Bundle, MapMeshStats) {
+    let ChunkGeometry {
+        range,
+        terrain,
+        details,
+    } = geometry;
+    let mut stats = MapMeshStats {
+        tiles: range
+            .into_iter()
+            .filter(|pos| map[(pos.y as usize, pos.x as usize)].height != TileHeight::default())
+            .count(),
+        entities: details.key_gates.len(),
         ..Default::default()
-    });
+    };
+    stats.add_mesh(&terrain);
 
-    for ((y, x), tile) in map.indexed_iter() {
-        if tile.height == TileHeight::default() {
-            continue;
+    let mut cuboid_object = |(cuboid, transform): (Cuboid, Transform), material: &Handle<_>| {
+        let mesh = shared.cuboid(cuboid, meshes);
+        if let Some(mesh) = meshes.get(&mesh) {
+            stats.add_mesh(mesh);
+        }
+        MeshObject {
+            mesh: Mesh3d(mesh),
+            material: MeshMaterial3d(material.clone()),
+            transform,
         }
+    };
+    let block_children = details
+        .blocks
+        .into_iter()
+        .map(|block| cuboid_object(block, &root.block_material))
+        .chain(
+            details
+                .trims
+                .into_iter()
+                .map(|trim| cuboid_object(trim, &root.trim_material)),
+        )
+        .collect::<Vec<_>>();
+    let key_gates = details
+        .key_gates
+        .into_iter()
+        .map(|(position, rotation)| key_gate(assets, position, rotation))
+        .collect::<Vec<_>>();
 
-        internal_mesh_top(&mut state, x, y, tile, 0.0);
-        match tile.height {
-            TileHeight::Flat { height, .. } => {
-                if x == 0 || height > map[(y, x - 1)].height.min_height() {
-                    mesh_wall(&mut state, x, y, tile, Direction::West);
-                }
-                if x == map.cols() - 1 || height > map[(y, x + 1)].height.min_height() {
-                    mesh_wall(&mut state, x, y, tile, Direction::East);
-                }
-                if y == 0 || height > map[(y - 1, x)].height.min_height() {
-                    mesh_wall(&mut state, x, y, tile, Direction::North);
-                }
-                if y == map.rows() - 1 || height > map[(y + 1, x)].height.min_height() {
-                    mesh_wall(&mut state, x, y, tile, Direction::South);
-                }
-            }
-            TileHeight::Ramp { height, .. } => {
-                let dir_v = height.dir == TileRampDirection::Vertical;
-                let height = tile.height.max_height();
-                if dir_v && (x == 0 || height > map[(y, x - 1)].height.center_height()) {
-                    mesh_wall(&mut state, x, y, tile, Direction::West);
-                }
-                if dir_v && (x == map.cols() - 1 || height > map[(y, x + 1)].height.center_height())
-                {
-                    mesh_wall(&mut state, x, y, tile, Direction::East);
-                }
-                if !dir_v && (y == 0 || height > map[(y - 1, x)].height.center_height()) {
-                    mesh_wall(&mut state, x, y, tile, Direction::North);
-                }
-                if !dir_v
-                    && (y == map.rows() - 1 || height > map[(y + 1, x)].height.center_height())
-                {
-                    mesh_wall(&mut state, x, y, tile, Direction::South);
-                }
-            }
+    let bundle = (
+        MeshObject {
+            mesh: Mesh3d(meshes.add(terrain)),
+            material: MeshMaterial3d(root.atlas.clone()),
+            transform: Transform::default(),
+        },
+        MapMeshMarker,
+        MapChunk { range, stats },
+        Children::spawn((block_children, key_gates)),
+    );
+    (bundle, stats)
+}
+
+/// The whole map, or [`None`] for an empty one
+fn map_range(map: &Grid<TileData>) -> Option<TileRange> {
+    (map.rows() > 0 && map.cols() > 0).then(|| TileRange {
+        start: MpsVec2::ZERO,
+        end: MpsVec2::new(map.cols() as i32 - 1, map.rows() as i32 - 1),
+    })
+}
+
+/// Splits the map into the chunks its mesh is built from, counting from the north-west corner
+fn map_chunks(map: &Grid<TileData>) -> impl Iterator<Item = TileRange> + use<> {
+    let end = MpsVec2::new(map.cols() as i32 - 1, map.rows() as i32 - 1);
+    (0..=end.y).step_by(CHUNK_SIZE as usize).flat_map(move |y| {
+        (0..=end.x)
+            .step_by(CHUNK_SIZE as usize)
+            .map(move |x| TileRange {
+                start: MpsVec2::new(x, y),
+                end: MpsVec2::new(x + CHUNK_SIZE - 1, y + CHUNK_SIZE - 1).min(end),
+            })
+    })
+}
+
+/// A plane under the whole map, with the floor texture repeated once per tile
+fn mesh_floor(map: &Grid<TileData>) -> Mesh {
+    let mut floor = State::new(map);
+    let x2 = map.cols() as f32 - 0.5;
+    let y2 = map.rows() as f32 - 0.5;
+    floor.positions.push([-0.5, 0.0, -0.5]);
+    floor.positions.push([x2, 0.0, -0.5]);
+    floor.positions.push([-0.5, 0.0, y2]);
+    floor.positions.push([x2, 0.0, y2]);
+    floor.push_quad_uv_indices((0.0, 0.0, map.cols() as f32, map.rows() as f32), 0);
+    floor.into_mesh()
+}
+
+/// Where the blocks, trims, and key gates go, separate from the meshes and materials they use so
+/// that exports can place them too
+#[derive(Default)]
+struct MapDetails {
+    blocks: Vec<(Cuboid, Transform)>,
+    trims: Vec<(Cuboid, Transform)>,
+    /// The position and Y rotation of each gate
+    key_gates: Vec<(Vec3, f32)>,
+}
+
+fn map_details(map: &Grid<TileData>, settings: &MeshSettings, region: TileRange) -> MapDetails {
+    let mut details = MapDetails::default();
+    for pos in region {
+        let (x, y) = (pos.x as usize, pos.y as usize);
+        let tile = &map[(y, x)];
+        if tile.height == TileHeight::default() {
+            continue;
         }
 
         let xf = x as f32;
@@ -95,15 +347,10 @@ pub fn mesh_map(
         const BLOCK_SIZE: f32 = 1.0 / 8.0;
         const BLOCK_SIZE_2: f32 = BLOCK_SIZE / 2.0;
         let mut add_block = |width, depth, x, z| {
-            block_children.push(MeshObject {
-                mesh: Mesh3d(meshes.add(Cuboid::new(width, BLOCK_SIZE, depth).mesh())),
-                material: MeshMaterial3d(block_material.clone()),
-                transform: Transform::from_translation(Vec3::new(
-                    x,
-                    center_height as f32 + BLOCK_SIZE_2,
-                    z,
-                )),
-            })
+            details.blocks.push((
+                Cuboid::new(width, BLOCK_SIZE, depth),
+                Transform::from_translation(Vec3::new(x, center_height as f32 + BLOCK_SIZE_2, z)),
+            ))
         };
         if x > 0 && tile.connections.west.impassible() && !tile.ramp() {
             let neighbor = &map[(y, x - 1)];
@@ -146,21 +393,11 @@ pub fn mesh_map(
         const TRIM_SIZE: f32 = BLOCK_SIZE / 2.0;
         const TRIM_SIZE_2: f32 = TRIM_SIZE / 2.0;
         let mut add_trim = |width, depth, x, y_offset, z, x_angle, z_angle| {
-            block_children.push(MeshObject {
-                mesh: Mesh3d(meshes.add(Cuboid::new(width, TRIM_SIZE, depth).mesh())),
-                material: MeshMaterial3d(trim_material.clone()),
-                transform: Transform::from_translation(Vec3::new(
-                    x,
-                    center_height as f32 + y_offset,
-                    z,
-                ))
-                .with_rotation(Quat::from_euler(
-                    EulerRot::XYZ,
-                    x_angle,
-                    0.0,
-                    z_angle,
-                )),
-            })
+            details.trims.push((
+                Cuboid::new(width, TRIM_SIZE, depth),
+                Transform::from_translation(Vec3::new(x, center_height as f32 + y_offset, z))
+                    .with_rotation(Quat::from_euler(EulerRot::XYZ, x_angle, 0.0, z_angle)),
+            ))
         };
         macro_rules! x_axis_trim {
             ($non_ramp_x_cond:expr, $x_check_col:expr, $x_coord:expr) => {
@@ -300,19 +537,24 @@ pub fn mesh_map(
                 }
             };
         }
-        if x == 0 || map[(y, x - 1)].height == TileHeight::default() {
-            x_axis_trim!(x > 0, x - 1, xf - 0.5 + TRIM_SIZE_2);
-        }
-        if x == map.cols() - 1 || map[(y, x + 1)].height == TileHeight::default() {
-            x_axis_trim!(x < map.cols() - 1, x + 1, xf + 0.5 - TRIM_SIZE_2);
-        }
-        if y == 0 || map[(y - 1, x)].height == TileHeight::default() {
-            z_axis_trim!(yf - 0.5 + TRIM_SIZE_2);
-        }
-        if y == map.rows() - 1 || map[(y + 1, x)].height == TileHeight::default() {
-            z_axis_trim!(yf + 0.5 - TRIM_SIZE_2);
+        if settings.show_trims {
+            if x == 0 || map[(y, x - 1)].height == TileHeight::default() {
+                x_axis_trim!(x > 0, x - 1, xf - 0.5 + TRIM_SIZE_2);
+            }
+            if x == map.cols() - 1 || map[(y, x + 1)].height == TileHeight::default() {
+                x_axis_trim!(x < map.cols() - 1, x + 1, xf + 0.5 - TRIM_SIZE_2);
+            }
+            if y == 0 || map[(y - 1, x)].height == TileHeight::default() {
+                z_axis_trim!(yf - 0.5 + TRIM_SIZE_2);
+            }
+            if y == map.rows() - 1 || map[(y + 1, x)].height == TileHeight::default() {
+                z_axis_trim!(yf + 0.5 - TRIM_SIZE_2);
+            }
         }
 
+        if !settings.show_key_gates {
+            continue;
+        }
         const LOCKED_CONNECTION: Connection = Connection::Conditional(ConnectionCondition::Lock);
         if x > 0
             && tile.connections.west == LOCKED_CONNECTION
@@ -321,8 +563,7 @@ pub fn mesh_map(
             let neighbor = &map[(y, x - 1)];
             let height = tile.height.center_height() as f32;
             let neighbor_height = neighbor.height.center_height() as f32;
-            key_gates.push(key_gate(
-                assets,
+            details.key_gates.push((
                 match height.total_cmp(&neighbor_height) {
                     Ordering::Greater => Vec3::new(xf - 0.4375, height, yf),
                     Ordering::Less => Vec3::new(xf - 1.0 + 0.4375, neighbor_height, yf),
@@ -342,8 +583,7 @@ pub fn mesh_map(
             let neighbor = &map[(y - 1, x)];
             let height = tile.height.center_height() as f32;
             let neighbor_height = neighbor.height.center_height() as f32;
-            key_gates.push(key_gate(
-                assets,
+            details.key_gates.push((
                 match height.total_cmp(&neighbor_height) {
                     Ordering::Greater => Vec3::new(xf, height, yf - 0.4375),
                     Ordering::Less => Vec3::new(xf, neighbor_height, yf - 1.0 + 0.4375),
@@ -353,53 +593,476 @@ pub fn mesh_map(
             ));
         }
     }
+    details
+}
 
-    (
-        MeshObject {
-            mesh: Mesh3d(meshes.add(state.into_mesh())),
-            material: MeshMaterial3d(atlas),
-            transform: Transform::default(),
-        },
-        MapMeshMarker,
-        Children::spawn((
-            block_children,
-            key_gates,
-            Spawn((
-                MeshObject {
-                    mesh: Mesh3d(meshes.add({
-                        let mut floor = State::new(map);
-                        let x2 = map.cols() as f32 - 0.5;
-                        let y2 = map.rows() as f32 - 0.5;
-                        floor.positions.push([-0.5, 0.0, -0.5]);
-                        floor.positions.push([x2, 0.0, -0.5]);
-                        floor.positions.push([-0.5, 0.0, y2]);
-                        floor.positions.push([x2, 0.0, y2]);
-                        floor.push_quad_uv_indices(
-                            (0.0, 0.0, map.cols() as f32, map.rows() as f32),
-                            0,
-                        );
-                        floor.into_mesh()
-                    })),
-                    material: MeshMaterial3d(materials.add(StandardMaterial {
-                        base_color_texture: Some(assets::floor(assets)),
-                        perceptual_roughness: 1.0,
-                        double_sided: true,
-                        cull_mode: None,
-                        alpha_mode: AlphaMode::Add,
-                        ..Default::default()
-                    })),
-                    transform: Transform::default(),
-                },
-                NotShadowCaster,
-                NotShadowReceiver,
-            )),
-        )),
-    )
+/// A mesh that was read back from [`Mesh`] to be written to a file
+#[derive(Default)]
+pub struct ModelMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl ModelMesh {
+    fn from_meshes<'a>(meshes: impl IntoIterator<Item = &'a Mesh>) -> Self {
+        let mut result = Self::default();
+        for mesh in meshes {
+            let index_start = result.positions.len() as u32;
+            if let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            {
+                result.positions.extend_from_slice(positions);
+            }
+            if let Some(VertexAttributeValues::Float32x3(normals)) =
+                mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+            {
+                result.normals.extend_from_slice(normals);
+            }
+            if let Some(VertexAttributeValues::Float32x2(uvs)) =
+                mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+            {
+                result.uvs.extend_from_slice(uvs);
+            }
+            if let Some(indices) = mesh.indices() {
+                result
+                    .indices
+                    .extend(indices.iter().map(|index| index as u32 + index_start));
+            }
+        }
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Reverses the winding of every triangle along with its normals
+    fn flip_winding(&mut self) {
+        for triangle in self.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+        for normal in &mut self.normals {
+            *normal = normal.map(|x| -x);
+        }
+    }
+}
+
+/// The map mesh split up by material, for exporting to other tools. Key gates are left out.
+pub struct MapModel {
+    /// The tops and walls of the tiles, textured with the atlas
+    pub terrain: ModelMesh,
+    pub blocks: ModelMesh,
+    pub trims: ModelMesh,
+    /// Textured with the floor texture repeated once per tile
+    pub floor: Option<ModelMesh>,
+}
+
+impl MapModel {
+    pub fn new(map: &Grid<TileData>, settings: &MeshSettings, include_floor: bool) -> Self {
+        // Merged tops need the atlas material to texture them, so exports keep one quad per tile
+        let range = map_range(map);
+        let terrain = range.map(|range| {
+            mesh_terrain(map, settings, &mut TileMeshCache::default(), false, range).into_mesh()
+        });
+        let details = range
+            .map(|range| map_details(map, settings, range))
+            .unwrap_or_default();
+        let cuboids = |cuboids: Vec<(Cuboid, Transform)>| {
+            let meshes = cuboids
+                .into_iter()
+                .map(|(cuboid, transform)| cuboid.mesh().build().transformed_by(transform))
+                .collect::<Vec<_>>();
+            ModelMesh::from_meshes(&meshes)
+        };
+        Self {
+            terrain: ModelMesh::from_meshes(&terrain),
+            blocks: cuboids(details.blocks),
+            trims: cuboids(details.trims),
+            floor: include_floor.then(|| ModelMesh::from_meshes([&mesh_floor(map)])),
+        }
+    }
+
+    /// Each part of the model along with its name, always in the same order so that exports can
+    /// refer to their materials by index
+    pub fn parts(&self) -> [(&'static str, Option<&ModelMesh>); 4] {
+        [
+            ("Terrain", Some(&self.terrain)),
+            ("Blocks", Some(&self.blocks)),
+            ("Trims", Some(&self.trims)),
+            ("Floor", self.floor.as_ref()),
+        ]
+    }
+
+    /// Reverses the winding of every triangle, for tools that expect clockwise front faces
+    pub fn flip_winding(&mut self) {
+        self.terrain.flip_winding();
+        self.blocks.flip_winding();
+        self.trims.flip_winding();
+        if let Some(floor) = &mut self.floor {
+            floor.flip_winding();
+        }
+    }
+}
+
+/// Below this many tiles, meshing on one thread is faster than splitting up the work
+const PARALLEL_MESH_MIN_TILES: usize = 64 * 64;
+/// How many rows of tiles each parallel meshing task handles
+const PARALLEL_MESH_ROWS: usize = 8;
+
+/// The top and wall geometry generated for each tile in the last remesh. A tile's geometry only
+/// depends on the tile itself and the heights of its direct neighbors, so those are kept as the
+/// key, and tiles whose key hasn't changed reuse their geometry.
+#[derive(Resource, Default)]
+pub struct TileMeshCache {
+    cols: usize,
+    settings: Option<MeshSettings>,
+    merge_tops: bool,
+    tiles: Vec<Option<CachedTile>>,
+    shared: SharedMeshAssets,
+}
+
+/// The block and trim meshes and materials, shared between every block and trim that looks the
+/// same instead of each getting its own copy
+#[derive(Default)]
+struct SharedMeshAssets {
+    /// Keyed by the bits of each cuboid's half size. Blocks only come in two sizes.
+    cuboids: HashMap<[u32; 3], Handle<Mesh>>,
+    /// The block and trim colors of the last remesh, keyed by sRGB color
+    colors: HashMap<[u8; 3], Handle<StandardMaterial>>,
+    floor: Option<Handle<StandardMaterial>>,
+}
+
+impl SharedMeshAssets {
+    fn cuboid(&mut self, cuboid: Cuboid, meshes: &mut Assets<Mesh>) -> Handle<Mesh> {
+        self.cuboids
+            .entry(cuboid.half_size.to_array().map(f32::to_bits))
+            .or_insert_with(|| meshes.add(cuboid.mesh().build()))
+            .clone()
+    }
+
+    fn color_material(
+        &mut self,
+        [r, g, b]: [u8; 3],
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Handle<StandardMaterial> {
+        self.colors
+            .entry([r, g, b])
+            .or_insert_with(|| {
+                materials.add(StandardMaterial {
+                    base_color: Srgba::rgb_u8(r, g, b).into(),
+                    perceptual_roughness: 1.0,
+                    ..Default::default()
+                })
+            })
+            .clone()
+    }
+
+    fn floor_material(
+        &mut self,
+        assets: &AssetServer,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Handle<StandardMaterial> {
+        self.floor
+            .get_or_insert_with(|| {
+                materials.add(StandardMaterial {
+                    base_color_texture: Some(assets::floor(assets)),
+                    perceptual_roughness: 1.0,
+                    double_sided: true,
+                    cull_mode: None,
+                    alpha_mode: AlphaMode::Add,
+                    ..Default::default()
+                })
+            })
+            .clone()
+    }
+}
+
+impl TileMeshCache {
+    /// Forces every tile to be meshed again on the next remesh
+    pub fn invalidate(&mut self) {
+        self.tiles.clear();
+    }
+
+    fn prepare(&mut self, map: &Grid<TileData>, settings: &MeshSettings, merge_tops: bool) {
+        if self.cols != map.cols()
+            || self.tiles.len() != map.rows() * map.cols()
+            || self.settings.as_ref() != Some(settings)
+            || self.merge_tops != merge_tops
+        {
+            self.cols = map.cols();
+            self.settings = Some(settings.clone());
+            self.merge_tops = merge_tops;
+            self.tiles.clear();
+            self.tiles.resize_with(map.rows() * map.cols(), || None);
+        }
+    }
+}
+
+struct CachedTile {
+    key: TileMeshKey,
+    geometry: TileGeometry,
+}
+
+#[derive(PartialEq)]
+struct TileMeshKey {
+    tile: TileData,
+    /// West, east, north, then south. [`None`] is outside the map.
+    neighbors: [Option<TileHeight>; 4],
+}
+
+impl TileMeshKey {
+    fn new(map: &Grid<TileData>, x: usize, y: usize) -> Self {
+        let neighbor = |x: Option<usize>, y: Option<usize>| Some(map.get(y?, x?)?.height);
+        Self {
+            tile: map[(y, x)].clone(),
+            neighbors: [
+                neighbor(x.checked_sub(1), Some(y)),
+                neighbor(Some(x + 1), Some(y)),
+                neighbor(Some(x), y.checked_sub(1)),
+                neighbor(Some(x), Some(y + 1)),
+            ],
+        }
+    }
+}
+
+/// Meshes the tops and walls of the tiles in `region` that changed since they were cached,
+/// returning how many were meshed again. Large regions are split into chunks of rows that are
+/// meshed in parallel.
+fn update_tile_cache(
+    map: &Grid<TileData>,
+    settings: &MeshSettings,
+    cache: &mut TileMeshCache,
+    merge_tops: bool,
+    region: TileRange,
+) -> usize {
+    cache.prepare(map, settings, merge_tops);
+    let cached = &cache.tiles;
+    let columns = region.start.x as usize..=region.end.x as usize;
+    let mesh_rows = |rows: Range<usize>| {
+        rows.flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .filter_map(|(x, y)| {
+                let index = y * map.cols() + x;
+                let key = TileMeshKey::new(map, x, y);
+                if cached[index]
+                    .as_ref()
+                    .is_some_and(|cached| cached.key == key)
+                {
+                    return None;
+                }
+                let geometry = mesh_tile(map, settings, merge_tops, x, y);
+                Some((index, CachedTile { key, geometry }))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let rows = region.start.y as usize..region.end.y as usize + 1;
+    let updated = if region.area() < PARALLEL_MESH_MIN_TILES {
+        mesh_rows(rows)
+    } else {
+        rows.clone()
+            .into_par_iter()
+            .step_by(PARALLEL_MESH_ROWS)
+            .map(|start| mesh_rows(start..(start + PARALLEL_MESH_ROWS).min(rows.end)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+            .collect()
+    };
+
+    let count = updated.len();
+    for (index, tile) in updated {
+        cache.tiles[index] = Some(tile);
+    }
+    count
+}
+
+/// Collects the cached geometry of the tiles in `region`, which has to be up to date. With
+/// `merge_tops`, the tops of flat tiles are merged into larger quads afterwards.
+fn cached_terrain<'a>(
+    map: &'a Grid<TileData>,
+    cache: &TileMeshCache,
+    merge_tops: bool,
+    region: TileRange,
+) -> State<'a> {
+    let mut state = State::new(map);
+    for pos in region {
+        if let Some(tile) = &cache.tiles[pos.y as usize * map.cols() + pos.x as usize] {
+            state.append(&tile.geometry);
+        }
+    }
+    if merge_tops {
+        mesh_merged_tops(&mut state, map, region);
+    }
+    state
+}
+
+/// Meshes the tops and walls of the tiles in `region`, reusing cached geometry for tiles that
+/// haven't changed
+fn mesh_terrain<'a>(
+    map: &'a Grid<TileData>,
+    settings: &MeshSettings,
+    cache: &mut TileMeshCache,
+    merge_tops: bool,
+    region: TileRange,
+) -> State<'a> {
+    update_tile_cache(map, settings, cache, merge_tops, region);
+    cached_terrain(map, cache, merge_tops, region)
+}
+
+fn mesh_tile(
+    map: &Grid<TileData>,
+    settings: &MeshSettings,
+    merge_tops: bool,
+    x: usize,
+    y: usize,
+) -> TileGeometry {
+    let mut state = State::new(map);
+    let tile = &map[(y, x)];
+    if tile.height == TileHeight::default() {
+        return state.into();
+    }
+
+    if !merge_tops || tile.ramp() {
+        internal_mesh_top(&mut state, x, y, tile, 0.0);
+    }
+    let show_wall = |direction, automatic| tile.wall_visibility[direction].resolve(automatic);
+    match tile.height {
+        TileHeight::Flat { height, .. } => {
+            if show_wall(
+                Direction::West,
+                x == 0 || height > map[(y, x - 1)].height.min_height(),
+            ) {
+                mesh_wall(&mut state, x, y, tile, Direction::West);
+            }
+            if show_wall(
+                Direction::East,
+                x == map.cols() - 1 || height > map[(y, x + 1)].height.min_height(),
+            ) {
+                mesh_wall(&mut state, x, y, tile, Direction::East);
+            }
+            if show_wall(
+                Direction::North,
+                y == 0 || height > map[(y - 1, x)].height.min_height(),
+            ) {
+                mesh_wall(&mut state, x, y, tile, Direction::North);
+            }
+            if show_wall(
+                Direction::South,
+                y == map.rows() - 1 || height > map[(y + 1, x)].height.min_height(),
+            ) {
+                mesh_wall(&mut state, x, y, tile, Direction::South);
+            }
+        }
+        TileHeight::Ramp { height, .. } => {
+            let dir_v = height.dir == TileRampDirection::Vertical;
+            // The wall is only visible where this ramp's edge rises above the neighbor's edge.
+            // A neighbor that meets it flush along the whole side, such as one continuing its
+            // slope sideways, would otherwise get a second wall in the same place, which shows
+            // up as a seam unless ramps are merged.
+            let needs_wall = |side: Direction, neighbor: &TileData| {
+                let edge = tile.height.edge_heights(side);
+                let neighbor_edge = neighbor.height.edge_heights(side.opposite());
+                edge[0] > neighbor_edge[0]
+                    || edge[1] > neighbor_edge[1]
+                    || (!settings.merge_ramps && edge == neighbor_edge)
+            };
+            // Ramps only have walls along their sides, so overrides on the sloped ends are ignored
+            if dir_v
+                && show_wall(
+                    Direction::West,
+                    x == 0 || needs_wall(Direction::West, &map[(y, x - 1)]),
+                )
+            {
+                mesh_wall(&mut state, x, y, tile, Direction::West);
+            }
+            if dir_v
+                && show_wall(
+                    Direction::East,
+                    x == map.cols() - 1 || needs_wall(Direction::East, &map[(y, x + 1)]),
+                )
+            {
+                mesh_wall(&mut state, x, y, tile, Direction::East);
+            }
+            if !dir_v
+                && show_wall(
+                    Direction::North,
+                    y == 0 || needs_wall(Direction::North, &map[(y - 1, x)]),
+                )
+            {
+                mesh_wall(&mut state, x, y, tile, Direction::North);
+            }
+            if !dir_v
+                && show_wall(
+                    Direction::South,
+                    y == map.rows() - 1 || needs_wall(Direction::South, &map[(y + 1, x)]),
+                )
+            {
+                mesh_wall(&mut state, x, y, tile, Direction::South);
+            }
+        }
+    }
+    state.into()
+}
+
+/// Meshes the tops of every flat tile in `region`, greedily merging rectangles of tiles with the
+/// same height and material into single quads. Their UVs count tiles instead of covering the
+/// atlas, so that [`AtlasTiling`] can repeat the material's cell across the quad.
+fn mesh_merged_tops(state: &mut State, map: &Grid<TileData>, region: TileRange) {
+    let top = |pos: MpsVec2| {
+        let tile = &map[(pos.y as usize, pos.x as usize)];
+        match tile.height {
+            _ if tile.height == TileHeight::default() => None,
+            TileHeight::Flat { height, .. } => Some((height, tile.materials.material)),
+            TileHeight::Ramp { .. } => None,
+        }
+    };
+
+    let mut merged = vec![false; region.area()];
+    for pos in region {
+        if merged[region.index_of(pos)] {
+            continue;
+        }
+        let Some((height, material)) = top(pos) else {
+            continue;
+        };
+        let can_merge = |x: i32, y: i32| {
+            let pos = MpsVec2::new(x, y);
+            !merged[region.index_of(pos)] && top(pos) == Some((height, material))
+        };
+        let width = (pos.x..=region.end.x)
+            .take_while(|&x| can_merge(x, pos.y))
+            .count() as i32;
+        let depth = (pos.y..=region.end.y)
+            .take_while(|&y| (pos.x..pos.x + width).all(|x| can_merge(x, y)))
+            .count() as i32;
+        for y in pos.y..pos.y + depth {
+            let row = region.index_of(MpsVec2::new(pos.x, y));
+            merged[row..row + width as usize].fill(true);
+        }
+
+        let index_start = state.positions.len() as u32;
+        let x1 = pos.x as f32 - 0.5;
+        let y1 = pos.y as f32 - 0.5;
+        let x2 = x1 + width as f32;
+        let y2 = y1 + depth as f32;
+        let height = height as f32;
+        state.positions.push([x1, height, y1]);
+        state.positions.push([x2, height, y1]);
+        state.positions.push([x1, height, y2]);
+        state.positions.push([x2, height, y2]);
+        state.push_quad_uv_indices((0.0, 0.0, width as f32, depth as f32), index_start);
+        let (u1, v1, _, _) = material.to_uv_coords();
+        state.cells.resize(index_start as usize, NO_ATLAS_CELL);
+        state.cells.extend([[u1, v1]; 4]);
+    }
 }
 
 pub fn mesh_top_highlights(
     map: &Grid<TileData>,
     tile_range: TileRange,
+    color: Srgba,
     materials: &mut Assets<StandardMaterial>,
     meshes: &mut Assets<Mesh>,
 ) -> impl Bundle {
@@ -414,10 +1077,40 @@ pub fn mesh_top_highlights(
         }
     }
 
+    highlight_object(state, color, materials, meshes)
+}
+
+/// Like [`mesh_top_highlights`], but covering the tiles of the whole map matching `filter`.
+/// Returns [`None`] if there aren't any.
+pub fn mesh_top_tint(
+    map: &Grid<TileData>,
+    filter: impl Fn(&TileData) -> bool,
+    color: Srgba,
+    materials: &mut Assets<StandardMaterial>,
+    meshes: &mut Assets<Mesh>,
+) -> Option<impl Bundle> {
+    let mut state = State::new(map);
+    for ((y, x), tile) in map.indexed_iter() {
+        if filter(tile) {
+            internal_mesh_top(&mut state, x, y, tile, 0.01);
+        }
+    }
+    if state.positions.is_empty() {
+        return None;
+    }
+    Some(highlight_object(state, color, materials, meshes))
+}
+
+fn highlight_object(
+    state: State,
+    color: Srgba,
+    materials: &mut Assets<StandardMaterial>,
+    meshes: &mut Assets<Mesh>,
+) -> impl Bundle {
     (
         Mesh3d(meshes.add(state.into_mesh())),
         MeshMaterial3d(materials.add(StandardMaterial {
-            base_color: Srgba::rgba_u8(0x54, 0xAF, 0xE7, 0x80).into(),
+            base_color: color.into(),
             perceptual_roughness: 1.0,
             double_sided: true,
             cull_mode: None,
@@ -429,10 +1122,16 @@ pub fn mesh_top_highlights(
     )
 }
 
+/// The atlas cell of vertices that aren't part of a merged quad
+const NO_ATLAS_CELL: [f32; 2] = [-1.0, -1.0];
+
 struct State<'a> {
     map: &'a Grid<TileData>,
     positions: Vec<[f32; 3]>,
     uvs: Vec<[f32; 2]>,
+    /// The corner of the atlas cell repeated across each merged quad, for [`AtlasTiling`]. Empty
+    /// when nothing is merged, and otherwise filled with [`NO_ATLAS_CELL`] up to each merged quad.
+    cells: Vec<[f32; 2]>,
     indices: Vec<u32>,
 }
 
@@ -442,6 +1141,7 @@ impl<'a> State<'a> {
             map,
             positions: vec![],
             uvs: vec![],
+            cells: vec![],
             indices: vec![],
         }
     }
@@ -468,7 +1168,19 @@ impl<'a> State<'a> {
             .extend([index_start, index_start + 3, index_start + 2]);
     }
 
-    fn into_mesh(self) -> Mesh {
+    /// Copies a tile's geometry into this state, offsetting its indices to match
+    fn append(&mut self, geometry: &TileGeometry) {
+        let index_start = self.positions.len() as u32;
+        self.positions.extend_from_slice(&geometry.positions);
+        self.uvs.extend_from_slice(&geometry.uvs);
+        self.indices
+            .extend(geometry.indices.iter().map(|index| index + index_start));
+    }
+
+    fn into_mesh(mut self) -> Mesh {
+        if !self.cells.is_empty() {
+            self.cells.resize(self.positions.len(), NO_ATLAS_CELL);
+        }
         let mut mesh = Mesh::new(
             PrimitiveTopology::TriangleList,
             RenderAssetUsages::default(),
@@ -476,11 +1188,30 @@ impl<'a> State<'a> {
         .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
         .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs)
         .with_inserted_indices(Indices::U32(self.indices));
+        if !self.cells.is_empty() {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, self.cells);
+        }
         mesh.compute_normals();
         mesh
     }
 }
 
+struct TileGeometry {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl From<State<'_>> for TileGeometry {
+    fn from(state: State) -> Self {
+        Self {
+            positions: state.positions,
+            uvs: state.uvs,
+            indices: state.indices,
+        }
+    }
+}
+
 fn internal_mesh_top(state: &mut State, x: usize, y: usize, tile: &TileData, y_offset: f32) {
     let xf = x as f32;
     let yf = y as f32;
@@ -693,3 +1424,276 @@ fn mesh_wall(
 
     Some(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChunkGeometry, SharedMeshAssets, State, TileMeshCache, cached_terrain, map_chunks,
+        map_range, mesh_map, mesh_terrain, mesh_tile, mesh_wall, update_tile_cache,
+    };
+    use crate::schema::{MpsMaterial, MpsVec2, TileData, TileHeight, TileRamp, TileRampDirection};
+    use crate::settings::MeshSettings;
+    use crate::sync::Direction;
+    use crate::tile_range::TileRange;
+    use bevy::prelude::{
+        App, AssetApp, AssetPlugin, AssetServer, Assets, Cuboid, Handle, Image, Mesh,
+        MinimalPlugins, StandardMaterial,
+    };
+    use grid::Grid;
+    use monostate::MustBeBool;
+    use rayon::prelude::*;
+    use std::time::Instant;
+
+    fn flat(height: f64) -> TileData {
+        TileData {
+            height: TileHeight::Flat {
+                ramp: MustBeBool,
+                height,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn triangle_count(map: &Grid<TileData>, merge_tops: bool) -> usize {
+        let settings = MeshSettings::default();
+        let range = map_range(map).unwrap();
+        let mut cache = TileMeshCache::default();
+        let state = mesh_terrain(map, &settings, &mut cache, merge_tops, range);
+        state.indices.len() / 3
+    }
+
+    #[test]
+    fn test_shared_cuboids() {
+        let mut meshes = Assets::<Mesh>::default();
+        let mut shared = SharedMeshAssets::default();
+        let block = shared.cuboid(Cuboid::new(0.125, 0.125, 1.0), &mut meshes);
+        assert_eq!(
+            shared.cuboid(Cuboid::new(0.125, 0.125, 1.0), &mut meshes),
+            block
+        );
+        assert_ne!(
+            shared.cuboid(Cuboid::new(1.0, 0.125, 0.125), &mut meshes),
+            block
+        );
+        assert_eq!(meshes.len(), 2);
+    }
+
+    #[test]
+    fn test_remesh_reuses_materials() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Image>();
+        let assets = app.world().resource::<AssetServer>();
+        let map = Grid::init(4, 4, flat(1.0));
+        let mut settings = MeshSettings::default();
+        let mut cache = TileMeshCache::default();
+        let mut materials = Assets::<StandardMaterial>::default();
+        let mut meshes = Assets::<Mesh>::default();
+        let mut remesh = |settings: &MeshSettings| {
+            mesh_map(
+                &map,
+                settings,
+                &mut cache,
+                Handle::default(),
+                assets,
+                &mut materials,
+                &mut meshes,
+            );
+            materials.len()
+        };
+
+        let count = remesh(&settings);
+        assert_eq!(remesh(&settings), count);
+        assert_eq!(remesh(&settings), count);
+
+        // The handles of old colors are dropped, so they're freed along with the old chunks
+        settings.block_color = [0x12, 0x34, 0x56];
+        remesh(&settings);
+        assert_eq!(cache.shared.colors.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_uniform_grid() {
+        let map = Grid::init(8, 8, flat(1.0));
+        // All 64 tops become a single quad, and the walls stay the same
+        assert_eq!(
+            triangle_count(&map, false) - triangle_count(&map, true),
+            64 * 2 - 2
+        );
+    }
+
+    #[test]
+    fn test_merge_differing_heights() {
+        let mut map = Grid::init(4, 4, flat(1.0));
+        for y in 0..4 {
+            for x in (y % 2..4).step_by(2) {
+                map[(y, x)] = flat(2.0);
+            }
+        }
+        assert_eq!(triangle_count(&map, false), triangle_count(&map, true));
+    }
+
+    #[test]
+    fn test_merge_around_ramp() {
+        let mut map = Grid::init(3, 3, flat(1.0));
+        map[(1, 1)].height = map[(1, 1)]
+            .height
+            .with_ramp_dir(Some(TileRampDirection::Vertical))
+            .with_pos_height(2.0);
+        // The eight flat tops around the ramp are merged into four quads: the top row, both
+        // sides of the ramp, and the tile below it
+        assert_eq!(
+            triangle_count(&map, false) - triangle_count(&map, true),
+            (8 - 4) * 2
+        );
+    }
+
+    #[test]
+    fn test_flipped_ramp_wall() {
+        let mut map = Grid::init(1, 1, flat(1.0));
+        map[(0, 0)].height = map[(0, 0)]
+            .height
+            .with_ramp_dir(Some(TileRampDirection::Vertical))
+            .with_pos_height(2.0);
+        map[(0, 0)].materials.wall_material.west = vec![MpsMaterial::default()];
+        // The peak of a ramp's side wall is at the high end, which moves when the ramp is flipped
+        let peak_z = |tile: &TileData| {
+            let mut state = State::new(&map);
+            mesh_wall(&mut state, 0, 0, tile, Direction::West).unwrap();
+            state.positions[2][2]
+        };
+        let tile = map[(0, 0)].clone();
+        assert_eq!(peak_z(&tile), 0.5);
+        let flipped = TileData {
+            height: tile.height.with_flipped_heights(),
+            ..tile.clone()
+        };
+        assert_eq!(peak_z(&flipped), -0.5);
+        let turned = TileData {
+            height: flipped.height.with_turned_ramp().with_turned_ramp(),
+            ..flipped
+        };
+        assert_eq!(peak_z(&turned), -0.5);
+    }
+
+    #[test]
+    fn test_flush_ramp_walls() {
+        let ramp = |neg, pos| TileData {
+            height: TileHeight::Ramp {
+                ramp: MustBeBool,
+                height: TileRamp {
+                    dir: TileRampDirection::Horizontal,
+                    pos,
+                    neg,
+                },
+            },
+            ..Default::default()
+        };
+        // Triangles of a ramp rising towards the east, with `south` below it
+        let triangles = |south: TileData, settings: &MeshSettings| {
+            let map = Grid::from_vec(vec![ramp(1.0, 2.0), south], 1);
+            mesh_tile(&map, settings, false, 0, 0).indices.len() / 3
+        };
+        let settings = MeshSettings::default();
+        // A higher neighbor covers the whole south side, leaving only the north wall
+        let north_wall_only = triangles(flat(3.0), &settings);
+        // The same ramp continuing south meets it flush, so neither of them gets a wall there
+        assert_eq!(triangles(ramp(1.0, 2.0), &settings), north_wall_only);
+        // A ramp sloping the other way crosses it, leaving the east half of the wall showing
+        assert!(triangles(ramp(2.5, 0.5), &settings) > north_wall_only);
+
+        // Without merging, the flush ramp gets its wall back
+        let settings = MeshSettings {
+            merge_ramps: false,
+            ..settings
+        };
+        assert!(triangles(ramp(1.0, 2.0), &settings) > north_wall_only);
+    }
+
+    #[test]
+    fn test_incremental_remesh() {
+        let settings = MeshSettings::default();
+        let mut map = Grid::init(100, 100, flat(1.0));
+        let mut cache = TileMeshCache::default();
+
+        let full_range = map_range(&map).unwrap();
+        assert_eq!(
+            update_tile_cache(&map, &settings, &mut cache, true, full_range),
+            100 * 100
+        );
+
+        map[(50, 50)] = flat(3.0);
+        let dirty = TileRange {
+            start: MpsVec2::new(50, 50),
+            end: MpsVec2::new(50, 50),
+        }
+        .grown(1, MpsVec2::new(100, 100));
+        let chunks = map_chunks(&map)
+            .filter(|chunk| chunk.intersection(dirty).is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 1);
+        // Only the edited tile and the four neighbors whose walls face it are meshed again
+        assert_eq!(
+            update_tile_cache(&map, &settings, &mut cache, true, chunks[0]),
+            5
+        );
+        let chunk = cached_terrain(&map, &cache, true, chunks[0]);
+
+        let fresh = mesh_terrain(
+            &map,
+            &settings,
+            &mut TileMeshCache::default(),
+            true,
+            chunks[0],
+        );
+        assert_eq!(chunk.positions, fresh.positions);
+        assert_eq!(chunk.uvs, fresh.uvs);
+        assert_eq!(chunk.cells, fresh.cells);
+        assert_eq!(chunk.indices, fresh.indices);
+    }
+
+    /// Run with `cargo test --release -- --ignored` to compare the timings
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_parallel_mesh_map() {
+        let settings = MeshSettings::default();
+        // Uneven heights, so that walls are meshed too
+        let tiles = (0..256 * 256)
+            .map(|i| flat(((i % 256 * 7 + i / 256 * 3) % 5) as f64 * 0.5))
+            .collect();
+        let map = Grid::from_vec(tiles, 256);
+        let build = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let start = Instant::now();
+                let mut cache = TileMeshCache::default();
+                let range = map_range(&map).unwrap();
+                update_tile_cache(&map, &settings, &mut cache, true, range);
+                let chunks = map_chunks(&map)
+                    .collect::<Vec<_>>()
+                    .into_par_iter()
+                    .map(|range| ChunkGeometry::new(&map, &settings, &cache, range))
+                    .collect::<Vec<_>>();
+                let elapsed = start.elapsed();
+                let vertices = chunks
+                    .iter()
+                    .map(|chunk| chunk.terrain.count_vertices())
+                    .sum::<usize>();
+                let indices = chunks
+                    .iter()
+                    .map(|chunk| chunk.terrain.indices().map_or(0, |indices| indices.len()))
+                    .sum::<usize>();
+                (vertices, indices, elapsed)
+            })
+        };
+
+        let (single_vertices, single_indices, single_time) = build(1);
+        let (multi_vertices, multi_indices, multi_time) = build(0);
+        assert_eq!(single_vertices, multi_vertices);
+        assert_eq!(single_indices, multi_indices);
+        println!("Meshing took {single_time:?} on one thread and {multi_time:?} on all of them");
+    }
+}
@@ -0,0 +1,172 @@
+use crate::load_file::{FileLoaded, LoadedFile};
+use crate::schema::{MapFile, MpsTransform, MpsVec2};
+use crate::sync::MapEdited;
+use bevy::asset::RenderAssetUsages;
+use bevy::image::ImageSampler;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use std::time::Duration;
+
+/// The minimap is redrawn at most this often while the map is being edited
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+const EMPTY_COLOR: [u8; 4] = [24, 24, 24, 255];
+const MIN_BRIGHTNESS: f64 = 70.0;
+
+/// How far above and behind a tile the camera is placed when it's clicked on the minimap
+const CAMERA_OFFSET: Vec3 = Vec3::new(0.0, 12.0, 8.0);
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, create_minimap_image)
+            .add_observer(on_file_loaded)
+            .add_observer(on_map_edited)
+            .add_systems(Update, update_minimap);
+    }
+}
+
+#[derive(Resource)]
+pub struct Minimap {
+    pub image: Handle<Image>,
+    /// The minimap is only redrawn while it's being shown
+    pub shown: bool,
+    /// Counts up each time the image is redrawn, so that the UI knows to pick up the new texture
+    pub revision: usize,
+    outdated: bool,
+    cooldown: Timer,
+}
+
+fn create_minimap_image(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let mut cooldown = Timer::new(UPDATE_INTERVAL, TimerMode::Once);
+    cooldown.finish();
+    commands.insert_resource(Minimap {
+        image: images.add(minimap_image(&MapFile::default())),
+        shown: false,
+        revision: 0,
+        outdated: true,
+        cooldown,
+    });
+}
+
+fn on_file_loaded(_: On<FileLoaded>, mut minimap: ResMut<Minimap>) {
+    minimap.outdated = true;
+    // Switching files shouldn't leave the old map on screen
+    minimap.cooldown.finish();
+}
+
+fn on_map_edited(_: On<MapEdited>, mut minimap: ResMut<Minimap>) {
+    minimap.outdated = true;
+}
+
+fn update_minimap(
+    mut minimap: ResMut<Minimap>,
+    time: Res<Time>,
+    file: Res<LoadedFile>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    minimap.cooldown.tick(time.delta());
+    if !minimap.shown || !minimap.outdated || !minimap.cooldown.is_finished() {
+        return;
+    }
+    minimap.outdated = false;
+    minimap.cooldown.reset();
+
+    if let Some(image) = images.get_mut(&minimap.image) {
+        *image = minimap_image(&file.file);
+        minimap.revision += 1;
+    }
+}
+
+fn minimap_image(file: &MapFile) -> Image {
+    let size = file.map_size().unwrap_or(MpsVec2::ONE);
+    let mut image = Image::new(
+        Extent3d {
+            width: size.x as u32,
+            height: size.y as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        minimap_pixels(file),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+/// Colors each tile by its height, from dim for the lowest built tile to white for the highest
+pub fn minimap_pixels(file: &MapFile) -> Vec<u8> {
+    if file.data.is_empty() {
+        return EMPTY_COLOR.to_vec();
+    }
+    let heights = file
+        .data
+        .iter()
+        .map(|tile| tile.height.max_height())
+        .filter(|&height| height > 0.0);
+    let (min, max) = heights.fold((f64::INFINITY, 0.0f64), |(min, max), height| {
+        (min.min(height), max.max(height))
+    });
+    let range = (max - min).max(f64::EPSILON);
+
+    let mut pixels = Vec::with_capacity(file.data.rows() * file.data.cols() * 4);
+    for tile in file.data.iter() {
+        let height = tile.height.max_height();
+        if height <= 0.0 {
+            pixels.extend_from_slice(&EMPTY_COLOR);
+            continue;
+        }
+        let t = if max > min {
+            (height - min) / range
+        } else {
+            1.0
+        };
+        let brightness = MIN_BRIGHTNESS.lerp(255.0, t) as u8;
+        pixels.extend_from_slice(&[brightness, brightness, brightness, 255]);
+    }
+    pixels
+}
+
+/// A view looking down at `pos` at an angle, for jumping there from the minimap
+pub fn minimap_view(file: &MapFile, pos: MpsVec2) -> MpsTransform {
+    let height = file[pos].height.max_height() as f32;
+    let target = Vec3::new(pos.x as f32, height, pos.y as f32);
+    let transform = Transform::from_translation(target + CAMERA_OFFSET).looking_at(target, Vec3::Y);
+    MpsTransform {
+        pos: transform.translation.into(),
+        rot: transform.rotation.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EMPTY_COLOR, minimap_pixels};
+    use crate::schema::{MapFile, TileData, TileHeight};
+    use grid::Grid;
+    use monostate::MustBeBool;
+
+    #[test]
+    fn test_minimap_pixels() {
+        let flat = |height| TileData {
+            height: TileHeight::Flat {
+                ramp: MustBeBool,
+                height,
+            },
+            ..Default::default()
+        };
+        let mut file = MapFile {
+            data: Grid::init(1, 3, TileData::default()),
+            ..Default::default()
+        };
+        file.data[(0, 1)] = flat(1.0);
+        file.data[(0, 2)] = flat(5.0);
+
+        let pixels = minimap_pixels(&file);
+        assert_eq!(pixels.len(), 12);
+        assert_eq!(pixels[0..4], EMPTY_COLOR);
+        assert_eq!(pixels[4..8], [70, 70, 70, 255]);
+        assert_eq!(pixels[8..12], [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::assets::{item_icons, player_sprite};
+use crate::load_file::LoadedFile;
+use crate::mesh::mesh_top_tint;
+use crate::schema::{Connection, PopupType, ShopItem, TileData, TileHeight};
+use crate::settings::EditorSettings;
+use crate::sync::Direction;
+use bevy::light::{NotShadowCaster, NotShadowReceiver};
+use bevy::prelude::*;
+use bevy::transform::TransformSystems;
+use bevy::ui::UiSystems;
+use grid::Grid;
+use transform_gizmo_bevy::GizmoCamera;
+
+/// Camera distance at which overlay icons are drawn at their base size
+const REFERENCE_DISTANCE: f32 = 8.0;
+const MIN_SCALE: f32 = 0.75;
+const MAX_SCALE: f32 = 4.0;
+
+const ICON_SIZE: f32 = 0.3;
+/// Where the two star icons of a two-star popup are drawn across its background
+const DOUBLE_ICON_OFFSETS: [f32; 2] = [-ICON_SIZE * 0.35, ICON_SIZE * 0.35];
+const LABEL_FONT_SIZE: f32 = 14.0;
+
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OverlayAssets>()
+            .add_systems(Startup, spawn_scale_reference)
+            .add_systems(Update, draw_connection_edges)
+            .add_systems(
+                PostUpdate,
+                (
+                    billboard_overlay_icons,
+                    place_overlay_labels.before(UiSystems::Layout),
+                    place_scale_reference,
+                )
+                    .before(TransformSystems::Propagate),
+            );
+    }
+}
+
+#[derive(Component)]
+pub struct TileOverlaysMarker;
+
+/// A camera-facing icon whose size tracks the camera distance, so that it stays readable when
+/// zoomed out
+#[derive(Component)]
+pub struct OverlayIcon;
+
+/// Text drawn over the viewport next to the overlay icon at this position, for what the icon
+/// can't show by itself
+#[derive(Component)]
+pub struct OverlayLabel(Vec3);
+
+/// A player-sized sprite standing on the selected tile, for judging proportions while editing
+#[derive(Component)]
+pub struct ScaleReference;
+
+/// The meshes and materials of the overlay icons, shared by every rebuild of the overlays
+#[derive(Resource)]
+pub struct OverlayAssets {
+    coin_gain_material: Handle<StandardMaterial>,
+    coin_loss_material: Handle<StandardMaterial>,
+    lucky_space_material: Handle<StandardMaterial>,
+    star_material: Handle<StandardMaterial>,
+    star_steal_material: Handle<StandardMaterial>,
+    shop_material: Handle<StandardMaterial>,
+    /// Popups don't have icons of their own, so they borrow the item icon closest to them
+    lucky_space_icon: Handle<StandardMaterial>,
+    star_icon: Handle<StandardMaterial>,
+    shop_icon: Handle<StandardMaterial>,
+    coin_mesh: Handle<Mesh>,
+    popup_mesh: Handle<Mesh>,
+    double_popup_mesh: Handle<Mesh>,
+    popup_icon_mesh: Handle<Mesh>,
+}
+
+impl FromWorld for OverlayAssets {
+    fn from_world(world: &mut World) -> Self {
+        let items = item_icons(world.resource::<AssetServer>());
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut material = |color: Srgba| {
+            materials.add(StandardMaterial {
+                base_color: color.into(),
+                unlit: true,
+                double_sided: true,
+                cull_mode: None,
+                ..Default::default()
+            })
+        };
+        let coin_gain_material = material(Srgba::rgb_u8(0xFF, 0xD7, 0x00));
+        let coin_loss_material = material(Srgba::rgb_u8(0xD0, 0x20, 0x20));
+        let lucky_space_material = material(Srgba::rgb_u8(0x30, 0xC0, 0x40));
+        let star_material = material(Srgba::rgb_u8(0xFF, 0xF0, 0x60));
+        let star_steal_material = material(Srgba::rgb_u8(0x90, 0x30, 0xC0));
+        let shop_material = material(Srgba::rgb_u8(0x30, 0x80, 0xE0));
+        let mut icon = |item: ShopItem| {
+            materials.add(StandardMaterial {
+                base_color_texture: Some(items[item].clone()),
+                unlit: true,
+                alpha_mode: AlphaMode::Mask(0.5),
+                double_sided: true,
+                cull_mode: None,
+                ..Default::default()
+            })
+        };
+        let lucky_space_icon = icon(ShopItem::DoubleDice);
+        let star_icon = icon(ShopItem::GoldPipe);
+        let shop_icon = icon(ShopItem::ShopHopBox);
+
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        Self {
+            coin_gain_material,
+            coin_loss_material,
+            lucky_space_material,
+            star_material,
+            star_steal_material,
+            shop_material,
+            lucky_space_icon,
+            star_icon,
+            shop_icon,
+            coin_mesh: meshes.add(Circle::new(ICON_SIZE / 2.0)),
+            popup_mesh: meshes.add(Rectangle::from_length(ICON_SIZE)),
+            double_popup_mesh: meshes.add(Rectangle::new(ICON_SIZE * 1.5, ICON_SIZE)),
+            popup_icon_mesh: meshes.add(Rectangle::from_length(ICON_SIZE * 0.7)),
+        }
+    }
+}
+
+/// Whether `tile` shows anything in the overlays, so that they need rebuilding when it moves
+pub fn has_overlay(tile: &TileData) -> bool {
+    tile.popup.is_some() || tile.coins != 0 || tile.silver_star_spawnable
+}
+
+/// The overlays of the whole map, and the labels that go with their icons. The labels are UI
+/// nodes, so they have to be spawned on their own instead of as children of the overlays.
+pub fn tile_overlays(
+    map: &Grid<TileData>,
+    overlay_assets: &OverlayAssets,
+    materials: &mut Assets<StandardMaterial>,
+    meshes: &mut Assets<Mesh>,
+) -> (impl Bundle, Vec<impl Bundle>) {
+    let OverlayAssets {
+        coin_gain_material,
+        coin_loss_material,
+        lucky_space_material,
+        star_material,
+        star_steal_material,
+        shop_material,
+        lucky_space_icon,
+        star_icon,
+        shop_icon,
+        coin_mesh,
+        popup_mesh,
+        double_popup_mesh,
+        popup_icon_mesh,
+    } = overlay_assets;
+
+    let mut icons = vec![];
+    let mut popups = vec![];
+    let mut labels = vec![];
+    for ((y, x), tile) in map.indexed_iter() {
+        let base = Vec3::new(x as f32, tile.height.max_height() as f32 + 0.4, y as f32);
+        let mut offset = 0.0;
+        if let Some(popup) = tile.popup {
+            // Two stars show the star icon twice, side by side on a wider background
+            let (mesh, material, icon, icon_offsets): (_, _, _, &[f32]) = match popup {
+                PopupType::LuckySpace => {
+                    (popup_mesh, lucky_space_material, lucky_space_icon, &[0.0])
+                }
+                PopupType::Star1 => (popup_mesh, star_material, star_icon, &[0.0]),
+                PopupType::Star2 => (
+                    double_popup_mesh,
+                    star_material,
+                    star_icon,
+                    &DOUBLE_ICON_OFFSETS,
+                ),
+                PopupType::StarSteal => (popup_mesh, star_steal_material, star_icon, &[0.0]),
+                PopupType::Shop(_) => (popup_mesh, shop_material, shop_icon, &[0.0]),
+            };
+            let (icon_mesh, icon) = (popup_icon_mesh.clone(), icon.clone());
+            popups.push((
+                overlay_icon(mesh.clone(), material.clone(), base),
+                Children::spawn(SpawnIter(
+                    icon_offsets
+                        .iter()
+                        .map(move |&x| popup_item_icon(icon_mesh.clone(), icon.clone(), x)),
+                )),
+            ));
+            offset += ICON_SIZE * 1.25;
+        }
+        if tile.coins != 0 {
+            let (material, color) = if tile.coins > 0 {
+                (coin_gain_material, Srgba::rgb_u8(0xFF, 0xD7, 0x00))
+            } else {
+                (coin_loss_material, Srgba::rgb_u8(0xFF, 0x60, 0x60))
+            };
+            let position = base + Vec3::Y * offset;
+            icons.push(overlay_icon(coin_mesh.clone(), material.clone(), position));
+            labels.push(overlay_label(format!("{:+}", tile.coins), color, position));
+        }
+    }
+
+    // Kept faint so that it doesn't hide the atlas, or get confused with the selection
+    let silver_star_tint = mesh_top_tint(
+        map,
+        |tile| tile.silver_star_spawnable,
+        Srgba::rgba_u8(0xC0, 0xC8, 0xD8, 0x28),
+        materials,
+        meshes,
+    )
+    .map(|tint| (tint, Pickable::IGNORE));
+
+    let overlays = (
+        TileOverlaysMarker,
+        Transform::default(),
+        Visibility::default(),
+        Children::spawn((icons, popups, SpawnIter(silver_star_tint.into_iter()))),
+    );
+    (overlays, labels)
+}
+
+fn overlay_icon(
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+    position: Vec3,
+) -> impl Bundle {
+    (
+        OverlayIcon,
+        Mesh3d(mesh),
+        MeshMaterial3d(material),
+        Transform::from_translation(position),
+        NotShadowCaster,
+        NotShadowReceiver,
+        Pickable::IGNORE,
+    )
+}
+
+/// An item icon drawn just in front of a popup's background, at `x` across it
+fn popup_item_icon(mesh: Handle<Mesh>, material: Handle<StandardMaterial>, x: f32) -> impl Bundle {
+    (
+        Mesh3d(mesh),
+        MeshMaterial3d(material),
+        Transform::from_xyz(x, 0.0, 0.001),
+        NotShadowCaster,
+        NotShadowReceiver,
+        Pickable::IGNORE,
+    )
+}
+
+fn overlay_label(text: String, color: Srgba, icon_position: Vec3) -> impl Bundle {
+    (
+        TileOverlaysMarker,
+        OverlayLabel(icon_position),
+        Text::new(text),
+        TextFont::from_font_size(LABEL_FONT_SIZE),
+        TextColor(color.into()),
+        BackgroundColor(Srgba::new(0.0, 0.0, 0.0, 0.5).into()),
+        Node {
+            position_type: PositionType::Absolute,
+            padding: UiRect::horizontal(Val::Px(2.0)),
+            ..Default::default()
+        },
+        Visibility::Hidden,
+        Pickable::IGNORE,
+    )
+}
+
+/// The scale of an overlay icon at `distance` from the camera
+fn icon_scale(distance: f32) -> f32 {
+    (distance / REFERENCE_DISTANCE).clamp(MIN_SCALE, MAX_SCALE)
+}
+
+fn billboard_overlay_icons(
+    camera: Query<&Transform, (With<GizmoCamera>, Without<OverlayIcon>)>,
+    icons: Query<&mut Transform, With<OverlayIcon>>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    for mut icon in icons {
+        let distance = camera.translation.distance(icon.translation);
+        icon.rotation = camera.rotation;
+        icon.scale = Vec3::splat(icon_scale(distance));
+    }
+}
+
+/// Keeps each label just right of its icon's edge on screen, and hides the ones behind the camera
+fn place_overlay_labels(
+    camera: Query<(&Camera, &Transform), With<GizmoCamera>>,
+    labels: Query<(&OverlayLabel, &ComputedNode, &mut Node, &mut Visibility)>,
+) {
+    let Ok((camera, transform)) = camera.single() else {
+        return;
+    };
+    // Transforms aren't propagated yet, and the camera doesn't have a parent
+    let camera_transform = GlobalTransform::from(*transform);
+    for (label, computed, mut node, mut visibility) in labels {
+        let radius = ICON_SIZE / 2.0 * icon_scale(transform.translation.distance(label.0));
+        let edge = label.0 + transform.right() * radius;
+        let Ok(position) = camera.world_to_viewport(&camera_transform, edge) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        let height = computed.size().y * computed.inverse_scale_factor();
+        node.left = Val::Px(position.x + 2.0);
+        node.top = Val::Px(position.y - height / 2.0);
+        visibility.set_if_neq(Visibility::Inherited);
+    }
+}
+
+/// Draws every side of each built tile in the color of its connection, just above the tile. Each
+/// side is pulled in towards its own tile so that both sides of an edge can be told apart.
+fn draw_connection_edges(mut gizmos: Gizmos, settings: Res<EditorSettings>, file: Res<LoadedFile>) {
+    if !settings.show_connection_edges {
+        return;
+    }
+    const INSET: f32 = 0.45;
+    for ((y, x), tile) in file.file.data.indexed_iter() {
+        if tile.height == TileHeight::default() {
+            continue;
+        }
+        let center = Vec3::new(x as f32, tile.height.max_height() as f32 + 0.05, y as f32);
+        for &dir in Direction::ALL_CLOCKWISE {
+            let offset = dir.offset();
+            let normal = Vec3::new(offset.x as f32, 0.0, offset.y as f32);
+            let tangent = Vec3::new(normal.z, 0.0, normal.x);
+            let edge = center + normal * INSET;
+            let color = match tile.connections[dir] {
+                Connection::Unconditional(true) => Srgba::rgb_u8(0x50, 0xC0, 0x60),
+                Connection::Unconditional(false) => Srgba::rgb_u8(0xE0, 0x30, 0x30),
+                Connection::Conditional(_) => Srgba::rgb_u8(0xF0, 0xB0, 0x20),
+            };
+            gizmos.line(edge - tangent * INSET, edge + tangent * INSET, color);
+        }
+    }
+}
+
+fn spawn_scale_reference(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.spawn((
+        ScaleReference,
+        player_sprite(&assets, Vec3::ZERO),
+        Visibility::Hidden,
+        Pickable::IGNORE,
+    ));
+}
+
+fn place_scale_reference(
+    settings: Res<EditorSettings>,
+    file: Res<LoadedFile>,
+    camera: Query<&Transform, (With<GizmoCamera>, Without<ScaleReference>)>,
+    reference: Query<(&mut Transform, &mut Visibility), With<ScaleReference>>,
+) {
+    let camera = camera.single().ok();
+    for (mut transform, mut visibility) in reference {
+        let Some(range) = file
+            .selected_range
+            .filter(|_| settings.show_scale_reference)
+        else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Inherited;
+        let pos = range.start;
+        transform.translation = Vec3::new(
+            pos.x as f32,
+            file.file[pos].height.center_height() as f32 + 0.375,
+            pos.y as f32,
+        );
+        // Only turn around the vertical axis so that the sprite stays upright
+        if let Some(camera) = camera {
+            let to_camera = camera.translation - transform.translation;
+            transform.rotation = Quat::from_rotation_y(to_camera.x.atan2(to_camera.z));
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::path::Path;
+
+/// Reads a file the editor wrote into its data directory. A missing file is expected the first
+/// time, so only a file that fails to parse is warned about.
+pub fn load_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let data = fs::read(path).ok()?;
+    serde_json::from_slice(&data)
+        .inspect_err(|e| warn!("Failed to parse {}: {e}", path.display()))
+        .ok()
+}
+
+pub fn save_json(path: &Path, value: &impl Serialize) {
+    let result = serde_json::to_vec_pretty(value)
+        .map_err(|e| e.to_string())
+        .and_then(|data| fs::write(path, data).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        warn!("Failed to save {}: {e}", path.display());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::schema::{MapFile, PopupType, ShopItem, ShopNumber, TileHeight};
+use crate::settings::GridOrigin;
+use crate::validation::{degenerate_ramps, floating_tiles, overlapping_positions};
+use itertools::Itertools;
+use std::fmt::Write;
+use strum::VariantArray;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Html => "html",
+        }
+    }
+}
+
+/// A titled table of label and value rows in a [`BoardSummary`]
+struct Section {
+    heading: &'static str,
+    rows: Vec<(String, String)>,
+}
+
+/// Everything about a board that's worth listing in documentation, in the order it's written
+pub struct BoardSummary {
+    title: String,
+    sections: Vec<Section>,
+}
+
+impl BoardSummary {
+    /// Collects the summary of `file`, with positions counted from `origin` like in the UI
+    pub fn new(file: &MapFile, title: String, origin: GridOrigin) -> Self {
+        let map_size = file.map_size().unwrap_or_default();
+        let position = |pos| {
+            let pos = origin.flip(pos, map_size);
+            format!("({}, {})", pos.x, pos.y)
+        };
+        let count = |label: &str, value: usize| (label.to_string(), value.to_string());
+
+        let built = file
+            .data
+            .iter()
+            .filter(|tile| tile.height != TileHeight::default())
+            .collect_vec();
+        let tiles = Section {
+            heading: "Tiles",
+            rows: vec![
+                (
+                    "Dimensions".to_string(),
+                    format!("{}x{}", map_size.x, map_size.y),
+                ),
+                count("Built tiles", built.len()),
+                count("Ramps", built.iter().filter(|tile| tile.ramp()).count()),
+                count(
+                    "Tiles with coins",
+                    built.iter().filter(|tile| tile.coins != 0).count(),
+                ),
+                (
+                    "Total coins".to_string(),
+                    built.iter().map(|tile| tile.coins).sum::<i32>().to_string(),
+                ),
+                count(
+                    "Silver star spawns",
+                    built
+                        .iter()
+                        .filter(|tile| tile.silver_star_spawnable)
+                        .count(),
+                ),
+                count(
+                    "Atlas cells used",
+                    file.material_usage()
+                        .into_iter()
+                        .filter(|&count| count > 0)
+                        .count(),
+                ),
+            ],
+        };
+
+        let special_positions = Section {
+            heading: "Special positions",
+            rows: file
+                .special_positions()
+                .into_iter()
+                .map(|(name, pos)| (name, position(pos)))
+                .collect(),
+        };
+
+        let shops = Section {
+            heading: "Shops",
+            rows: ShopNumber::VARIANTS
+                .iter()
+                .map(|&shop| {
+                    let items = file.shops[shop]
+                        .iter()
+                        .map(|&item| item_name(item))
+                        .join(", ");
+                    let items = if items.is_empty() {
+                        "Empty".to_string()
+                    } else {
+                        items
+                    };
+                    (<&str>::from(shop).to_string(), items)
+                })
+                .collect(),
+        };
+
+        let popups = Section {
+            heading: "Popups",
+            rows: POPUP_TYPES
+                .iter()
+                .map(|&popup| {
+                    let tiles = built.iter().filter(|tile| tile.popup == Some(popup));
+                    count(popup_name(popup), tiles.count())
+                })
+                .collect(),
+        };
+
+        let issues = Section {
+            heading: "Issues",
+            rows: vec![
+                count("Floating tiles", floating_tiles(file).len()),
+                count("Overlapping positions", overlapping_positions(file).len()),
+                count("Degenerate ramps", degenerate_ramps(file).len()),
+            ],
+        };
+
+        Self {
+            title,
+            sections: vec![tiles, special_positions, shops, popups, issues],
+        }
+    }
+
+    /// Writes the report, with the image at `thumbnail` shown at the top if present
+    pub fn render(&self, format: ReportFormat, thumbnail: Option<&str>) -> String {
+        match format {
+            ReportFormat::Markdown => self.to_markdown(thumbnail),
+            ReportFormat::Html => self.to_html(thumbnail),
+        }
+    }
+
+    fn to_markdown(&self, thumbnail: Option<&str>) -> String {
+        let mut result = format!("# {}\n\n", self.title);
+        if let Some(thumbnail) = thumbnail {
+            // Angle brackets let the path contain spaces
+            writeln!(result, "![Top-down view](<{thumbnail}>)\n").unwrap();
+        }
+        for section in &self.sections {
+            writeln!(result, "## {}\n", section.heading).unwrap();
+            result.push_str("| | |\n|---|---|\n");
+            for (label, value) in &section.rows {
+                writeln!(result, "| {label} | {value} |").unwrap();
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    fn to_html(&self, thumbnail: Option<&str>) -> String {
+        let title = escape_html(&self.title);
+        let mut result = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+            </head>\n<body>\n<h1>{title}</h1>\n"
+        );
+        if let Some(thumbnail) = thumbnail {
+            writeln!(
+                result,
+                "<img src=\"{}\" alt=\"Top-down view\">",
+                escape_html(thumbnail)
+            )
+            .unwrap();
+        }
+        for section in &self.sections {
+            writeln!(result, "<h2>{}</h2>\n<table>", section.heading).unwrap();
+            for (label, value) in &section.rows {
+                writeln!(
+                    result,
+                    "<tr><th>{}</th><td>{}</td></tr>",
+                    escape_html(label),
+                    escape_html(value)
+                )
+                .unwrap();
+            }
+            result.push_str("</table>\n");
+        }
+        result.push_str("</body>\n</html>\n");
+        result
+    }
+}
+
+const POPUP_TYPES: [PopupType; 7] = [
+    PopupType::LuckySpace,
+    PopupType::Star1,
+    PopupType::Star2,
+    PopupType::StarSteal,
+    PopupType::Shop(ShopNumber::Shop1),
+    PopupType::Shop(ShopNumber::Shop2),
+    PopupType::Shop(ShopNumber::Shop3),
+];
+
+fn popup_name(popup: PopupType) -> &'static str {
+    match popup {
+        PopupType::LuckySpace => "Lucky space",
+        PopupType::Star1 => "Star",
+        PopupType::Star2 => "Two stars",
+        PopupType::StarSteal => "Star steal",
+        PopupType::Shop(shop) => shop.into(),
+    }
+}
+
+fn item_name(item: ShopItem) -> &'static str {
+    match item {
+        ShopItem::DoubleDice => "Double dice",
+        ShopItem::TripleDice => "Triple dice",
+        ShopItem::Pipe => "Pipe",
+        ShopItem::GoldPipe => "Gold pipe",
+        ShopItem::CustomDice => "Custom dice",
+        ShopItem::Tacticooler => "Tacticooler",
+        ShopItem::ShopHopBox => "Shop hop box",
+        ShopItem::InkJet => "Ink jet",
+        ShopItem::Key => "Key",
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoardSummary, ReportFormat};
+    use crate::schema::{MapFile, PopupType, ShopItem, ShopNumber, TileData, TileHeight};
+    use crate::settings::GridOrigin;
+    use grid::Grid;
+    use monostate::MustBeBool;
+
+    #[test]
+    fn test_markdown_report() {
+        let built = TileData {
+            height: TileHeight::Flat {
+                ramp: MustBeBool,
+                height: 1.0,
+            },
+            ..Default::default()
+        };
+        let mut file = MapFile {
+            data: Grid::init(2, 3, built),
+            ..Default::default()
+        };
+        file.data[(0, 0)].popup = Some(PopupType::Star1);
+        file.data[(0, 1)].popup = Some(PopupType::Shop(ShopNumber::Shop2));
+        file.data[(1, 2)] = TileData::default();
+        file.shops[ShopNumber::Shop1] = vec![ShopItem::DoubleDice, ShopItem::Key];
+
+        let report = BoardSummary::new(&file, "Test board".to_string(), GridOrigin::NorthWest)
+            .render(ReportFormat::Markdown, Some("test board.png"));
+        assert!(report.starts_with("# Test board\n\n![Top-down view](<test board.png>)\n"));
+        assert!(report.contains("| Dimensions | 3x2 |\n| Built tiles | 5 |\n"));
+        assert!(report.contains("| Shop #1 | Double dice, Key |\n| Shop #2 | Empty |\n"));
+        assert!(report.contains("| Star | 1 |\n"));
+        assert!(report.contains("| Shop #2 | 1 |\n"));
+        assert!(report.contains("| Lucky space | 0 |\n"));
+    }
+}
@@ -1,16 +1,17 @@
-use crate::sync::{Direction, MaterialLocation};
+use crate::sync::{Direction, MaterialLocation, TileTransform};
 use crate::tile_range::TileRange;
-use crate::utils::grid_as_vec_vec;
+use crate::utils::{grid_as_vec_vec, one_or_cube_map};
 use bevy::prelude::{EulerRot, FloatExt, Transform};
 use bevy_math::{Quat, Vec3};
 use enum_map::{Enum, EnumMap};
 use grid::{Grid, grid};
+use itertools::Itertools;
 use monostate::{MustBe, MustBeBool};
 use relative_path::RelativePathBuf;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_with::OneOrMany;
 use serde_with::serde_as;
-use std::ops::{AddAssign, Index, IndexMut, Sub};
+use std::ops::{Add, AddAssign, Index, IndexMut, Sub};
 use strum::{Display, IntoStaticStr, VariantArray};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -28,6 +29,11 @@ pub struct MapFile {
     pub shops: EnumMap<ShopNumber, Vec<ShopItem>>,
     #[serde(with = "grid_as_vec_vec")]
     pub data: Grid<TileData>,
+    /// Top-level keys the editor doesn't know about, such as ones added by newer versions of the
+    /// game, which are kept so they aren't lost when saving. This has to stay the last flattened
+    /// field, so the fields of [`Textures`] are taken out before it collects the rest.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Default for MapFile {
@@ -43,6 +49,7 @@ impl Default for MapFile {
             textures: Default::default(),
             shops: Default::default(),
             data: grid![[TileData::default()]],
+            extra: Default::default(),
         }
     }
 }
@@ -55,6 +62,60 @@ impl MapFile {
         ))
     }
 
+    /// Turns ramps whose ends are at the same height into flat tiles, returning how many there
+    /// were
+    pub fn flatten_level_ramps(&mut self) -> usize {
+        let mut count = 0;
+        for tile in self.data.iter_mut() {
+            if let TileHeight::Ramp { height, .. } = tile.height
+                && height.pos == height.neg
+            {
+                tile.height = tile.height.with_ramp_dir(None);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// How many built tiles use each atlas cell, on their top or on any of their walls
+    pub fn material_usage(&self) -> Vec<usize> {
+        let mut counts = vec![0; MpsMaterial::TEXTURES_COUNT];
+        for tile in self.data.iter() {
+            if tile.height == TileHeight::default() {
+                continue;
+            }
+            let walls = Direction::ALL_CLOCKWISE
+                .iter()
+                .flat_map(|&side| &tile.materials.wall_material[side]);
+            for material in walls
+                .chain([&tile.materials.material])
+                .map(|x| x.index())
+                .unique()
+            {
+                counts[material] += 1;
+            }
+        }
+        counts
+    }
+
+    /// The row-major index of a tile, as used by game scripts to refer to tiles
+    pub fn tile_index(&self, pos: MpsVec2) -> usize {
+        pos.y as usize * self.data.cols() + pos.x as usize
+    }
+
+    pub fn special_positions(&self) -> Vec<(String, MpsVec2)> {
+        let mut result = vec![("Starting tile".to_string(), self.starting_tile)];
+        result.extend(
+            self.shop_warp_tiles
+                .iter()
+                .enumerate()
+                .map(|(index, &pos)| (format!("Shop hop #{}", index + 1), pos)),
+        );
+        result.push(("Star warp tile".to_string(), self.star_warp_tile));
+        result.push(("Podium".to_string(), self.podium_position));
+        result
+    }
+
     pub fn adjust_height(&mut self, range: TileRange, change: f64) {
         for y in range.start.y..=range.end.y {
             let y = y as usize;
@@ -73,6 +134,21 @@ impl MapFile {
             }
         }
     }
+
+    /// Moves and turns the tiles in `range`. Connections leading out of the range are left as
+    /// they were, so that they still agree with the neighboring tiles.
+    pub fn transform_tiles(&mut self, range: TileRange, transform: TileTransform) {
+        let old = range.into_iter().map(|pos| self[pos].clone()).collect_vec();
+        for pos in range {
+            let mut tile = transform.tile(&old[range.index_of(transform.source(range, pos))]);
+            for &dir in Direction::ALL_CLOCKWISE {
+                if !range.contains(pos + dir.offset()) {
+                    tile.connections[dir] = old[range.index_of(pos)].connections[dir];
+                }
+            }
+            self[pos] = tile;
+        }
+    }
 }
 
 impl Index<MpsVec2> for MapFile {
@@ -110,6 +186,13 @@ impl MpsVec2 {
         }
     }
 
+    pub fn min(self, other: Self) -> Self {
+        Self {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+        }
+    }
+
     pub fn max(self, other: Self) -> Self {
         Self {
             x: self.x.max(other.x),
@@ -120,6 +203,32 @@ impl MpsVec2 {
     pub const fn as_array(self) -> [i32; 2] {
         [self.x, self.y]
     }
+
+    pub fn mirrored(self, map_size: Self, mirror_x: bool, mirror_y: bool) -> Self {
+        Self {
+            x: if mirror_x {
+                map_size.x - 1 - self.x
+            } else {
+                self.x
+            },
+            y: if mirror_y {
+                map_size.y - 1 - self.y
+            } else {
+                self.y
+            },
+        }
+    }
+}
+
+impl Add for MpsVec2 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+        }
+    }
 }
 
 impl AddAssign for MpsVec2 {
@@ -237,6 +346,10 @@ impl From<Quat> for MpsVec3 {
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Textures<T> {
+    #[serde(
+        deserialize_with = "one_or_cube_map::deserialize",
+        bound(deserialize = "T: Deserialize<'de> + Clone")
+    )]
     pub skybox: CubeMap<T>,
     pub atlas: T,
 }
@@ -309,17 +422,119 @@ pub struct TileData {
     pub materials: MaterialMap,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub popup: Option<PopupType>,
-    #[serde(default, skip_serializing_if = "is_no_coins")]
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub coins: i32,
     #[serde(default)]
     pub walk_over: bool,
     pub silver_star_spawnable: bool,
+    #[serde(default, skip_serializing_if = "WallVisibilityMap::is_automatic")]
+    pub wall_visibility: WallVisibilityMap,
 }
 
-fn is_no_coins(x: &i32) -> bool {
+fn is_zero(x: &i32) -> bool {
     *x == 0
 }
 
+/// Serializes a [`MapFile`] with `popup` and `coins` written even for tiles that have neither,
+/// for game versions that expect every tile to have them
+pub struct WithEmptyFields<'a>(pub &'a MapFile);
+
+impl Serialize for WithEmptyFields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // These mirror MapFile and TileData field for field, which the destructuring below keeps
+        // in sync
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct File<'a> {
+            starting_tile: &'a MpsVec2,
+            shop_warp_tiles: &'a [MpsVec2],
+            star_warp_tile: &'a MpsVec2,
+            podium_position: &'a MpsVec2,
+            results_anim_cam_poses: &'a [MpsVec3],
+            tutorial_star: &'a MpsTransform,
+            tutorial_shop: &'a MpsTransform,
+            #[serde(flatten)]
+            textures: &'a Textures<RelativePathBuf>,
+            shops: &'a EnumMap<ShopNumber, Vec<ShopItem>>,
+            data: Vec<Vec<Tile<'a>>>,
+            #[serde(flatten)]
+            extra: &'a serde_json::Map<String, serde_json::Value>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Tile<'a> {
+            #[serde(flatten)]
+            height: &'a TileHeight,
+            connections: &'a ConnectionMap,
+            #[serde(flatten)]
+            materials: &'a MaterialMap,
+            popup: &'a Option<PopupType>,
+            coins: &'a i32,
+            walk_over: &'a bool,
+            silver_star_spawnable: &'a bool,
+            #[serde(skip_serializing_if = "WallVisibilityMap::is_automatic")]
+            wall_visibility: WallVisibilityMap,
+        }
+
+        let MapFile {
+            starting_tile,
+            shop_warp_tiles,
+            star_warp_tile,
+            podium_position,
+            results_anim_cam_poses,
+            tutorial_star,
+            tutorial_shop,
+            textures,
+            shops,
+            data,
+            extra,
+        } = self.0;
+        let data = data
+            .iter_rows()
+            .map(|row| {
+                row.map(|tile| {
+                    let TileData {
+                        height,
+                        connections,
+                        materials,
+                        popup,
+                        coins,
+                        walk_over,
+                        silver_star_spawnable,
+                        wall_visibility,
+                    } = tile;
+                    Tile {
+                        height,
+                        connections,
+                        materials,
+                        popup,
+                        coins,
+                        walk_over,
+                        silver_star_spawnable,
+                        wall_visibility: *wall_visibility,
+                    }
+                })
+                .collect()
+            })
+            .collect();
+        File {
+            starting_tile,
+            shop_warp_tiles,
+            star_warp_tile,
+            podium_position,
+            results_anim_cam_poses,
+            tutorial_star,
+            tutorial_shop,
+            textures,
+            shops,
+            data,
+            extra,
+        }
+        .serialize(serializer)
+    }
+}
+
 impl TileData {
     pub fn ramp(&self) -> bool {
         matches!(self.height, TileHeight::Ramp { .. })
@@ -370,6 +585,28 @@ impl TileHeight {
         }
     }
 
+    /// The heights at the two ends of the tile's `side`, from its north or west end
+    pub fn edge_heights(self, side: Direction) -> [f64; 2] {
+        match self {
+            Self::Flat { height, .. } => [height; 2],
+            Self::Ramp { height, .. } => {
+                let across = match height.dir {
+                    TileRampDirection::Horizontal => {
+                        matches!(side, Direction::North | Direction::South)
+                    }
+                    TileRampDirection::Vertical => {
+                        matches!(side, Direction::West | Direction::East)
+                    }
+                };
+                match side {
+                    _ if across => [height.neg, height.pos],
+                    Direction::West | Direction::North => [height.neg; 2],
+                    Direction::East | Direction::South => [height.pos; 2],
+                }
+            }
+        }
+    }
+
     pub fn pos_height(self) -> f64 {
         match self {
             Self::Flat { height, .. } => height,
@@ -418,6 +655,27 @@ impl TileHeight {
         }
     }
 
+    /// A ramp along the other axis, rising towards the same end. Flat tiles are left as they are.
+    pub fn with_turned_ramp(self) -> Self {
+        match self.ramp_dir() {
+            Some(TileRampDirection::Horizontal) => {
+                self.with_ramp_dir(Some(TileRampDirection::Vertical))
+            }
+            Some(TileRampDirection::Vertical) => {
+                self.with_ramp_dir(Some(TileRampDirection::Horizontal))
+            }
+            None => self,
+        }
+    }
+
+    pub fn mirrored(self, mirror_x: bool, mirror_y: bool) -> Self {
+        match self.ramp_dir() {
+            Some(TileRampDirection::Horizontal) if mirror_x => self.with_flipped_heights(),
+            Some(TileRampDirection::Vertical) if mirror_y => self.with_flipped_heights(),
+            _ => self,
+        }
+    }
+
     pub fn equals_flat(self, other: f64) -> bool {
         self == Self::Flat {
             ramp: MustBeBool,
@@ -512,6 +770,69 @@ macro_rules! impl_index_direction {
 
 impl_index_direction!(ConnectionMap => Connection);
 
+/// Overrides for which of a tile's walls get meshed. These only affect the editor's geometry and
+/// aren't read by the game.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WallVisibilityMap {
+    #[serde(
+        rename = "n",
+        default,
+        skip_serializing_if = "WallVisibility::is_automatic"
+    )]
+    pub north: WallVisibility,
+    #[serde(
+        rename = "e",
+        default,
+        skip_serializing_if = "WallVisibility::is_automatic"
+    )]
+    pub east: WallVisibility,
+    #[serde(
+        rename = "s",
+        default,
+        skip_serializing_if = "WallVisibility::is_automatic"
+    )]
+    pub south: WallVisibility,
+    #[serde(
+        rename = "w",
+        default,
+        skip_serializing_if = "WallVisibility::is_automatic"
+    )]
+    pub west: WallVisibility,
+}
+
+impl WallVisibilityMap {
+    pub fn is_automatic(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl_index_direction!(WallVisibilityMap => WallVisibility);
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WallVisibility {
+    /// Shown when the neighboring tile is lower
+    #[default]
+    Automatic,
+    Hidden,
+    Shown,
+}
+
+impl WallVisibility {
+    pub fn is_automatic(&self) -> bool {
+        *self == Self::Automatic
+    }
+
+    /// Whether the wall is meshed, given whether it would be meshed automatically
+    pub fn resolve(self, automatic: bool) -> bool {
+        match self {
+            Self::Automatic => automatic,
+            Self::Hidden => false,
+            Self::Shown => true,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Connection {
@@ -617,6 +938,10 @@ impl MpsMaterial {
         }
     }
 
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
     /// Return value: `(u1, v1, u2, v2)`
     pub const fn to_uv_coords(self) -> (f32, f32, f32, f32) {
         let u = (self.0 % ATLAS_SIZE.0) as f32 / ATLAS_SIZE.0 as f32;
@@ -628,6 +953,16 @@ impl MpsMaterial {
             v + Self::V_INCREMENT - 0.001,
         )
     }
+
+    /// The material whose cell in the atlas contains `(u, v)`, the inverse of [`Self::to_uv_coords`]
+    pub fn from_uv_coords(u: f32, v: f32) -> Option<Self> {
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+        let column = (u * ATLAS_SIZE.0 as f32) as AtlasCoordValue;
+        let row = ATLAS_SIZE.1 - 1 - (v * ATLAS_SIZE.1 as f32) as AtlasCoordValue;
+        Some(Self(row * ATLAS_SIZE.0 + column))
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -643,3 +978,205 @@ pub enum PopupType {
     #[serde(untagged)]
     Shop(ShopNumber),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Connection, MapFile, MpsMaterial, MpsVec2, PopupType, TileData, TileHeight};
+    use super::{TileRamp, TileRampDirection, WallVisibility, WithEmptyFields};
+    use crate::sync::{Direction, TileTransform};
+    use crate::tile_range::TileRange;
+    use grid::Grid;
+    use monostate::MustBeBool;
+
+    const CLOSED: Connection = Connection::Unconditional(false);
+    const OPEN: Connection = Connection::Unconditional(true);
+
+    /// A 2x2 map with a closed connection between its two northern tiles, a wall material on the
+    /// north-west tile's north side, and a ramp rising towards the east on the north-west tile
+    fn hand_built_map() -> (MapFile, TileRange) {
+        let mut file = MapFile {
+            data: Grid::init(2, 2, TileData::default()),
+            ..Default::default()
+        };
+        let north_west = &mut file.data[(0, 0)];
+        north_west.connections.east = CLOSED;
+        north_west.materials.wall_material.north = vec![MpsMaterial::from_index(5).unwrap()];
+        north_west.wall_visibility.north = WallVisibility::Shown;
+        north_west.height = TileHeight::Ramp {
+            ramp: MustBeBool,
+            height: TileRamp {
+                dir: TileRampDirection::Horizontal,
+                pos: 2.0,
+                neg: 1.0,
+            },
+        };
+        file.data[(0, 1)].connections.west = CLOSED;
+        let range = TileRange {
+            start: MpsVec2::ZERO,
+            end: MpsVec2::ONE,
+        };
+        (file, range)
+    }
+
+    #[test]
+    fn test_rotate_clockwise() {
+        let (mut file, range) = hand_built_map();
+        file.transform_tiles(range, TileTransform::RotateClockwise);
+
+        // The north-west tile is now the north-east tile, turned to face east
+        let north_east = &file.data[(0, 1)];
+        assert_eq!(
+            north_east.materials.wall_material.east,
+            [MpsMaterial::from_index(5).unwrap()]
+        );
+        assert_eq!(
+            north_east.materials.wall_material.north,
+            [MpsMaterial::default()]
+        );
+        assert_eq!(north_east.wall_visibility.east, WallVisibility::Shown);
+        assert_eq!(
+            north_east.height,
+            TileHeight::Ramp {
+                ramp: MustBeBool,
+                height: TileRamp {
+                    dir: TileRampDirection::Vertical,
+                    pos: 2.0,
+                    neg: 1.0,
+                },
+            }
+        );
+
+        // The closed connection now runs down the east side, and still pairs up
+        assert_eq!(north_east.connections.south, CLOSED);
+        assert_eq!(file.data[(1, 1)].connections.north, CLOSED);
+        assert_eq!(file.data[(0, 0)].connections.east, OPEN);
+        assert_eq!(file.data[(0, 1)].connections.west, OPEN);
+        // Connections leading out of the range are left alone
+        assert_eq!(north_east.connections.north, OPEN);
+        assert_eq!(north_east.connections.east, OPEN);
+    }
+
+    #[test]
+    fn test_rotate_counter_clockwise() {
+        let (mut file, range) = hand_built_map();
+        file.transform_tiles(range, TileTransform::RotateCounterClockwise);
+
+        // The north-west tile is now the south-west tile, turned to face west
+        let south_west = &file.data[(1, 0)];
+        assert_eq!(
+            south_west.materials.wall_material.west,
+            [MpsMaterial::from_index(5).unwrap()]
+        );
+        assert_eq!(
+            south_west.height,
+            TileHeight::Ramp {
+                ramp: MustBeBool,
+                height: TileRamp {
+                    dir: TileRampDirection::Vertical,
+                    pos: 1.0,
+                    neg: 2.0,
+                },
+            }
+        );
+        assert_eq!(south_west.connections.north, CLOSED);
+        assert_eq!(file.data[(0, 0)].connections.south, CLOSED);
+    }
+
+    #[test]
+    fn test_transforms_reverse() {
+        let (original, range) = hand_built_map();
+        for transform in [
+            TileTransform::MirrorX,
+            TileTransform::MirrorY,
+            TileTransform::RotateClockwise,
+            TileTransform::RotateCounterClockwise,
+        ] {
+            let mut file = hand_built_map().0;
+            file.transform_tiles(range, transform);
+            file.transform_tiles(range, transform.reverse());
+            assert!(file.data.iter().eq(original.data.iter()), "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn test_flatten_level_ramps() {
+        let ramp = |pos, neg| TileHeight::Ramp {
+            ramp: MustBeBool,
+            height: TileRamp {
+                dir: TileRampDirection::Vertical,
+                pos,
+                neg,
+            },
+        };
+        let mut file = MapFile {
+            data: Grid::init(1, 2, TileData::default()),
+            ..Default::default()
+        };
+        file.data[(0, 0)].height = ramp(2.0, 2.0);
+        file.data[(0, 1)].height = ramp(2.0, 1.0);
+
+        assert_eq!(file.flatten_level_ramps(), 1);
+        assert_eq!(
+            file.data[(0, 0)].height,
+            TileHeight::Flat {
+                ramp: MustBeBool,
+                height: 2.0,
+            }
+        );
+        assert_eq!(file.data[(0, 1)].height, ramp(2.0, 1.0));
+        assert_eq!(file.flatten_level_ramps(), 0);
+    }
+
+    #[test]
+    fn test_edge_heights() {
+        let ramp = TileHeight::Ramp {
+            ramp: MustBeBool,
+            height: TileRamp {
+                dir: TileRampDirection::Vertical,
+                pos: 2.0,
+                neg: 1.0,
+            },
+        };
+        assert_eq!(ramp.edge_heights(Direction::North), [1.0, 1.0]);
+        assert_eq!(ramp.edge_heights(Direction::South), [2.0, 2.0]);
+        assert_eq!(ramp.edge_heights(Direction::West), [1.0, 2.0]);
+        assert_eq!(ramp.edge_heights(Direction::East), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_single_skybox() {
+        let mut value = serde_json::to_value(MapFile::default()).unwrap();
+        value["skybox"] = "sky.png".into();
+        let file: MapFile = serde_json::from_value(value).unwrap();
+        assert!(
+            file.textures
+                .skybox
+                .iter()
+                .all(|face| face.as_str() == "sky.png")
+        );
+
+        let value = serde_json::to_value(&file).unwrap();
+        assert_eq!(
+            value["skybox"],
+            serde_json::to_value(["sky.png"; 6]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_with_empty_fields() {
+        let mut file = MapFile {
+            data: Grid::init(1, 2, TileData::default()),
+            ..Default::default()
+        };
+        file.data[(0, 1)].popup = Some(PopupType::StarSteal);
+        file.data[(0, 1)].coins = 3;
+
+        let plain = serde_json::to_value(&file).unwrap();
+        let mut with_empty = serde_json::to_value(WithEmptyFields(&file)).unwrap();
+        let empty_tile = with_empty["data"][0][0].as_object_mut().unwrap();
+        assert_eq!(empty_tile.remove("popup"), Some(serde_json::Value::Null));
+        assert_eq!(empty_tile.remove("coins"), Some(0.into()));
+        // Apart from those, both are written the same
+        assert_eq!(with_empty, plain);
+    }
+}
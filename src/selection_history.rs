@@ -0,0 +1,83 @@
+use crate::load_file::{FileLoaded, LoadedFile};
+use crate::shortcut_pressed;
+use crate::sync::{EditObject, SelectForEditing};
+use crate::tile_range::TileRange;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+const MAX_SELECTIONS: usize = 32;
+
+pub struct SelectionHistoryPlugin;
+
+impl Plugin for SelectionHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectionHistory>()
+            .add_observer(on_file_loaded)
+            .add_systems(
+                Update,
+                (navigate_selection_history, record_selection).chain(),
+            );
+    }
+}
+
+/// Previously selected tile ranges, navigated with Alt+Left and Alt+Right. Unlike undo, moving
+/// through these only changes the selection.
+#[derive(Resource, Default)]
+pub struct SelectionHistory {
+    items: VecDeque<TileRange>,
+    index: usize,
+}
+
+impl SelectionHistory {
+    fn current(&self) -> Option<TileRange> {
+        self.items.get(self.index).copied()
+    }
+
+    fn push(&mut self, range: TileRange) {
+        self.items.truncate(self.index + 1);
+        self.items.push_back(range);
+        if self.items.len() > MAX_SELECTIONS {
+            self.items.pop_front();
+        }
+        self.index = self.items.len() - 1;
+    }
+}
+
+fn on_file_loaded(_: On<FileLoaded>, mut history: ResMut<SelectionHistory>) {
+    *history = SelectionHistory::default();
+}
+
+fn navigate_selection_history(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+    mut history: ResMut<SelectionHistory>,
+) {
+    if shortcut_pressed!(keys, Alt + ArrowLeft) && history.index > 0 {
+        history.index -= 1;
+    } else if shortcut_pressed!(keys, Alt + ArrowRight) && history.index + 1 < history.items.len() {
+        history.index += 1;
+    } else {
+        return;
+    }
+    let Some(range) = history.current() else {
+        return;
+    };
+    commands.trigger(SelectForEditing {
+        object: EditObject::Tile(range.start),
+        exclusive: true,
+    });
+    if range.end != range.start {
+        commands.trigger(SelectForEditing {
+            object: EditObject::Tile(range.end),
+            exclusive: false,
+        });
+    }
+}
+
+fn record_selection(file: Res<LoadedFile>, mut history: ResMut<SelectionHistory>) {
+    if let Some(range) = file.selected_range
+        && history.current() != Some(range)
+    {
+        history.push(range);
+    }
+}
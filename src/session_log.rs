@@ -0,0 +1,170 @@
+use crate::load_file::{LoadedFile, file_error, load_map_texture};
+use crate::schema::MpsVec2;
+use crate::sync::{Direction, MapEdit, MapEdited};
+use bevy::image::ImageSampler;
+use bevy::prelude::*;
+use bevy_file_dialog::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct SessionLogPlugin;
+
+impl Plugin for SessionLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SessionLog>()
+            .add_observer(on_map_edited)
+            .add_systems(Update, (session_log_saved, session_log_loaded));
+    }
+}
+
+pub struct SessionLogDialog;
+
+/// Every edit applied while recording, for reproducing a map's state from a starting map
+#[derive(Resource, Default)]
+pub struct SessionLog {
+    recording: bool,
+    entries: Vec<LogEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogEntry {
+    /// Seconds since the Unix epoch
+    time: f64,
+    edit: MapEdit,
+}
+
+impl SessionLog {
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Starting a new recording discards the previous one
+    pub fn set_recording(&mut self, recording: bool) {
+        if recording && !self.recording {
+            self.entries.clear();
+        }
+        self.recording = recording;
+    }
+
+    /// Writes the log as JSON lines, one edit per line
+    pub fn to_json_lines(&self) -> Vec<u8> {
+        let mut result = vec![];
+        for entry in &self.entries {
+            if let Err(err) = serde_json::to_writer(&mut result, entry) {
+                warn!("Failed to write session log entry: {err}");
+                continue;
+            }
+            result.push(b'\n');
+        }
+        result
+    }
+}
+
+pub fn save_session_log(commands: &mut Commands, log: &SessionLog) {
+    commands
+        .dialog()
+        .set_title("Save session log")
+        .add_filter("Session logs", &["jsonl"])
+        .save_file(log.to_json_lines(), SessionLogDialog);
+}
+
+pub fn replay_session_log(commands: &mut Commands) {
+    commands
+        .dialog()
+        .set_title("Replay session log")
+        .add_filter("Session logs", &["jsonl"])
+        .load_file(SessionLogDialog);
+}
+
+fn on_map_edited(on: On<MapEdited>, mut log: ResMut<SessionLog>) {
+    if !log.recording {
+        return;
+    }
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |x| x.as_secs_f64());
+    log.entries.push(LogEntry {
+        time,
+        edit: on.0.clone(),
+    });
+}
+
+fn session_log_saved(mut saved_reader: MessageReader<DialogFileSaved<SessionLogDialog>>) {
+    for saved in saved_reader.read() {
+        if let Err(err) = &saved.result {
+            file_error("save", err);
+        }
+    }
+}
+
+/// Replays each edit through [`LoadedFile::edit_map`], so the whole log can be undone at once
+fn session_log_loaded(
+    mut loaded_reader: MessageReader<DialogFileLoaded<SessionLogDialog>>,
+    mut commands: Commands,
+    mut file: ResMut<LoadedFile>,
+    assets: Res<AssetServer>,
+) {
+    for loaded in loaded_reader.read() {
+        let entries: Result<Vec<LogEntry>, _> = loaded
+            .contents
+            .split(|&x| x == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .map(serde_json::from_slice)
+            .collect();
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(err) => {
+                file_error("replay", &err);
+                continue;
+            }
+        };
+        if let Err(err) = check_entries(&file, &entries) {
+            file_error("replay", &err);
+            continue;
+        }
+        for entry in entries {
+            let mut edit = entry.edit;
+            match &mut edit {
+                MapEdit::Skybox(_, texture) => {
+                    *texture =
+                        load_map_texture(&assets, texture.path.clone(), ImageSampler::Default);
+                }
+                MapEdit::Atlas(texture) => {
+                    *texture =
+                        load_map_texture(&assets, texture.path.clone(), ImageSampler::nearest());
+                }
+                _ => {}
+            }
+            file.edit_map(&mut commands, edit);
+        }
+    }
+}
+
+/// Finds the first entry that wouldn't fit the map as it'll be by the time it's replayed, so a
+/// log recorded against a different map is rejected before any of it is applied
+fn check_entries(file: &LoadedFile, entries: &[LogEntry]) -> Result<(), String> {
+    let mut size = file.file.map_size().unwrap_or_default();
+    for (index, entry) in entries.iter().enumerate() {
+        if !entry.edit.fits_map(size) {
+            return Err(format!(
+                "entry {} ({}) doesn't fit a {}x{} map",
+                index + 1,
+                entry.edit.description(),
+                size.x,
+                size.y
+            ));
+        }
+        match entry.edit {
+            MapEdit::ExpandMap(Direction::West | Direction::East, _) => size.x += 1,
+            MapEdit::ExpandMap(Direction::North | Direction::South, _) => size.y += 1,
+            MapEdit::ShrinkMap(Direction::West | Direction::East) if size.x > 1 => size.x -= 1,
+            MapEdit::ShrinkMap(Direction::North | Direction::South) if size.y > 1 => size.y -= 1,
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use crate::Directories;
+use crate::persistence::{load_json, save_json};
+use crate::schema::MpsVec2;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use strum::{IntoStaticStr, VariantArray};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = app
+            .world()
+            .get_resource::<Directories>()
+            .and_then(|dirs| load_json::<EditorSettings>(&dirs.data.join(SETTINGS_FILE)))
+            .unwrap_or_default();
+        app.insert_resource(settings)
+            .add_systems(Last, save_settings);
+    }
+}
+
+/// Editor preferences, saved in the data directory rather than in the map
+#[derive(Resource, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EditorSettings {
+    pub mesh: MeshSettings,
+    /// Whether to show a player-sized sprite on the selected tile
+    pub show_scale_reference: bool,
+    /// Whether to mark both sides of locked connections in the viewport, to find locks that
+    /// aren't paired up
+    pub show_locked_connections: bool,
+    /// Whether to outline each tile in the colors of its connections: open, impassible or locked
+    pub show_connection_edges: bool,
+    pub grid_origin: GridOrigin,
+    pub load: LoadSettings,
+    pub save: SaveSettings,
+    pub autosave: AutosaveSettings,
+    pub gizmo: GizmoSettings,
+    pub export: ExportSettings,
+    pub budget: BudgetSettings,
+    pub history: HistorySettings,
+    pub background: BackgroundSettings,
+}
+
+/// The corner of the map that tile coordinates count from in the UI. Maps are always stored
+/// counting from the north-west corner, so this only changes what's shown and typed in.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, IntoStaticStr, VariantArray,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum GridOrigin {
+    #[default]
+    #[strum(serialize = "North-west")]
+    NorthWest,
+    #[strum(serialize = "North-east")]
+    NorthEast,
+    #[strum(serialize = "South-west")]
+    SouthWest,
+    #[strum(serialize = "South-east")]
+    SouthEast,
+}
+
+impl GridOrigin {
+    /// Converts a stored position to the one shown in the UI. Flipping twice gets the original
+    /// position back, so this also converts typed-in positions back.
+    pub fn flip(self, pos: MpsVec2, map_size: MpsVec2) -> MpsVec2 {
+        pos.mirrored(
+            map_size,
+            matches!(self, Self::NorthEast | Self::SouthEast),
+            matches!(self, Self::SouthWest | Self::SouthEast),
+        )
+    }
+}
+
+/// Settings that affect the generated map mesh. Changing any of these remeshes the map.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MeshSettings {
+    /// Skip the walls between side-by-side ramps with the same slope, so that wide ramps look
+    /// like a single surface
+    pub merge_ramps: bool,
+    /// Mesh rectangles of flat tiles with the same height and material as single quads, which
+    /// makes large maps much cheaper to draw
+    pub merge_flat_tops: bool,
+    /// Whether to generate the trims along the edges of the map. Turning them off is also faster
+    /// to mesh.
+    pub show_trims: bool,
+    /// sRGB color of the blocks drawn along impassible connections
+    pub block_color: [u8; 3],
+    /// sRGB color of the trims along the edges of the map
+    pub trim_color: [u8; 3],
+    /// Whether to place gate models on locked connections. The connections are unaffected.
+    pub show_key_gates: bool,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            merge_ramps: true,
+            merge_flat_tops: false,
+            show_trims: true,
+            block_color: [0x11; 3],
+            trim_color: [0xAA; 3],
+            show_key_gates: true,
+        }
+    }
+}
+
+/// Settings that affect how map files are read
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LoadSettings {
+    /// Turn ramps whose ends are at the same height into flat tiles when opening a map, since
+    /// they're meshed with walls and trims that have no area
+    pub flatten_level_ramps: bool,
+}
+
+impl Default for LoadSettings {
+    fn default() -> Self {
+        Self {
+            flatten_level_ramps: true,
+        }
+    }
+}
+
+/// Settings that affect how map files are written
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SaveSettings {
+    /// Write `popup` and `coins` on every tile, even when they're empty, instead of leaving them
+    /// out
+    pub write_empty_fields: bool,
+    /// Show a summary of what changed since the last save before saving
+    pub show_summary: bool,
+    /// Add `.json` to paths picked with "Save as" that don't already end with it, instead of
+    /// only warning about them
+    pub append_json_extension: bool,
+}
+
+impl Default for SaveSettings {
+    fn default() -> Self {
+        Self {
+            write_empty_fields: false,
+            show_summary: true,
+            append_json_extension: true,
+        }
+    }
+}
+
+/// Periodic snapshots of the open file, kept in the data directory
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AutosaveSettings {
+    pub enabled: bool,
+    /// Minutes between snapshots. A snapshot is only written if the file was edited since the
+    /// last one.
+    pub interval_minutes: u32,
+    /// How many snapshots to keep before the oldest ones are deleted
+    pub keep: u32,
+}
+
+impl Default for AutosaveSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_minutes: 5,
+            keep: 10,
+        }
+    }
+}
+
+/// Settings for dragging objects around with the viewport gizmos
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GizmoSettings {
+    /// Snap the height of dragged tiles to the height of a neighboring tile when it's close, so
+    /// that the surfaces line up exactly
+    pub snap_to_neighbors: bool,
+    /// How close a neighboring height has to be to snap to it
+    pub snap_threshold: f64,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> Self {
+        Self {
+            snap_to_neighbors: false,
+            snap_threshold: 0.25,
+        }
+    }
+}
+
+/// What's drawn behind the map in the viewport
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BackgroundSettings {
+    /// Draw the map's skybox. Without it, the background is filled with `color`.
+    pub show_skybox: bool,
+    /// sRGB color of the background when the skybox is hidden
+    pub color: [u8; 3],
+}
+
+impl Default for BackgroundSettings {
+    fn default() -> Self {
+        Self {
+            show_skybox: true,
+            color: [0x2B; 3],
+        }
+    }
+}
+
+/// Settings for exporting the map mesh to other tools
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ExportSettings {
+    /// Reverse the winding of every exported triangle, which also flips its normal, for tools
+    /// that expect clockwise front faces
+    pub flip_winding: bool,
+    /// Export the plane under the map along with it
+    pub include_floor: bool,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            flip_winding: false,
+            include_floor: true,
+        }
+    }
+}
+
+/// How big the map mesh can get before the editor warns about it. Past these, the game and the
+/// editor start to struggle.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct BudgetSettings {
+    pub max_vertices: usize,
+    pub max_entities: usize,
+}
+
+impl Default for BudgetSettings {
+    fn default() -> Self {
+        Self {
+            max_vertices: 250_000,
+            max_entities: 5_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HistorySettings {
+    /// Height edits of the same tiles made within this many milliseconds of each other, such as
+    /// while scrolling or nudging the gizmo, are undone together. Zero turns this off.
+    pub coalesce_window_ms: u32,
+}
+
+impl HistorySettings {
+    pub fn coalesce_window(&self) -> Duration {
+        Duration::from_millis(self.coalesce_window_ms.into())
+    }
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            coalesce_window_ms: 750,
+        }
+    }
+}
+
+fn save_settings(settings: Res<EditorSettings>, dirs: Option<Res<Directories>>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    let Some(dirs) = dirs else {
+        return;
+    };
+    save_json(&dirs.data.join(SETTINGS_FILE), &*settings);
+}
@@ -0,0 +1,243 @@
+use crate::export::ExportTasks;
+use crate::load_file::{LoadedFile, file_error, get_snapshot_data, open_file_contents};
+use crate::settings::EditorSettings;
+use crate::ui::UiState;
+use bevy::prelude::*;
+use bevy_file_dialog::prelude::*;
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use serde_json::{Map, Serializer, Value, json};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Tiles are written to `<name>.tiles/<x>_<y>.json` next to the `<name>.json` metadata file, so
+/// that maps exported to the same directory don't share their tiles
+const TILES_DIR_EXTENSION: &str = "tiles";
+/// Replaces `data` in the metadata file, holding the size of the map
+const TILE_FILES_KEY: &str = "tileFiles";
+
+/// Reads and writes maps split into a metadata file and a file for each tile, so that changes
+/// show up tile by tile in version control
+pub struct SplitMapPlugin;
+
+impl Plugin for SplitMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (split_map_saved, split_map_loaded));
+    }
+}
+
+pub struct SplitMapExportDialog;
+
+pub struct SplitMapImportDialog;
+
+pub fn export_split_map(commands: &mut Commands) {
+    commands
+        .dialog()
+        .set_title("Export tile files")
+        .add_filter("MSP map metadata", &["json"])
+        .save_file(vec![], SplitMapExportDialog);
+}
+
+pub fn import_split_map(commands: &mut Commands) {
+    commands
+        .dialog()
+        .set_title("Import tile files")
+        .add_filter("MSP map metadata", &["json"])
+        .load_file(SplitMapImportDialog);
+}
+
+fn split_map_saved(
+    mut saved_reader: MessageReader<DialogFileSaved<SplitMapExportDialog>>,
+    file: Res<LoadedFile>,
+    settings: Res<EditorSettings>,
+    mut tasks: ResMut<ExportTasks>,
+) {
+    for saved in saved_reader.read() {
+        if let Err(err) = &saved.result {
+            file_error("export", err);
+            continue;
+        }
+        let dir = saved
+            .path
+            .parent()
+            .expect("Saved file should have a parent directory");
+        let split = get_snapshot_data(&file, dir, &settings.save)
+            .map_err(|err| err.to_string())
+            .and_then(|data| split_map(&data).map_err(|err| err.to_string()));
+        let (metadata, tiles) = match split {
+            Ok(split) => split,
+            Err(err) => {
+                file_error("export", &err);
+                continue;
+            }
+        };
+        tasks.spawn(saved.path.clone(), move |path| {
+            write_split_map(path, &metadata, &tiles).map_err(|err| err.to_string())
+        });
+    }
+}
+
+fn split_map_loaded(
+    mut loaded_reader: MessageReader<DialogFileLoaded<SplitMapImportDialog>>,
+    mut ui_state: ResMut<UiState>,
+    assets: Res<AssetServer>,
+    settings: Res<EditorSettings>,
+) {
+    for loaded in loaded_reader.read() {
+        match join_map(&loaded.contents, &loaded.path) {
+            Ok(data) => {
+                let path = loaded.path.clone();
+                open_file_contents(&mut ui_state, data, path, assets.clone(), settings.load);
+            }
+            Err(err) => file_error("import", &err),
+        }
+    }
+}
+
+/// Splits a serialized map into its metadata and each of its tiles, along with the file name of
+/// each tile
+fn split_map(data: &[u8]) -> serde_json::Result<(Vec<u8>, Vec<(String, Vec<u8>)>)> {
+    let mut map: Map<String, Value> = serde_json::from_slice(data)?;
+    let rows = match map.remove("data") {
+        Some(Value::Array(rows)) => rows,
+        _ => vec![],
+    };
+    let height = rows.len();
+    let width = rows.first().and_then(Value::as_array).map_or(0, Vec::len);
+
+    let mut tiles = Vec::with_capacity(width * height);
+    for (y, row) in rows.into_iter().enumerate() {
+        let Value::Array(row) = row else {
+            continue;
+        };
+        for (x, tile) in row.into_iter().enumerate() {
+            tiles.push((tile_file_name(x, y), to_pretty_json(&tile)?));
+        }
+    }
+    map.insert(
+        TILE_FILES_KEY.to_string(),
+        json!({ "width": width, "height": height }),
+    );
+    Ok((to_pretty_json(&map)?, tiles))
+}
+
+/// Reassembles a map written by [`split_map`] into the single file format
+fn join_map(metadata: &[u8], path: &Path) -> io::Result<Vec<u8>> {
+    let mut map: Map<String, Value> = serde_json::from_slice(metadata)?;
+    let size = map
+        .remove(TILE_FILES_KEY)
+        .ok_or_else(|| io::Error::other(format!("Missing {TILE_FILES_KEY}, not a tile export")))?;
+    let dimension = |name: &str| {
+        size.get(name)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| io::Error::other(format!("Missing {TILE_FILES_KEY}.{name}")))
+    };
+    let (width, height) = (dimension("width")?, dimension("height")?);
+
+    let tiles_dir = path.with_extension(TILES_DIR_EXTENSION);
+    let rows = (0..height as usize)
+        .map(|y| {
+            (0..width as usize)
+                .map(|x| {
+                    let tile_path = tiles_dir.join(tile_file_name(x, y));
+                    let tile =
+                        fs::read(&tile_path).and_then(|data| Ok(serde_json::from_slice(&data)?));
+                    tile.map_err(|err| io::Error::other(format!("{}: {err}", tile_path.display())))
+                })
+                .collect::<io::Result<Vec<Value>>>()
+                .map(Value::Array)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    map.insert("data".to_string(), Value::Array(rows));
+    Ok(serde_json::to_vec(&map)?)
+}
+
+fn write_split_map(path: &Path, metadata: &[u8], tiles: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let tiles_dir = path.with_extension(TILES_DIR_EXTENSION);
+    fs::create_dir_all(&tiles_dir)?;
+    for (name, data) in tiles {
+        fs::write(tiles_dir.join(name), data)?;
+    }
+
+    // Tiles from a bigger version of the map would otherwise stay around in the repository
+    let written = tiles
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<HashSet<_>>();
+    for entry in fs::read_dir(&tiles_dir)? {
+        let entry = entry?;
+        if let Some(name) = entry.file_name().to_str()
+            && is_tile_file_name(name)
+            && !written.contains(name)
+        {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    fs::write(path, metadata)
+}
+
+fn tile_file_name(x: usize, y: usize) -> String {
+    format!("{x}_{y}.json")
+}
+
+fn is_tile_file_name(name: &str) -> bool {
+    name.strip_suffix(".json")
+        .and_then(|name| name.split_once('_'))
+        .is_some_and(|(x, y)| x.parse::<usize>().is_ok() && y.parse::<usize>().is_ok())
+}
+
+fn to_pretty_json(value: &impl Serialize) -> serde_json::Result<Vec<u8>> {
+    let mut serializer =
+        Serializer::with_formatter(Vec::new(), PrettyFormatter::with_indent("\t".as_bytes()));
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{join_map, split_map, write_split_map};
+    use crate::schema::{
+        Connection, ConnectionCondition, MapFile, PopupType, ShopNumber, TileData, TileHeight,
+        TileRamp, TileRampDirection, WallVisibility,
+    };
+    use grid::Grid;
+    use monostate::MustBeBool;
+    use std::fs;
+
+    #[test]
+    fn test_split_round_trip() {
+        let mut file = MapFile {
+            data: Grid::new(2, 3),
+            ..Default::default()
+        };
+        file.data[(0, 1)].height = TileHeight::Ramp {
+            ramp: MustBeBool,
+            height: TileRamp {
+                dir: TileRampDirection::Horizontal,
+                pos: 2.0,
+                neg: 1.0,
+            },
+        };
+        file.data[(0, 2)].connections.west = Connection::Conditional(ConnectionCondition::Lock);
+        file.data[(1, 0)].wall_visibility.south = WallVisibility::Shown;
+        file.data[(1, 1)].popup = Some(PopupType::Star2);
+        file.data[(1, 2)].popup = Some(PopupType::Shop(ShopNumber::Shop1));
+        file.data[(1, 2)].coins = -3;
+
+        let dir = std::env::temp_dir().join(format!("msp-split-map-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("map.json");
+        let (metadata, tiles) = split_map(&serde_json::to_vec(&file).unwrap()).unwrap();
+        write_split_map(&path, &metadata, &tiles).unwrap();
+        assert!(dir.join("map.tiles").join("2_1.json").is_file());
+
+        let joined = join_map(&fs::read(&path).unwrap(), &path);
+        fs::remove_dir_all(&dir).unwrap();
+        let joined: MapFile = serde_json::from_slice(&joined.unwrap()).unwrap();
+        assert_eq!(joined.data, file.data);
+        assert_eq!(joined.data[(0, 0)], TileData::default());
+    }
+}
@@ -1,10 +1,11 @@
 use crate::load_file::LoadedTexture;
 use crate::schema::{
     Connection, MpsMaterial, MpsTransform, MpsVec2, MpsVec3, PopupType, ShopItem, ShopNumber,
-    TileData, TileHeight,
+    TileData, TileHeight, TileRampDirection, WallVisibility,
 };
 use crate::tile_range::TileRange;
 use bevy::prelude::{Component, Event};
+use serde::{Deserialize, Serialize};
 use std::mem;
 use strum::{AsRefStr, Display};
 use transform_gizmo_bevy::{GizmoHotkeys, GizmoMode, GizmoOptions};
@@ -12,7 +13,7 @@ use transform_gizmo_bevy::{GizmoHotkeys, GizmoMode, GizmoOptions};
 #[derive(Event, Clone, Debug)]
 pub struct MapEdited(pub MapEdit);
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum MapEdit {
     StartingTile(MpsVec2),
     ShopWarpTile(usize, ListEdit<MpsVec2>),
@@ -34,11 +35,185 @@ pub enum MapEdit {
     ChangeCoins(TileRange, Vec<i32>),
     ChangeWalkOver(TileRange, Vec<bool>),
     ChangeSilverStarSpawnable(TileRange, Vec<bool>),
+    ChangeWallVisibility(TileRange, Direction, Vec<WallVisibility>),
+    ReplaceTiles(TileRange, Vec<TileData>),
+    TransformTiles(TileRange, TileTransform),
+}
+
+impl MapEdit {
+    /// The tiles touched by this edit, for edits that target a range of tiles
+    pub fn tile_range(&self) -> Option<TileRange> {
+        match self {
+            Self::AdjustHeight(range, _)
+            | Self::ChangeHeight(range, _)
+            | Self::ChangeConnection(range, _, _)
+            | Self::ChangeMaterial(range, _, _)
+            | Self::ChangePopupType(range, _)
+            | Self::ChangeCoins(range, _)
+            | Self::ChangeWalkOver(range, _)
+            | Self::ChangeSilverStarSpawnable(range, _)
+            | Self::ChangeWallVisibility(range, _, _)
+            | Self::ReplaceTiles(range, _)
+            | Self::TransformTiles(range, _) => Some(*range),
+            Self::StartingTile(_)
+            | Self::ShopWarpTile(_, _)
+            | Self::StarWarpTile(_)
+            | Self::PodiumPosition(_)
+            | Self::ResultsCamera(_, _)
+            | Self::Skybox(_, _)
+            | Self::Atlas(_)
+            | Self::ExpandMap(_, _)
+            | Self::ShrinkMap(_)
+            | Self::ChangeCameraPos(_, _)
+            | Self::ChangeCameraRot(_, _)
+            | Self::EditShop(_, _, _) => None,
+        }
+    }
+
+    /// Whether this edit can be applied to a map of the given size without going out of bounds,
+    /// for edits that don't come from the editor itself
+    pub fn fits_map(&self, size: MpsVec2) -> bool {
+        let values = match self {
+            Self::ChangeHeight(_, values) => values.len(),
+            Self::ChangeConnection(_, _, values) => values.len(),
+            Self::ChangeMaterial(_, _, values) => values.len(),
+            Self::ChangePopupType(_, values) => values.len(),
+            Self::ChangeCoins(_, values) => values.len(),
+            Self::ChangeWalkOver(_, values) | Self::ChangeSilverStarSpawnable(_, values) => {
+                values.len()
+            }
+            Self::ChangeWallVisibility(_, _, values) => values.len(),
+            Self::ReplaceTiles(_, values) => values.len(),
+            Self::ExpandMap(side, Some(data)) => {
+                let edge = match side {
+                    Direction::West | Direction::East => size.y,
+                    Direction::North | Direction::South => size.x,
+                };
+                return data.len() == edge as usize;
+            }
+            _ => return self.tile_range().is_none_or(|range| range.fits_map(size)),
+        };
+        self.tile_range()
+            .is_some_and(|range| range.fits_map(size) && range.area() == values)
+    }
+
+    /// What this edit does, for labelling undo and redo steps
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::StartingTile(_) => "starting tile move",
+            Self::ShopWarpTile(_, _) => "shop warp tile edit",
+            Self::StarWarpTile(_) => "star warp tile move",
+            Self::PodiumPosition(_) => "podium position move",
+            Self::ResultsCamera(_, _) => "results camera edit",
+            Self::Skybox(_, _) => "skybox change",
+            Self::Atlas(_) => "atlas change",
+            Self::ExpandMap(_, _) => "map expansion",
+            Self::ShrinkMap(_) => "map shrink",
+            Self::ChangeCameraPos(_, _) => "camera move",
+            Self::ChangeCameraRot(_, _) => "camera rotation",
+            Self::EditShop(_, _, _) => "shop edit",
+            Self::AdjustHeight(_, _) => "height adjustment",
+            Self::ChangeHeight(_, _) => "height change",
+            Self::ChangeConnection(_, _, _) => "connection change",
+            Self::ChangeMaterial(_, _, _) => "material change",
+            Self::ChangePopupType(_, _) => "popup change",
+            Self::ChangeCoins(_, _) => "coin change",
+            Self::ChangeWalkOver(_, _) => "walk over change",
+            Self::ChangeSilverStarSpawnable(_, _) => "silver star spawn change",
+            Self::ChangeWallVisibility(_, _, _) => "wall visibility change",
+            Self::ReplaceTiles(_, _) => "tile replacement",
+            Self::TransformTiles(_, _) => "tile transform",
+        }
+    }
 }
 
 pub type MaterialLocation = Option<(Direction, usize)>;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// Rearranges the tiles of a range in place, turning each tile to match
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileTransform {
+    /// Swaps the west and east sides of the range
+    MirrorX,
+    /// Swaps the north and south sides of the range
+    MirrorY,
+    /// Turns a square range a quarter turn, so that its north side faces east
+    RotateClockwise,
+    /// Turns a square range a quarter turn, so that its north side faces west
+    RotateCounterClockwise,
+}
+
+impl TileTransform {
+    pub const fn reverse(self) -> Self {
+        match self {
+            Self::MirrorX => Self::MirrorX,
+            Self::MirrorY => Self::MirrorY,
+            Self::RotateClockwise => Self::RotateCounterClockwise,
+            Self::RotateCounterClockwise => Self::RotateClockwise,
+        }
+    }
+
+    /// Whether this can only be applied to a [square](TileRange::is_square) range
+    pub const fn needs_square(self) -> bool {
+        matches!(self, Self::RotateClockwise | Self::RotateCounterClockwise)
+    }
+
+    /// The position in `range` of the tile that gets moved to `pos`
+    pub fn source(self, range: TileRange, pos: MpsVec2) -> MpsVec2 {
+        let offset = pos - range.start;
+        let last = range.width() - 1;
+        match self {
+            Self::MirrorX => MpsVec2::new(range.start.x + range.end.x - pos.x, pos.y),
+            Self::MirrorY => MpsVec2::new(pos.x, range.start.y + range.end.y - pos.y),
+            Self::RotateClockwise => range.start + MpsVec2::new(offset.y, last - offset.x),
+            Self::RotateCounterClockwise => range.start + MpsVec2::new(last - offset.y, offset.x),
+        }
+    }
+
+    /// Which side of a moved tile ends up facing `dir`
+    pub fn source_side(self, dir: Direction) -> Direction {
+        match self {
+            Self::MirrorX => dir.mirrored(true, false),
+            Self::MirrorY => dir.mirrored(false, true),
+            Self::RotateClockwise => dir.counter_clockwise(),
+            Self::RotateCounterClockwise => dir.clockwise(),
+        }
+    }
+
+    /// Turns a single tile, so that its sides and ramp face the way they do after moving
+    pub fn tile(self, tile: &TileData) -> TileData {
+        let mut result = tile.clone();
+        for &dir in Direction::ALL_CLOCKWISE {
+            let source = self.source_side(dir);
+            result.connections[dir] = tile.connections[source];
+            result.materials.wall_material[dir] = tile.materials.wall_material[source].clone();
+            result.wall_visibility[dir] = tile.wall_visibility[source];
+        }
+        // Ramps rise towards the east or south, so a ramp turned to rise towards the west or north
+        // has its ends swapped
+        result.height = match (self, tile.height.ramp_dir()) {
+            (Self::MirrorX, _) => tile.height.mirrored(true, false),
+            (Self::MirrorY, _) => tile.height.mirrored(false, true),
+            (_, None) => tile.height,
+            (Self::RotateClockwise, Some(TileRampDirection::Horizontal)) => {
+                tile.height.with_ramp_dir(Some(TileRampDirection::Vertical))
+            }
+            (Self::RotateClockwise, Some(TileRampDirection::Vertical)) => tile
+                .height
+                .with_ramp_dir(Some(TileRampDirection::Horizontal))
+                .with_flipped_heights(),
+            (Self::RotateCounterClockwise, Some(TileRampDirection::Horizontal)) => tile
+                .height
+                .with_ramp_dir(Some(TileRampDirection::Vertical))
+                .with_flipped_heights(),
+            (Self::RotateCounterClockwise, Some(TileRampDirection::Vertical)) => tile
+                .height
+                .with_ramp_dir(Some(TileRampDirection::Horizontal)),
+        };
+        result
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ListEdit<V> {
     Set(V),
     MoveUp,
@@ -75,7 +250,7 @@ impl<V> ListEdit<V> {
     }
 }
 
-#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CameraId {
     StarTutorial,
     ShopTutorial,
@@ -201,7 +376,7 @@ impl EditObject {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, AsRefStr, Display)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, AsRefStr, Display, Serialize, Deserialize)]
 pub enum Direction {
     West,
     East,
@@ -216,6 +391,45 @@ impl Direction {
         Direction::South,
         Direction::West,
     ];
+
+    pub const fn clockwise(self) -> Self {
+        match self {
+            Self::North => Self::East,
+            Self::East => Self::South,
+            Self::South => Self::West,
+            Self::West => Self::North,
+        }
+    }
+
+    pub const fn counter_clockwise(self) -> Self {
+        self.clockwise().opposite()
+    }
+
+    pub const fn opposite(self) -> Self {
+        match self {
+            Self::West => Self::East,
+            Self::East => Self::West,
+            Self::North => Self::South,
+            Self::South => Self::North,
+        }
+    }
+
+    pub const fn offset(self) -> MpsVec2 {
+        match self {
+            Self::West => MpsVec2::new(-1, 0),
+            Self::East => MpsVec2::new(1, 0),
+            Self::North => MpsVec2::new(0, -1),
+            Self::South => MpsVec2::new(0, 1),
+        }
+    }
+
+    pub fn mirrored(self, mirror_x: bool, mirror_y: bool) -> Self {
+        match self {
+            Self::West | Self::East if mirror_x => self.opposite(),
+            Self::North | Self::South if mirror_y => self.opposite(),
+            _ => self,
+        }
+    }
 }
 
 #[derive(Event, Copy, Clone, Debug, PartialEq)]
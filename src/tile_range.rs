@@ -1,6 +1,8 @@
 use crate::schema::MpsVec2;
+use serde::{Deserialize, Serialize};
+use strum::{IntoStaticStr, VariantArray};
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TileRange {
     pub start: MpsVec2,
     pub end: MpsVec2,
@@ -10,6 +12,134 @@ impl TileRange {
     pub fn area(self) -> usize {
         (self.end.x - self.start.x + 1) as usize * (self.end.y - self.start.y + 1) as usize
     }
+
+    pub fn width(self) -> i32 {
+        self.end.x - self.start.x + 1
+    }
+
+    pub fn height(self) -> i32 {
+        self.end.y - self.start.y + 1
+    }
+
+    pub fn is_square(self) -> bool {
+        self.width() == self.height()
+    }
+
+    pub fn contains(self, pos: MpsVec2) -> bool {
+        (self.start.x..=self.end.x).contains(&pos.x) && (self.start.y..=self.end.y).contains(&pos.y)
+    }
+
+    /// Whether this range is non-empty and lies inside a map of `size` tiles
+    pub fn fits_map(self, size: MpsVec2) -> bool {
+        self.start.x >= 0
+            && self.start.y >= 0
+            && self.start.x <= self.end.x
+            && self.start.y <= self.end.y
+            && self.end.x < size.x
+            && self.end.y < size.y
+    }
+
+    /// Index of `pos` in this range's iteration order
+    pub fn index_of(self, pos: MpsVec2) -> usize {
+        debug_assert!(self.contains(pos));
+        ((pos.y - self.start.y) * self.width() + pos.x - self.start.x) as usize
+    }
+
+    pub fn intersection(self, other: Self) -> Option<Self> {
+        let start = MpsVec2::new(
+            self.start.x.max(other.start.x),
+            self.start.y.max(other.start.y),
+        );
+        let end = MpsVec2::new(self.end.x.min(other.end.x), self.end.y.min(other.end.y));
+        (start.x <= end.x && start.y <= end.y).then_some(Self { start, end })
+    }
+
+    /// The smallest range containing both ranges
+    pub fn bounding(self, other: Self) -> Self {
+        Self {
+            start: MpsVec2::new(
+                self.start.x.min(other.start.x),
+                self.start.y.min(other.start.y),
+            ),
+            end: MpsVec2::new(self.end.x.max(other.end.x), self.end.y.max(other.end.y)),
+        }
+    }
+
+    /// Splits the part of this range not covered by `other` into up to four ranges
+    pub fn difference(self, other: Self) -> Vec<Self> {
+        let Some(overlap) = self.intersection(other) else {
+            return vec![self];
+        };
+        let mut result = vec![];
+        if overlap.start.y > self.start.y {
+            result.push(Self {
+                start: self.start,
+                end: MpsVec2::new(self.end.x, overlap.start.y - 1),
+            });
+        }
+        if overlap.end.y < self.end.y {
+            result.push(Self {
+                start: MpsVec2::new(self.start.x, overlap.end.y + 1),
+                end: self.end,
+            });
+        }
+        if overlap.start.x > self.start.x {
+            result.push(Self {
+                start: MpsVec2::new(self.start.x, overlap.start.y),
+                end: MpsVec2::new(overlap.start.x - 1, overlap.end.y),
+            });
+        }
+        if overlap.end.x < self.end.x {
+            result.push(Self {
+                start: MpsVec2::new(overlap.end.x + 1, overlap.start.y),
+                end: MpsVec2::new(self.end.x, overlap.end.y),
+            });
+        }
+        result
+    }
+
+    /// This range with `amount` more tiles on every side, staying inside a map of `map_size`
+    pub fn grown(self, amount: i32, map_size: MpsVec2) -> Self {
+        let amount = MpsVec2::new(amount, amount);
+        Self {
+            start: (self.start - amount).max(MpsVec2::ZERO),
+            end: (self.end + amount).min(map_size - MpsVec2::ONE),
+        }
+    }
+
+    pub fn mirrored(self, map_size: MpsVec2, mirror_x: bool, mirror_y: bool) -> Self {
+        let a = self.start.mirrored(map_size, mirror_x, mirror_y);
+        let b = self.end.mirrored(map_size, mirror_x, mirror_y);
+        Self {
+            start: MpsVec2::new(a.x.min(b.x), a.y.min(b.y)),
+            end: MpsVec2::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, IntoStaticStr, VariantArray)]
+pub enum Symmetry {
+    #[default]
+    #[strum(serialize = "None")]
+    None,
+    #[strum(serialize = "Mirror X")]
+    MirrorX,
+    #[strum(serialize = "Mirror Y")]
+    MirrorY,
+    #[strum(serialize = "4-way")]
+    FourWay,
+}
+
+impl Symmetry {
+    /// The `(mirror_x, mirror_y)` reflections applied in addition to the original edit
+    pub fn mirrors(self) -> &'static [(bool, bool)] {
+        match self {
+            Self::None => &[],
+            Self::MirrorX => &[(true, false)],
+            Self::MirrorY => &[(false, true)],
+            Self::FourWay => &[(true, false), (false, true), (true, true)],
+        }
+    }
 }
 
 impl IntoIterator for TileRange {
@@ -169,4 +299,80 @@ mod tests {
         iter.nth(24);
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_difference() {
+        let range = |x1, y1, x2, y2| TileRange {
+            start: MpsVec2::new(x1, y1),
+            end: MpsVec2::new(x2, y2),
+        };
+
+        assert_eq!(
+            range(0, 0, 2, 2).difference(range(5, 5, 6, 6)),
+            vec![range(0, 0, 2, 2)]
+        );
+        assert_eq!(range(0, 0, 2, 2).difference(range(0, 0, 2, 2)), vec![]);
+        assert_eq!(
+            range(0, 0, 2, 2).difference(range(1, 1, 1, 1)),
+            vec![
+                range(0, 0, 2, 0),
+                range(0, 2, 2, 2),
+                range(0, 1, 0, 1),
+                range(2, 1, 2, 1),
+            ]
+        );
+        assert_eq!(
+            range(0, 0, 3, 0).difference(range(2, 0, 5, 0)),
+            vec![range(0, 0, 1, 0)]
+        );
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let size = MpsVec2::new(5, 4);
+        let range = TileRange {
+            start: MpsVec2::new(0, 1),
+            end: MpsVec2::new(1, 1),
+        };
+        assert_eq!(
+            range.mirrored(size, true, false),
+            TileRange {
+                start: MpsVec2::new(3, 1),
+                end: MpsVec2::new(4, 1),
+            }
+        );
+        assert_eq!(
+            range.mirrored(size, false, true),
+            TileRange {
+                start: MpsVec2::new(0, 2),
+                end: MpsVec2::new(1, 2),
+            }
+        );
+        assert_eq!(range.mirrored(size, false, false), range);
+    }
+
+    #[test]
+    fn test_grown() {
+        let size = MpsVec2::new(5, 4);
+        let range = |x1, y1, x2, y2| TileRange {
+            start: MpsVec2::new(x1, y1),
+            end: MpsVec2::new(x2, y2),
+        };
+        assert_eq!(range(1, 1, 2, 2).grown(1, size), range(0, 0, 3, 3));
+        assert_eq!(range(0, 2, 4, 3).grown(1, size), range(0, 1, 4, 3));
+        assert_eq!(range(2, 2, 2, 2).grown(0, size), range(2, 2, 2, 2));
+    }
+
+    #[test]
+    fn test_fits_map() {
+        let size = MpsVec2::new(5, 4);
+        let range = |x1, y1, x2, y2| TileRange {
+            start: MpsVec2::new(x1, y1),
+            end: MpsVec2::new(x2, y2),
+        };
+        assert!(range(0, 0, 4, 3).fits_map(size));
+        assert!(!range(0, 0, 5, 3).fits_map(size));
+        assert!(!range(-1, 0, 2, 2).fits_map(size));
+        assert!(!range(2, 2, 1, 2).fits_map(size));
+    }
 }
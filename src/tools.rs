@@ -0,0 +1,277 @@
+use crate::load_file::{FileLoaded, LoadedFile};
+use crate::schema::{MpsMaterial, MpsVec2};
+use crate::shortcut_pressed;
+use crate::sync::{ListEdit, MapEdit};
+use crate::tile_range::TileRange;
+use crate::ui::UiState;
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::mem;
+use strum::{IntoStaticStr, VariantArray};
+
+pub struct ToolsPlugin;
+
+impl Plugin for ToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveTool>()
+            .init_resource::<ToolOptions>()
+            .init_resource::<PaintStroke>()
+            .init_resource::<Measurement>()
+            .add_observer(on_use_tool)
+            .add_observer(on_file_loaded)
+            .add_systems(
+                Update,
+                (tool_shortcuts, finish_paint_stroke, draw_measurement),
+            );
+    }
+}
+
+/// What clicking a tile in the viewport does
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, IntoStaticStr, VariantArray)]
+pub enum Tool {
+    /// Selects tiles and objects for editing
+    #[default]
+    Select,
+    /// Copies a property of the clicked tile onto the selected tiles
+    Eyedropper,
+    /// Changes the top material of the clicked tile and the connected tiles that share it
+    Fill,
+    /// Changes the top material of every tile dragged across, as a single edit
+    Paint,
+    /// Shows the distance between two clicked tiles without changing anything
+    Measure,
+}
+
+#[derive(Resource, Default)]
+pub struct ActiveTool(pub Tool);
+
+/// Per-tool configuration, shown in the tool options panel for the active tool
+#[derive(Resource, Default)]
+pub struct ToolOptions {
+    /// Shared by every tool
+    pub picking: PickingOptions,
+    /// The top material that [`Tool::Fill`] and [`Tool::Paint`] change tiles to
+    pub material: MpsMaterial,
+    pub eyedropper: EyedropperOptions,
+}
+
+/// How a click in the viewport decides which tile was clicked
+#[derive(Default)]
+pub struct PickingOptions {
+    /// Project clicks onto a horizontal plane at [`Self::plane_height`] instead of using the point
+    /// on the mesh that was hit, so the same spot on screen picks the same tile whatever the
+    /// terrain looks like
+    pub use_plane: bool,
+    pub plane_height: f32,
+}
+
+#[derive(Default)]
+pub struct EyedropperOptions {
+    pub sample: EyedropperSample,
+    /// Switch back to [`Tool::Select`] after copying once
+    pub return_to_select: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, IntoStaticStr, VariantArray)]
+pub enum EyedropperSample {
+    #[default]
+    #[strum(serialize = "Top material")]
+    TopMaterial,
+    Height,
+}
+
+/// A tile was clicked in the viewport while a tool other than [`Tool::Select`] was active
+#[derive(Event, Copy, Clone, Debug)]
+pub struct UseTool {
+    pub tile: MpsVec2,
+}
+
+fn on_use_tool(
+    on: On<UseTool>,
+    mut commands: Commands,
+    mut tool: ResMut<ActiveTool>,
+    options: Res<ToolOptions>,
+    mut file: ResMut<LoadedFile>,
+    mut ui_state: ResMut<UiState>,
+    mut measurement: ResMut<Measurement>,
+) {
+    match tool.0 {
+        Tool::Select => {}
+        Tool::Eyedropper => {
+            let options = &options.eyedropper;
+            let Some(range) = file.selected_range else {
+                return;
+            };
+            let source = &file.file[on.tile];
+            let edit = match options.sample {
+                EyedropperSample::TopMaterial => MapEdit::ChangeMaterial(
+                    range,
+                    None,
+                    vec![ListEdit::Set(source.materials.material); range.area()],
+                ),
+                EyedropperSample::Height => {
+                    MapEdit::ChangeHeight(range, vec![source.height; range.area()])
+                }
+            };
+            file.edit_tiles(&mut commands, edit, ui_state.tile_edit_mode());
+            if options.return_to_select {
+                tool.0 = Tool::Select;
+            }
+        }
+        Tool::Fill => {
+            let count = file.flood_fill_material(&mut commands, on.tile, options.material);
+            if count > 0 {
+                ui_state.show_notice(format!("Filled {count} tiles"));
+            }
+        }
+        // Painting happens over the whole drag, in [`finish_paint_stroke`]
+        Tool::Paint => {}
+        Tool::Measure => measurement.click(on.tile),
+    }
+}
+
+/// The tiles dragged across so far with [`Tool::Paint`], painted once the mouse is released
+#[derive(Resource, Default)]
+pub struct PaintStroke {
+    tiles: HashSet<MpsVec2>,
+    range: Option<TileRange>,
+}
+
+impl PaintStroke {
+    pub fn is_active(&self) -> bool {
+        self.range.is_some()
+    }
+
+    pub fn start(&mut self, tile: MpsVec2) {
+        self.tiles.clear();
+        self.range = None;
+        self.add(tile);
+    }
+
+    pub fn add(&mut self, tile: MpsVec2) {
+        let single = TileRange {
+            start: tile,
+            end: tile,
+        };
+        self.range = Some(self.range.map_or(single, |range| range.bounding(single)));
+        self.tiles.insert(tile);
+    }
+}
+
+fn finish_paint_stroke(
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut stroke: ResMut<PaintStroke>,
+    mut commands: Commands,
+    options: Res<ToolOptions>,
+    mut file: ResMut<LoadedFile>,
+) {
+    if !stroke.is_active() || mouse.pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(range) = stroke.range.take() else {
+        return;
+    };
+    let tiles = mem::take(&mut stroke.tiles);
+    // The map may have been switched or resized mid-stroke
+    if file.in_bounds(range.end) != range.end
+        || tiles
+            .iter()
+            .all(|&pos| file.file[pos].materials.material == options.material)
+    {
+        return;
+    }
+
+    // Tiles in the bounding range that weren't dragged across keep their material
+    let edits = range
+        .into_iter()
+        .map(|pos| {
+            ListEdit::Set(if tiles.contains(&pos) {
+                options.material
+            } else {
+                file.file[pos].materials.material
+            })
+        })
+        .collect();
+    file.edit_map(&mut commands, MapEdit::ChangeMaterial(range, None, edits));
+}
+
+/// The tiles clicked with [`Tool::Measure`]. A third click starts a new measurement.
+#[derive(Resource, Default)]
+pub struct Measurement {
+    pub start: Option<MpsVec2>,
+    pub end: Option<MpsVec2>,
+}
+
+impl Measurement {
+    fn click(&mut self, tile: MpsVec2) {
+        if self.start.is_none() || self.end.is_some() {
+            self.start = Some(tile);
+            self.end = None;
+        } else {
+            self.end = Some(tile);
+        }
+    }
+
+    fn delta(&self) -> Option<MpsVec2> {
+        Some(self.end? - self.start?)
+    }
+
+    /// The number of steps between the tiles when only moving along rows and columns
+    pub fn manhattan(&self) -> Option<i32> {
+        self.delta().map(|delta| delta.x.abs() + delta.y.abs())
+    }
+
+    pub fn euclidean(&self) -> Option<f64> {
+        self.delta()
+            .map(|delta| (delta.x as f64).hypot(delta.y as f64))
+    }
+
+    /// The number of tiles on a path between the tiles along rows and columns, counting both ends
+    pub fn path_tiles(&self) -> Option<i32> {
+        self.manhattan().map(|steps| steps + 1)
+    }
+}
+
+fn on_file_loaded(_: On<FileLoaded>, mut measurement: ResMut<Measurement>) {
+    *measurement = Measurement::default();
+}
+
+fn draw_measurement(
+    mut gizmos: Gizmos,
+    tool: Res<ActiveTool>,
+    measurement: Res<Measurement>,
+    file: Res<LoadedFile>,
+) {
+    if tool.0 != Tool::Measure {
+        return;
+    }
+    let top = |pos: MpsVec2| {
+        let height = file.file[pos].height.max_height() as f32;
+        Vec3::new(pos.x as f32, height + 0.05, pos.y as f32)
+    };
+    let color = Srgba::rgb_u8(0, 180, 255);
+    let Some(start) = measurement.start.filter(|&pos| file.in_bounds(pos) == pos) else {
+        return;
+    };
+    gizmos.sphere(Isometry3d::from_translation(top(start)), 0.2, color);
+    if let Some(end) = measurement.end.filter(|&pos| file.in_bounds(pos) == pos) {
+        gizmos.sphere(Isometry3d::from_translation(top(end)), 0.2, color);
+        gizmos.line(top(start), top(end), color);
+    }
+}
+
+fn tool_shortcuts(
+    keys: Res<ButtonInput<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut tool: ResMut<ActiveTool>,
+) {
+    if ui_state.typing() {
+        return;
+    }
+    if shortcut_pressed!(keys, Escape) {
+        tool.0 = Tool::Select;
+    }
+    if shortcut_pressed!(keys, Ctrl + KeyB) {
+        tool.0 = Tool::Fill;
+    }
+}
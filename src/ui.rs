@@ -1,22 +1,39 @@
-use crate::assets::{icons_atlas, item_icons, unset_texture_icon};
+use crate::assets::{icons_atlas, item_icons, missing_atlas, unset_texture_icon};
+use crate::autosave::Autosaves;
 use crate::docking::UiDocking;
+use crate::export::{
+    AtlasExportDialog, ExportAtlasLayout, ExportImage, ExportModel, ExportReport, ExportSchema,
+    ExportScreenshot, ExportTasks, ImageExportDialog, ModelExportDialog, ModelFormat,
+    ReportExportDialog, SchemaExportDialog, ScreenshotExportDialog,
+};
 use crate::load_file::{
-    FileLoaded, LoadedFile, LoadedTexture, MapFileDialog, new_file, open_file, save_file,
-    save_file_as,
+    ChangeSummary, FileLoaded, LoadedFile, LoadedTexture, MapFileDialog, OpenTabs, RecentFiles,
+    RecoverMapFileDialog, SaveAbandoned, TextureReloaded, TileEditMode, new_file, open_file,
+    open_file_copy, open_file_path, recover_file, save_file, save_file_as,
 };
+use crate::mesh::MapMeshStats;
+use crate::minimap::{Minimap, minimap_view};
+use crate::report::ReportFormat;
 use crate::schema::{
     Connection, ConnectionCondition, CubeMap, MapFile, MpsMaterial, MpsTransform, MpsVec2,
-    PopupType, ShopItem, ShopNumber, TileHeight, TileRampDirection,
+    PopupType, ShopItem, ShopNumber, TileData, TileHeight, TileRampDirection, WallVisibility,
+};
+use crate::session_log::{SessionLog, SessionLogDialog, replay_session_log, save_session_log};
+use crate::settings::{BudgetSettings, EditorSettings, GridOrigin, MeshSettings};
+use crate::split_map::{
+    SplitMapExportDialog, SplitMapImportDialog, export_split_map, import_split_map,
 };
 use crate::sync::{
     CameraId, Direction, ListEdit, MaterialLocation, PresetView, PreviewObject,
-    PreviewResultsAnimation, TogglePreviewVisibility,
+    PreviewResultsAnimation, TileTransform, TogglePreviewVisibility,
 };
 use crate::sync::{EditObject, MapEdit, MapEdited, SelectForEditing};
-use crate::tile_range::TileRange;
+use crate::tile_range::{Symmetry, TileRange};
+use crate::tools::{ActiveTool, EyedropperSample, Measurement, Tool, ToolOptions};
 use crate::utils::TriStateCheckbox;
-use crate::viewport::ViewportTarget;
-use crate::{Directories, shortcut_pressed};
+use crate::validation::{MAX_RAMP_RISE, MapIssues};
+use crate::viewport::{HistoryHighlight, ShowWireframe, TextureLoadErrors, ViewportTarget};
+use crate::{Directories, modifier_key, shortcut_pressed};
 use bevy::asset::LoadState;
 use bevy::asset::io::embedded::GetAssetServer;
 use bevy::image::{ImageFormatSetting, ImageLoaderSettings};
@@ -28,6 +45,7 @@ use bevy_file_dialog::{DialogFilePicked, FileDialogExt, FileDialogPlugin};
 use bevy_mod_imgui::prelude::*;
 use enum_map::{Enum, EnumMap, enum_map};
 use imgui::Image as ImguiImage;
+use imgui::{Key, MouseCursor, TabItem, TabItemFlags};
 use itertools::Itertools;
 use monostate::MustBeBool;
 use std::borrow::Cow;
@@ -48,6 +66,7 @@ impl Plugin for MapEditorUi {
             unset_texture_icon: unset_texture_icon(app.get_asset_server()),
             icon_atlas_handle: icons_atlas(app.get_asset_server()),
             item_handles: item_icons(app.get_asset_server()),
+            missing_atlas_handle: missing_atlas(app.get_asset_server()),
             preview_star_warp_tile: true,
             ..Default::default()
         })
@@ -56,7 +75,18 @@ impl Plugin for MapEditorUi {
             FileDialogPlugin::new()
                 .with_pick_file::<SettingImagePick>()
                 .with_load_file::<MapFileDialog>()
-                .with_save_file::<MapFileDialog>(),
+                .with_save_file::<MapFileDialog>()
+                .with_load_file::<RecoverMapFileDialog>()
+                .with_save_file::<ImageExportDialog>()
+                .with_save_file::<AtlasExportDialog>()
+                .with_save_file::<ModelExportDialog>()
+                .with_save_file::<ReportExportDialog>()
+                .with_save_file::<SchemaExportDialog>()
+                .with_save_file::<ScreenshotExportDialog>()
+                .with_save_file::<SessionLogDialog>()
+                .with_load_file::<SessionLogDialog>()
+                .with_save_file::<SplitMapExportDialog>()
+                .with_load_file::<SplitMapImportDialog>(),
         ))
         .add_systems(Startup, |mut imgui: NonSendMut<ImguiContext>| {
             imgui.with_io_mut(|io| {
@@ -65,6 +95,8 @@ impl Plugin for MapEditorUi {
         })
         .add_observer(on_file_loaded)
         .add_observer(on_map_edited)
+        .add_observer(on_texture_reloaded)
+        .add_observer(on_close_tab)
         .add_systems(
             Update,
             (
@@ -84,18 +116,53 @@ pub struct UiState {
     textures_to_free: Vec<TextureId>,
     free_timer: Timer,
     pending_close_state: PendingCloseState,
+    pending_shrink_state: PendingShrinkState,
     skybox_textures: Option<CubeMap<TextureId>>,
     atlas_texture: Option<TextureId>,
     waiting_textures: Vec<SettingImageLoadWait>,
     unset_texture_icon: Handle<BevyImage>,
     icon_atlas_handle: Handle<BevyImage>,
     icon_atlas_texture: Option<TextureId>,
+    missing_atlas_handle: Handle<BevyImage>,
+    /// Shown in the material palette while the map has no atlas
+    missing_atlas_texture: Option<TextureId>,
+    /// The minimap texture, along with the [`Minimap::revision`] it was registered for
+    minimap_texture: Option<(TextureId, usize)>,
     item_handles: EnumMap<ShopItem, Handle<BevyImage>>,
     item_textures: Option<EnumMap<ShopItem, TextureId>>,
     material_target: Option<(TileRange, MaterialLocation)>,
+    material_picker: MaterialPickerState,
     item_target: Option<(ShopNumber, usize)>,
     preview_star_warp_tile: bool,
     preview_podium: bool,
+    resize_size: [i32; 2],
+    resize_anchor: usize,
+    /// Whether growing the map copies the tiles along the edge it grows from, instead of adding
+    /// blank tiles
+    pub expand_duplicates_edge: bool,
+    tile_edit_mode: TileEditMode,
+    ramp_profile: RampProfileSettings,
+    shared_slope: SharedSlopeSettings,
+    set_height: SetHeightSettings,
+    show_settings: bool,
+    show_statistics: bool,
+    show_material_palette: bool,
+    /// Set to open the "Go to tile" dialog on the next frame
+    open_go_to_tile: bool,
+    /// Set to ask whether to clear every tile on the next frame
+    open_clear_map: bool,
+    /// The tile typed into the "Go to tile" dialog, counted from the grid origin
+    go_to_tile: [i32; 2],
+    /// Whether an imgui text field had keyboard focus last frame, so arrow keys are left to it
+    typing: bool,
+    /// The corner of the selection that stays put while Shift+arrow keys move the other one
+    selection_anchor: Option<MpsVec2>,
+    /// The tab that imgui is known to be showing as selected
+    shown_tab: Option<usize>,
+    /// A message shown in the status bar until the timer runs out
+    status_notice: Option<(String, Timer)>,
+    /// Shown in a popup before saving, if that's enabled
+    save_summary: Option<ChangeSummary>,
 }
 
 impl UiState {
@@ -105,6 +172,42 @@ impl UiState {
     ) {
         self.pending_close_state = PendingCloseState::PendingUi(Box::new(action));
     }
+
+    pub fn tile_edit_mode(&self) -> TileEditMode {
+        self.tile_edit_mode
+    }
+
+    /// Whether an imgui text field has keyboard focus, so shortcuts should be left to it
+    pub fn typing(&self) -> bool {
+        self.typing
+    }
+
+    /// Saves the file, first showing what changed since the last save if that's enabled
+    pub fn request_save(
+        &mut self,
+        commands: &mut Commands,
+        file: &mut LoadedFile,
+        settings: &EditorSettings,
+    ) {
+        if settings.save.show_summary
+            && file.path.is_some()
+            && let Some(summary) = file.changes_since_save()
+        {
+            self.save_summary = Some(summary);
+        } else {
+            save_file(commands, file, &settings.save, settings.grid_origin);
+        }
+    }
+
+    pub fn show_notice(&mut self, text: String) {
+        self.status_notice = Some((text, Timer::from_seconds(2.0, TimerMode::Once)));
+    }
+
+    pub fn request_shrink(&mut self, side: Direction, count: usize) {
+        if matches!(self.pending_shrink_state, PendingShrinkState::None) {
+            self.pending_shrink_state = PendingShrinkState::PendingUi(side, count);
+        }
+    }
 }
 
 type BoxedCloseHandler = Box<dyn FnOnce(&mut Commands, &mut LoadedFile) + Send + Sync>;
@@ -115,9 +218,129 @@ enum PendingCloseState {
     None,
     PendingUi(BoxedCloseHandler),
     PendingUserInput(BoxedCloseHandler),
+    /// "Save" was picked, so the file is closed once it's saved, or kept open if it isn't
+    PendingSave(BoxedCloseHandler),
     Confirmed(BoxedCloseHandler),
 }
 
+#[derive(Copy, Clone, Default)]
+enum PendingShrinkState {
+    #[default]
+    None,
+    PendingUi(Direction, usize),
+    PendingUserInput(Direction, usize),
+}
+
+struct RampProfileSettings {
+    staircase: bool,
+    step: f64,
+}
+
+impl Default for RampProfileSettings {
+    fn default() -> Self {
+        Self {
+            staircase: false,
+            step: 0.25,
+        }
+    }
+}
+
+/// Keyboard state of the material picker
+#[derive(Default)]
+struct MaterialPickerState {
+    /// The highlighted cell, picked with Enter
+    focus: usize,
+    /// Digits typed since the picker was opened or the focus was moved with the arrow keys
+    typed_index: String,
+}
+
+impl MaterialPickerState {
+    fn open(&mut self, focus: usize) {
+        self.focus = focus;
+        self.typed_index.clear();
+    }
+
+    fn handle_keys(&mut self, ui: &Ui) -> bool {
+        let moves = [
+            (Key::LeftArrow, -1),
+            (Key::RightArrow, 1),
+            (Key::UpArrow, -(MpsMaterial::TEXTURES_PER_ROW as isize)),
+            (Key::DownArrow, MpsMaterial::TEXTURES_PER_ROW as isize),
+        ];
+        for (key, offset) in moves {
+            if ui.is_key_pressed(key) {
+                self.focus = self
+                    .focus
+                    .saturating_add_signed(offset)
+                    .min(MpsMaterial::TEXTURES_COUNT - 1);
+                self.typed_index.clear();
+            }
+        }
+
+        const DIGIT_KEYS: [[Key; 2]; 10] = [
+            [Key::Alpha0, Key::Keypad0],
+            [Key::Alpha1, Key::Keypad1],
+            [Key::Alpha2, Key::Keypad2],
+            [Key::Alpha3, Key::Keypad3],
+            [Key::Alpha4, Key::Keypad4],
+            [Key::Alpha5, Key::Keypad5],
+            [Key::Alpha6, Key::Keypad6],
+            [Key::Alpha7, Key::Keypad7],
+            [Key::Alpha8, Key::Keypad8],
+            [Key::Alpha9, Key::Keypad9],
+        ];
+        for (digit, keys) in DIGIT_KEYS.iter().enumerate() {
+            if keys.iter().any(|&key| ui.is_key_pressed(key)) {
+                self.typed_index.push(char::from(b'0' + digit as u8));
+                // Start a new number once the typed one can't be a material anymore
+                if !self
+                    .typed_index
+                    .parse()
+                    .is_ok_and(|index: usize| index < MpsMaterial::TEXTURES_COUNT)
+                {
+                    self.typed_index = digit.to_string();
+                }
+                self.focus = self.typed_index.parse().unwrap();
+            }
+        }
+        if ui.is_key_pressed(Key::Backspace) {
+            self.typed_index.pop();
+            if let Ok(index) = self.typed_index.parse() {
+                self.focus = index;
+            }
+        }
+
+        ui.is_key_pressed(Key::Enter) || ui.is_key_pressed(Key::KeypadEnter)
+    }
+}
+
+struct SharedSlopeSettings {
+    uphill: Direction,
+    rise: f64,
+    start_height: f64,
+}
+
+impl Default for SharedSlopeSettings {
+    fn default() -> Self {
+        Self {
+            uphill: Direction::East,
+            rise: 0.25,
+            start_height: 0.0,
+        }
+    }
+}
+
+/// Heights entered in "Set height" are kept within this far of zero. Target heights can't go
+/// below zero at all, as they'd be under the floor.
+const SET_HEIGHT_LIMIT: f64 = 1000.0;
+
+#[derive(Default)]
+struct SetHeightSettings {
+    height: f64,
+    /// Raises or lowers each tile by the height instead of setting them all to it
+    relative: bool,
+}
+
 #[derive(Copy, Clone)]
 enum SettingImagePick {
     Skybox(usize),
@@ -197,10 +420,27 @@ fn on_map_edited(on: On<MapEdited>, mut state: ResMut<UiState>) {
         | MapEdit::ChangePopupType(_, _)
         | MapEdit::ChangeCoins(_, _)
         | MapEdit::ChangeWalkOver(_, _)
-        | MapEdit::ChangeSilverStarSpawnable(_, _) => {}
+        | MapEdit::ChangeSilverStarSpawnable(_, _)
+        | MapEdit::ChangeWallVisibility(_, _, _)
+        | MapEdit::ReplaceTiles(_, _)
+        | MapEdit::TransformTiles(_, _) => {}
     }
 }
 
+fn on_texture_reloaded(on: On<TextureReloaded>, file: Res<LoadedFile>, mut state: ResMut<UiState>) {
+    let (image, pick) = match *on {
+        TextureReloaded::Skybox(index) => (
+            &file.loaded_textures.skybox[index].image,
+            SettingImagePick::Skybox(index),
+        ),
+        TextureReloaded::Atlas => (&file.loaded_textures.atlas.image, SettingImagePick::Atlas),
+    };
+    state.waiting_textures.push(SettingImageLoadWait {
+        image: image.clone(),
+        pick,
+    });
+}
+
 fn setting_image_picked(
     mut files: MessageReader<DialogFilePicked<SettingImagePick>>,
     assets: Res<AssetServer>,
@@ -243,6 +483,26 @@ fn draw_imgui(
     mut viewport_target: ResMut<ViewportTarget>,
     mut images: ResMut<Assets<BevyImage>>,
     assets: Res<AssetServer>,
+    mut issues: ResMut<MapIssues>,
+    mut settings: ResMut<EditorSettings>,
+    (export_tasks, load_errors, mesh_stats): (
+        Res<ExportTasks>,
+        Res<TextureLoadErrors>,
+        Res<MapMeshStats>,
+    ),
+    mut tabs: ResMut<OpenTabs>,
+    (mut tool, mut tool_options, measurement): (
+        ResMut<ActiveTool>,
+        ResMut<ToolOptions>,
+        Res<Measurement>,
+    ),
+    (mut session_log, autosaves): (ResMut<SessionLog>, Res<Autosaves>),
+    (mut recent_files, mut history_highlight, mut wireframe, mut minimap): (
+        ResMut<RecentFiles>,
+        ResMut<HistoryHighlight>,
+        ResMut<ShowWireframe>,
+        ResMut<Minimap>,
+    ),
 ) {
     if state.viewport_texture.is_none() {
         state.viewport_texture =
@@ -279,6 +539,10 @@ fn draw_imgui(
     state.atlas_texture = atlas_texture;
     state.textures_to_free.extend(removed_textures);
 
+    if state.missing_atlas_texture.is_none() && assets.is_loaded(&state.missing_atlas_handle) {
+        state.missing_atlas_texture =
+            Some(context.register_bevy_texture(state.missing_atlas_handle.clone()));
+    }
     if state.icon_atlas_texture.is_none() && assets.is_loaded(&state.icon_atlas_handle) {
         state.icon_atlas_texture =
             Some(context.register_bevy_texture(state.icon_atlas_handle.clone()));
@@ -304,6 +568,7 @@ fn draw_imgui(
     }
 
     let ui = context.ui();
+    state.typing = ui.io().want_text_input;
 
     if !state.setup_complete {
         ui.dockspace_over_viewport().split(
@@ -315,6 +580,8 @@ fn draw_imgui(
             |right| {
                 right.dock_window("Map settings");
                 right.dock_window("Tile settings");
+                right.dock_window("Map issues");
+                right.dock_window("Tool options");
             },
         );
         state.setup_complete = true;
@@ -322,6 +589,12 @@ fn draw_imgui(
         ui.dockspace_over_main_viewport();
     }
 
+    // Positions shown and typed into the UI count from the corner picked in the settings
+    let grid_origin = settings.grid_origin;
+    let map_size = file.file.map_size().unwrap_or_default();
+    let flip_pos = |pos: MpsVec2| grid_origin.flip(pos, map_size);
+
+    let mut hovered_history_range = None;
     ui.main_menu_bar(|| {
         ui.menu("File", || {
             if ui.menu_item_config("New").shortcut("Ctrl+N").build() {
@@ -332,8 +605,51 @@ fn draw_imgui(
                 open_file(&mut state);
             }
 
+            ui.menu_with_enabled("Open Recent", !recent_files.paths().is_empty(), || {
+                if recent_files.has_missing() {
+                    recent_files.prune_missing();
+                }
+                let mut selected = None;
+                for (index, path) in recent_files.paths().iter().enumerate() {
+                    if ui.menu_item(format!("{}##{index}", path.display())) {
+                        selected = Some(path.clone());
+                    }
+                }
+                if let Some(path) = selected {
+                    if let Some(index) = tabs.find(&path, &file) {
+                        tabs.switch_to(&mut commands, &mut file, index);
+                    } else {
+                        open_file_path(&mut state, path, assets.clone(), settings.load);
+                    }
+                }
+
+                ui.separator();
+                if ui.menu_item("Clear list") {
+                    recent_files.clear();
+                }
+            });
+
+            if ui.menu_item("Recover...") {
+                recover_file(&mut state);
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(
+                    "Opens a map that fails to load, replacing the tiles that can't be read with \
+                    empty tiles",
+                );
+            }
+
+            if ui.menu_item_config("New tab").shortcut("Ctrl+T").build() {
+                tabs.new_tab(&mut commands, &mut file);
+            }
+
+            if ui.menu_item("Open in new tab") {
+                tabs.new_tab(&mut commands, &mut file);
+                open_file(&mut state);
+            }
+
             if ui.menu_item_config("Save").shortcut("Ctrl+S").build() {
-                save_file(&mut commands, &mut file);
+                state.request_save(&mut commands, &mut file, &settings);
             }
 
             if ui
@@ -341,7 +657,134 @@ fn draw_imgui(
                 .shortcut("Ctrl+Shift+S")
                 .build()
             {
-                save_file_as(&mut commands);
+                save_file_as(
+                    &mut commands,
+                    &mut file,
+                    &settings.save,
+                    settings.grid_origin,
+                );
+            }
+
+            ui.menu_with_enabled(
+                "Restore from autosave",
+                !autosaves.snapshots.is_empty(),
+                || {
+                    for (index, snapshot) in autosaves.snapshots.iter().enumerate() {
+                        if ui.menu_item(format!("{}##{index}", snapshot.label())) {
+                            let path = snapshot.path.clone();
+                            open_file_copy(&mut state, path, assets.clone(), settings.load);
+                        }
+                    }
+                },
+            );
+
+            ui.separator();
+
+            ui.menu("Export image", || {
+                if ui.menu_item("Whole map") {
+                    commands.trigger(ExportImage { range: None });
+                }
+
+                if ui
+                    .menu_item_config("Selection")
+                    .enabled(file.selected_range.is_some())
+                    .build()
+                {
+                    commands.trigger(ExportImage {
+                        range: file.selected_range,
+                    });
+                }
+            });
+
+            ui.menu("Session log", || {
+                if ui
+                    .menu_item_config("Record")
+                    .selected(session_log.is_recording())
+                    .build()
+                {
+                    let recording = !session_log.is_recording();
+                    session_log.set_recording(recording);
+                }
+
+                if ui
+                    .menu_item_config("Save log")
+                    .enabled(!session_log.is_empty())
+                    .build()
+                {
+                    save_session_log(&mut commands, &session_log);
+                }
+
+                if ui.menu_item("Replay log") {
+                    replay_session_log(&mut commands);
+                }
+            });
+
+            ui.menu("Export atlas layout", || {
+                if ui.menu_item("Grid only") {
+                    commands.trigger(ExportAtlasLayout { show_counts: false });
+                }
+
+                if ui.menu_item("With usage counts") {
+                    commands.trigger(ExportAtlasLayout { show_counts: true });
+                }
+            });
+
+            ui.menu("Export", || {
+                ui.menu("Screenshot", || {
+                    for scale in [1, 2, 4] {
+                        if ui.menu_item(format!("{scale}x")) {
+                            commands.trigger(ExportScreenshot { scale });
+                        }
+                    }
+                });
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Saves the current view of the viewport as a PNG");
+                }
+                if ui.menu_item("glTF") {
+                    commands.trigger(ExportModel(ModelFormat::Gltf));
+                }
+                if ui.menu_item("OBJ") {
+                    commands.trigger(ExportModel(ModelFormat::Obj));
+                }
+                if ui
+                    .menu_item_config("Include floor")
+                    .selected(settings.export.include_floor)
+                    .build()
+                {
+                    settings.export.include_floor = !settings.export.include_floor;
+                }
+                ui.separator();
+                if ui.menu_item("Schema") {
+                    commands.trigger(ExportSchema);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Saves a JSON Schema describing the map file format");
+                }
+            });
+
+            ui.menu("Export board report", || {
+                if ui.menu_item("Markdown") {
+                    commands.trigger(ExportReport(ReportFormat::Markdown));
+                }
+                if ui.menu_item("HTML") {
+                    commands.trigger(ExportReport(ReportFormat::Html));
+                }
+            });
+            if ui.is_item_hovered() {
+                ui.tooltip_text(
+                    "Summarizes the board with a top-down thumbnail, for documentation",
+                );
+            }
+
+            if ui.menu_item("Export tile files") {
+                export_split_map(&mut commands);
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Writes every tile to its own file, for smaller diffs in git");
+            }
+
+            if ui.menu_item("Import tile files") {
+                import_split_map(&mut commands);
             }
 
             ui.separator();
@@ -354,22 +797,50 @@ fn draw_imgui(
         });
 
         ui.menu("Edit", || {
+            let undo_label = match file.undo_description() {
+                Some(description) => format!("Undo {description}###Undo"),
+                None => "Undo###Undo".to_string(),
+            };
             if ui
-                .menu_item_config("Undo")
+                .menu_item_config(undo_label)
                 .shortcut("Ctrl+Z")
                 .enabled(file.can_undo())
                 .build()
             {
                 file.undo(&mut commands);
+            } else if ui.is_item_hovered() {
+                hovered_history_range = file.undo_range();
             }
 
+            let redo_label = match file.redo_description() {
+                Some(description) => format!("Redo {description}###Redo"),
+                None => "Redo###Redo".to_string(),
+            };
             if ui
-                .menu_item_config("Redo")
-                .shortcut("Ctrl+Shift+Z")
+                .menu_item_config(redo_label)
+                .shortcut("Ctrl+Y")
                 .enabled(file.can_redo())
                 .build()
             {
                 file.redo(&mut commands);
+            } else if ui.is_item_hovered() {
+                hovered_history_range = file.redo_range();
+            }
+
+            ui.separator();
+
+            if ui.menu_item_config("Go to tile").shortcut("Ctrl+G").build() {
+                state.open_go_to_tile = true;
+            }
+
+            if ui.menu_item("Clear all tiles...") {
+                state.open_clear_map = true;
+            }
+
+            ui.separator();
+
+            if ui.menu_item("Settings") {
+                state.show_settings = true;
             }
         });
 
@@ -378,91 +849,825 @@ fn draw_imgui(
                 commands.trigger(PresetView::Player);
             }
 
-            if ui.menu_item("Center") {
+            if ui.menu_item("Fit to map") {
                 commands.trigger(PresetView::Center);
             }
 
-            if ui.menu_item_config("Selection").shortcut("Num .").build() {
+            if ui
+                .menu_item_config("Fit to selection")
+                .shortcut("Num .")
+                .build()
+            {
                 commands.trigger(PresetView::Selection);
             }
 
             if ui.menu_item_config("Top-down").shortcut("Num 7").build() {
                 commands.trigger(PresetView::TopDown);
             }
-        });
-    });
 
-    ui.window("Viewport").collapsible(true).build(|| {
-        if let Some(texture) = state.viewport_texture {
-            let dest_size = ui.content_region_avail();
-            if dest_size[0] < 1.0 || dest_size[1] < 1.0 {
-                return;
+            ui.separator();
+
+            if ui
+                .menu_item_config("Key gates")
+                .selected(settings.mesh.show_key_gates)
+                .build()
+            {
+                settings.mesh.show_key_gates = !settings.mesh.show_key_gates;
             }
-            let target_size = UVec2::new(dest_size[0] as u32, dest_size[1] as u32);
-            if images
-                .get(&viewport_target.texture)
-                .is_some_and(|i| i.size() != target_size)
+
+            if ui
+                .menu_item_config("Locked connections")
+                .selected(settings.show_locked_connections)
+                .build()
             {
-                let real_image = images.get_mut(&viewport_target.texture).unwrap();
-                real_image.resize_in_place(Extent3d {
-                    width: target_size.x,
-                    height: target_size.y,
-                    depth_or_array_layers: 1,
-                });
-                state.textures_to_free.push(texture);
-                state.viewport_texture = None;
+                settings.show_locked_connections = !settings.show_locked_connections;
             }
-            viewport_target.upper_left = ui.cursor_screen_pos().into();
-            viewport_target.size = dest_size.into();
-            ImguiImage::new(texture, dest_size).build(ui);
-        }
-    });
 
-    let mut open_item_picker = false;
-    ui.window("Map settings").collapsible(true).build(|| {
-        ui.text("Starting tile");
-        ui.same_line();
-        if ui.button("Select") {
-            commands.trigger(SelectForEditing {
-                object: EditObject::StartingTile,
-                exclusive: true,
-            });
-        }
-        let mut starting_tile = file.file.starting_tile.as_array();
-        if ui
-            .input_scalar_n("##Starting Tile", &mut starting_tile)
-            .step(1)
-            .build()
-        {
-            let starting_tile = file.in_bounds(starting_tile.into());
-            file.edit_map(&mut commands, MapEdit::StartingTile(starting_tile));
-        }
+            if ui
+                .menu_item_config("Connection edges")
+                .selected(settings.show_connection_edges)
+                .build()
+            {
+                settings.show_connection_edges = !settings.show_connection_edges;
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(
+                    "Green sides are open, red ones impassible, and yellow ones locked",
+                );
+            }
 
-        ui.spacing();
+            if ui
+                .menu_item_config("Scale reference")
+                .selected(settings.show_scale_reference)
+                .build()
+            {
+                settings.show_scale_reference = !settings.show_scale_reference;
+            }
+
+            if ui
+                .menu_item_config("Wireframe")
+                .shortcut("Alt+W")
+                .selected(wireframe.0)
+                .build()
+            {
+                wireframe.0 = !wireframe.0;
+            }
+
+            ui.separator();
+
+            if ui
+                .menu_item_config("Minimap")
+                .selected(minimap.shown)
+                .build()
+            {
+                minimap.shown = !minimap.shown;
+            }
+            if ui
+                .menu_item_config("Material palette")
+                .selected(state.show_material_palette)
+                .build()
+            {
+                state.show_material_palette = !state.show_material_palette;
+            }
+            if ui
+                .menu_item_config("Statistics")
+                .selected(state.show_statistics)
+                .build()
+            {
+                state.show_statistics = !state.show_statistics;
+            }
+        });
+    });
+    if history_highlight.0 != hovered_history_range {
+        history_highlight.0 = hovered_history_range;
+    }
 
+    if let Some(count) = file.take_merged_count() {
+        state.show_notice(format!("Merged {count} edits into one undo step"));
+    }
+    if let Some((_, timer)) = &mut state.status_notice
+        && timer.tick(time.delta()).is_finished()
+    {
+        state.status_notice = None;
+    }
+    ui.viewport_status_bar(|| {
         ui.text(format!(
             "Map size: {}x{}",
             file.file.data.cols(),
             file.file.data.rows()
         ));
-        if ui.button("Edit map bounds") {
-            commands.trigger(SelectForEditing {
-                object: EditObject::MapSize(Direction::West),
-                exclusive: true,
-            });
-            commands.trigger(SelectForEditing {
-                object: EditObject::MapSize(Direction::East),
-                exclusive: false,
-            });
-            commands.trigger(SelectForEditing {
-                object: EditObject::MapSize(Direction::North),
-                exclusive: false,
+        if let Some(range) = file.selected_range {
+            ui.separator();
+            let start = flip_pos(range.start);
+            if range.start == range.end {
+                ui.text(format!("Selected: ({}, {})", start.x, start.y));
+            } else {
+                let end = flip_pos(range.end);
+                ui.text(format!(
+                    "Selected: ({}, {}) to ({}, {})",
+                    start.x, start.y, end.x, end.y
+                ));
+            }
+        }
+        if let Some((text, _)) = &state.status_notice {
+            ui.separator();
+            ui.text_disabled(text);
+        }
+        let over_vertices = mesh_stats.vertices > settings.budget.max_vertices;
+        let over_entities = mesh_stats.entities > settings.budget.max_entities;
+        if over_vertices || over_entities {
+            ui.separator();
+            ui.text_colored([1.0, 0.6, 0.0, 1.0], "Map mesh is over budget");
+            if ui.is_item_clicked() {
+                state.show_statistics = true;
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip(|| {
+                    if over_vertices {
+                        ui.text(format!(
+                            "{} vertices, budget is {}",
+                            mesh_stats.vertices, settings.budget.max_vertices
+                        ));
+                    }
+                    if over_entities {
+                        ui.text(format!(
+                            "{} entities, budget is {}",
+                            mesh_stats.entities, settings.budget.max_entities
+                        ));
+                    }
+                    ui.text_disabled("Click for statistics");
+                });
+            }
+        }
+        if let Some((path, err)) = &load_errors.atlas {
+            ui.separator();
+            ui.text_colored(
+                [1.0, 0.6, 0.0, 1.0],
+                format!("Atlas failed to load: {}", path.display()),
+            );
+            if ui.is_item_hovered() {
+                ui.tooltip_text(err);
+            }
+        }
+    });
+
+    if state.show_settings {
+        let mut show_settings = true;
+        ui.window("Settings")
+            .opened(&mut show_settings)
+            .collapsible(true)
+            .build(|| {
+                ui.text("Map mesh");
+                ui.separator();
+                let mut mesh = settings.mesh.clone();
+                ui.checkbox("Merge neighboring identical ramps", &mut mesh.merge_ramps);
+                ui.checkbox(
+                    "Merge flat tiles into larger quads",
+                    &mut mesh.merge_flat_tops,
+                );
+                ui.checkbox("Generate trims", &mut mesh.show_trims);
+                let color_edit = |label, color: &mut [u8; 3]| {
+                    let mut value = color.map(|x| x as f32 / 255.0);
+                    if ui.color_edit3(label, &mut value) {
+                        *color = value.map(|x| (x * 255.0).round() as u8);
+                    }
+                };
+                color_edit("Block color", &mut mesh.block_color);
+                color_edit("Trim color", &mut mesh.trim_color);
+                if ui.button("Reset colors") {
+                    let defaults = MeshSettings::default();
+                    mesh.block_color = defaults.block_color;
+                    mesh.trim_color = defaults.trim_color;
+                }
+                if mesh != settings.mesh {
+                    settings.mesh = mesh;
+                }
+
+                ui.spacing();
+                ui.text("Viewport background");
+                ui.separator();
+                let mut background = settings.background.clone();
+                ui.checkbox("Show skybox", &mut background.show_skybox);
+                if !background.show_skybox {
+                    color_edit("Background color", &mut background.color);
+                }
+                if background != settings.background {
+                    settings.background = background;
+                }
+
+                ui.spacing();
+                ui.text("Coordinates");
+                ui.separator();
+                let mut origin_index = GridOrigin::VARIANTS
+                    .iter()
+                    .position(|&x| x == settings.grid_origin)
+                    .unwrap();
+                if ui.combo(
+                    "Origin corner",
+                    &mut origin_index,
+                    GridOrigin::VARIANTS,
+                    |&x| <GridOrigin as Into<&str>>::into(x).into(),
+                ) {
+                    settings.grid_origin = GridOrigin::VARIANTS[origin_index];
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "The corner tile positions count from. Maps are saved the same either way.",
+                    );
+                }
+
+                ui.spacing();
+                ui.text("Loading");
+                ui.separator();
+                let mut load = settings.load;
+                ui.checkbox("Flatten level ramps", &mut load.flatten_level_ramps);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Opens ramps whose ends are at the same height as flat tiles");
+                }
+                if load != settings.load {
+                    settings.load = load;
+                }
+
+                ui.spacing();
+                ui.text("Saving");
+                ui.separator();
+                let mut save = settings.save.clone();
+                ui.checkbox("Always write popup and coins", &mut save.write_empty_fields);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "Some game versions need every tile to have these, even when empty",
+                    );
+                }
+                ui.checkbox("Show changes before saving", &mut save.show_summary);
+                ui.checkbox(
+                    "Add missing .json extension",
+                    &mut save.append_json_extension,
+                );
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("The game only loads map files ending with .json");
+                }
+                if save != settings.save {
+                    settings.save = save;
+                }
+
+                ui.spacing();
+                ui.text("Autosave");
+                ui.separator();
+                let mut autosave = settings.autosave.clone();
+                ui.checkbox("Autosave while editing", &mut autosave.enabled);
+                ui.input_scalar("Interval (minutes)", &mut autosave.interval_minutes)
+                    .step(1)
+                    .build();
+                autosave.interval_minutes = autosave.interval_minutes.max(1);
+                ui.input_scalar("Snapshots to keep", &mut autosave.keep)
+                    .step(1)
+                    .build();
+                autosave.keep = autosave.keep.max(1);
+                if autosave != settings.autosave {
+                    settings.autosave = autosave;
+                }
+
+                ui.spacing();
+                ui.text("Gizmos");
+                ui.separator();
+                let mut gizmo = settings.gizmo.clone();
+                ui.checkbox(
+                    "Snap tile heights to neighbors",
+                    &mut gizmo.snap_to_neighbors,
+                );
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Highlighted in green while dragging when it engages");
+                }
+                ui.disabled(!gizmo.snap_to_neighbors, || {
+                    ui.input_scalar("Snap threshold", &mut gizmo.snap_threshold)
+                        .step(0.05)
+                        .display_format("%.2f")
+                        .build();
+                });
+                gizmo.snap_threshold = gizmo.snap_threshold.clamp(0.0, 1.0);
+                if gizmo != settings.gizmo {
+                    settings.gizmo = gizmo;
+                }
+
+                ui.spacing();
+                ui.text("Undo history");
+                ui.separator();
+                let mut history = settings.history.clone();
+                ui.input_scalar("Coalesce window (ms)", &mut history.coalesce_window_ms)
+                    .step(50)
+                    .build();
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "Height edits of the same tiles made this close together are undone as \
+                        one step. Set to 0 to keep every edit separate.",
+                    );
+                }
+                if history != settings.history {
+                    settings.history = history;
+                }
+
+                ui.spacing();
+                ui.text("Mesh export");
+                ui.separator();
+                let mut export = settings.export.clone();
+                ui.checkbox("Flip triangle winding", &mut export.flip_winding);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "Use clockwise front faces in exported meshes, for tools that render the \
+                        map inside out otherwise",
+                    );
+                }
+                if export != settings.export {
+                    settings.export = export;
+                }
+
+                ui.spacing();
+                ui.text("Performance budget");
+                ui.separator();
+                let mut budget = settings.budget.clone();
+                ui.input_scalar("Max vertices", &mut budget.max_vertices)
+                    .step(10_000)
+                    .build();
+                ui.input_scalar("Max entities", &mut budget.max_entities)
+                    .step(100)
+                    .build();
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Blocks, trims, and key gates are each an entity");
+                }
+                if ui.button("Reset budget") {
+                    budget = BudgetSettings::default();
+                }
+                if budget != settings.budget {
+                    settings.budget = budget;
+                }
+            });
+        state.show_settings = show_settings;
+    }
+
+    if minimap.shown {
+        if state
+            .minimap_texture
+            .is_none_or(|(_, revision)| revision != minimap.revision)
+        {
+            let texture = context.register_bevy_texture(minimap.image.clone());
+            if let Some((old_texture, _)) =
+                state.minimap_texture.replace((texture, minimap.revision))
+            {
+                state.textures_to_free.push(old_texture);
+            }
+        }
+
+        let mut show_minimap = true;
+        ui.window("Minimap")
+            .opened(&mut show_minimap)
+            .collapsible(true)
+            .always_auto_resize(true)
+            .build(|| {
+                let Some(((texture, _), map_size)) =
+                    state.minimap_texture.zip(file.file.map_size())
+                else {
+                    return;
+                };
+                const MINIMAP_SIZE: f32 = 256.0;
+                let scale = MINIMAP_SIZE / map_size.x.max(map_size.y) as f32;
+                ImguiImage::new(
+                    texture,
+                    [map_size.x as f32 * scale, map_size.y as f32 * scale],
+                )
+                .build(ui);
+
+                let origin = ui.item_rect_min();
+                let tile_center = |pos: MpsVec2| {
+                    [
+                        origin[0] + (pos.x as f32 + 0.5) * scale,
+                        origin[1] + (pos.y as f32 + 0.5) * scale,
+                    ]
+                };
+                let radius = (scale / 2.0).max(3.0);
+                let draw_list = ui.get_window_draw_list();
+                let mut marker = |pos: MpsVec2, color: [f32; 4]| {
+                    draw_list
+                        .add_circle(tile_center(pos), radius, color)
+                        .filled(true)
+                        .build();
+                };
+                marker(file.file.starting_tile, [0.2, 0.9, 0.3, 1.0]);
+                for &pos in &file.file.shop_warp_tiles {
+                    marker(pos, [0.2, 0.5, 0.9, 1.0]);
+                }
+                marker(file.file.star_warp_tile, [1.0, 0.9, 0.2, 1.0]);
+
+                if ui.is_item_hovered() {
+                    let mouse = ui.io().mouse_pos;
+                    let pos = MpsVec2::new(
+                        ((mouse[0] - origin[0]) / scale) as i32,
+                        ((mouse[1] - origin[1]) / scale) as i32,
+                    )
+                    .clamp(MpsVec2::ZERO, map_size - MpsVec2::ONE);
+                    let shown = flip_pos(pos);
+                    ui.tooltip_text(format!("({}, {})", shown.x, shown.y));
+                    if ui.is_item_clicked() {
+                        commands.trigger(PresetView::Transform(minimap_view(&file.file, pos)));
+                    }
+                }
+            });
+        minimap.shown = show_minimap;
+    }
+
+    if state.show_material_palette {
+        let mut show_material_palette = true;
+        ui.window("Material palette")
+            .opened(&mut show_material_palette)
+            .collapsible(true)
+            .always_auto_resize(true)
+            .build(|| {
+                let atlas = if assets.is_loaded(&file.loaded_textures.atlas.image) {
+                    state.atlas_texture
+                } else {
+                    state.missing_atlas_texture
+                };
+                let Some(atlas) = atlas else {
+                    return;
+                };
+                const PALETTE_SIZE: f32 = 384.0;
+                ImguiImage::new(atlas, [PALETTE_SIZE; 2]).build(ui);
+
+                let origin = ui.item_rect_min();
+                let active = &mut tool_options.material;
+                let (u1, v1, u2, v2) = active.to_uv_coords();
+                ui.get_window_draw_list()
+                    .add_rect(
+                        [origin[0] + u1 * PALETTE_SIZE, origin[1] + v1 * PALETTE_SIZE],
+                        [origin[0] + u2 * PALETTE_SIZE, origin[1] + v2 * PALETTE_SIZE],
+                        [1.0, 1.0, 0.0, 1.0],
+                    )
+                    .thickness(2.0)
+                    .build();
+
+                if ui.is_item_hovered() {
+                    let mouse = ui.io().mouse_pos;
+                    let u = (mouse[0] - origin[0]) / PALETTE_SIZE;
+                    let v = (mouse[1] - origin[1]) / PALETTE_SIZE;
+                    // Snap to the middle of the cell, so clicks on a cell's edge don't round over
+                    let u =
+                        ((u / MpsMaterial::U_INCREMENT).floor() + 0.5) * MpsMaterial::U_INCREMENT;
+                    let v =
+                        ((v / MpsMaterial::V_INCREMENT).floor() + 0.5) * MpsMaterial::V_INCREMENT;
+                    if let Some(material) = MpsMaterial::from_uv_coords(u, v) {
+                        ui.tooltip_text(format!("Material #{}", material.index()));
+                        if ui.is_item_clicked() {
+                            *active = material;
+                        }
+                    }
+                }
+                ui.text(format!("Active material: #{}", active.index()));
+                ui.text_disabled("Used by the Fill and Paint tools");
+            });
+        state.show_material_palette = show_material_palette;
+    }
+
+    if state.show_statistics {
+        let mut show_statistics = true;
+        ui.window("Statistics")
+            .opened(&mut show_statistics)
+            .collapsible(true)
+            .always_auto_resize(true)
+            .build(|| {
+                let map_size = file.file.data.cols() * file.file.data.rows();
+                ui.text(format!("Tiles: {} of {map_size}", mesh_stats.tiles));
+                ui.text(format!("Triangles: {}", mesh_stats.triangles));
+                let budgeted = |label, value: usize, budget: usize| {
+                    let text = format!("{label}: {value} / {budget}");
+                    if value > budget {
+                        ui.text_colored([1.0, 0.6, 0.0, 1.0], text);
+                    } else {
+                        ui.text(text);
+                    }
+                };
+                budgeted(
+                    "Vertices",
+                    mesh_stats.vertices,
+                    settings.budget.max_vertices,
+                );
+                budgeted(
+                    "Entities",
+                    mesh_stats.entities,
+                    settings.budget.max_entities,
+                );
+            });
+        state.show_statistics = show_statistics;
+    }
+
+    ui.window("Viewport").collapsible(true).build(|| {
+        if tabs.count() > 1
+            && let Some(_token) = ui.tab_bar("Open files")
+        {
+            // Keep selecting the active tab until imgui shows it, so that a tab opened or
+            // switched to from elsewhere isn't mistaken for the user clicking the old one
+            let syncing = state.shown_tab != Some(tabs.active());
+            let mut switch_to = None;
+            let mut close = None;
+            for index in 0..tabs.count() {
+                let flags = if syncing && index == tabs.active() {
+                    TabItemFlags::SET_SELECTED
+                } else {
+                    TabItemFlags::empty()
+                };
+                let tab = tabs.get(index, &file);
+                let label = format!(
+                    "{}{}###Tab {index}",
+                    tab.display_name(),
+                    if tab.dirty { "*" } else { "" }
+                );
+                let mut open = true;
+                if TabItem::new(label)
+                    .opened(&mut open)
+                    .flags(flags)
+                    .begin(ui)
+                    .is_some()
+                {
+                    if index == tabs.active() {
+                        state.shown_tab = Some(index);
+                    } else if !syncing {
+                        switch_to = Some(index);
+                    }
+                }
+                if !open {
+                    close = Some(index);
+                }
+            }
+            if let Some(index) = close {
+                // Switch first so that the unsaved changes prompt is about the closed tab
+                tabs.switch_to(&mut commands, &mut file, index);
+                state.request_close_file(|commands, _| {
+                    commands.trigger(CloseTab {
+                        close_window: false,
+                    });
+                });
+            } else if let Some(index) = switch_to {
+                tabs.switch_to(&mut commands, &mut file, index);
+            }
+        }
+
+        for (index, &variant) in Tool::VARIANTS.iter().enumerate() {
+            if index > 0 {
+                ui.same_line();
+            }
+            let label: &str = variant.into();
+            if ui
+                .selectable_config(label)
+                .selected(tool.0 == variant)
+                .size([ui.calc_text_size(label)[0], 0.0])
+                .build()
+            {
+                tool.0 = variant;
+            }
+        }
+
+        if let Some(texture) = state.viewport_texture {
+            let dest_size = ui.content_region_avail();
+            if dest_size[0] < 1.0 || dest_size[1] < 1.0 {
+                return;
+            }
+            let target_size = UVec2::new(dest_size[0] as u32, dest_size[1] as u32);
+            if images
+                .get(&viewport_target.texture)
+                .is_some_and(|i| i.size() != target_size)
+            {
+                let real_image = images.get_mut(&viewport_target.texture).unwrap();
+                real_image.resize_in_place(Extent3d {
+                    width: target_size.x,
+                    height: target_size.y,
+                    depth_or_array_layers: 1,
+                });
+                state.textures_to_free.push(texture);
+                state.viewport_texture = None;
+            }
+            viewport_target.upper_left = ui.cursor_screen_pos().into();
+            viewport_target.size = dest_size.into();
+            ImguiImage::new(texture, dest_size).build(ui);
+            if tool.0 != Tool::Select && ui.is_item_hovered() {
+                ui.set_mouse_cursor(Some(MouseCursor::Hand));
+            }
+            // Dragging with the right mouse button orbits the camera instead
+            if file.selected_range.is_some()
+                && ui.is_item_hovered()
+                && ui.is_mouse_released(imgui::MouseButton::Right)
+                && ui.mouse_drag_delta_with_button(imgui::MouseButton::Right) == [0.0; 2]
+            {
+                ui.open_popup("Tile actions");
+            }
+            ui.popup("Tile actions", || {
+                let mode = state.tile_edit_mode;
+                if ui.menu_item_config("Flip ramps").shortcut("Alt+F").build() {
+                    edit_selected_ramps(
+                        &mut commands,
+                        &mut file,
+                        mode,
+                        TileHeight::with_flipped_heights,
+                    );
+                }
+                if ui.menu_item_config("Turn ramps").shortcut("Alt+R").build() {
+                    edit_selected_ramps(
+                        &mut commands,
+                        &mut file,
+                        mode,
+                        TileHeight::with_turned_ramp,
+                    );
+                }
+            });
+        }
+    });
+
+    if tool.0 != Tool::Select {
+        ui.window("Tool options").collapsible(true).build(|| {
+            let label: &str = tool.0.into();
+            ui.text(label);
+            ui.separator();
+
+            let mut symmetry_index = Symmetry::VARIANTS
+                .iter()
+                .position(|&x| x == state.tile_edit_mode.symmetry)
+                .unwrap();
+            if ui.combo("Symmetry", &mut symmetry_index, Symmetry::VARIANTS, |&x| {
+                <Symmetry as Into<&str>>::into(x).into()
+            }) {
+                state.tile_edit_mode.symmetry = Symmetry::VARIANTS[symmetry_index];
+            }
+
+            let picking = &mut tool_options.picking;
+            ui.checkbox("Pick tiles on a plane", &mut picking.use_plane);
+            if ui.is_item_hovered() {
+                ui.tooltip_text(
+                    "Clicks pick the tile under the cursor at a fixed height, ignoring the terrain",
+                );
+            }
+            if picking.use_plane {
+                ui.input_float("Plane height", &mut picking.plane_height)
+                    .step(0.25)
+                    .build();
+                if let Some(range) = file.selected_range
+                    && ui.button("Use selection height")
+                {
+                    picking.plane_height = file.file[range.start].height.center_height() as f32;
+                }
+            }
+            ui.separator();
+
+            match tool.0 {
+                Tool::Select => {}
+                Tool::Eyedropper => {
+                    let options = &mut tool_options.eyedropper;
+                    let mut sample_index = EyedropperSample::VARIANTS
+                        .iter()
+                        .position(|&x| x == options.sample)
+                        .unwrap();
+                    if ui.combo(
+                        "Copy",
+                        &mut sample_index,
+                        EyedropperSample::VARIANTS,
+                        |&x| <EyedropperSample as Into<&str>>::into(x).into(),
+                    ) {
+                        options.sample = EyedropperSample::VARIANTS[sample_index];
+                    }
+                    ui.checkbox("Return to Select after use", &mut options.return_to_select);
+                }
+                Tool::Fill | Tool::Paint => {
+                    let options = &mut *tool_options;
+                    if let Some(atlas) = state.atlas_texture {
+                        let (u1, v1, u2, v2) = options.material.to_uv_coords();
+                        ImguiImage::new(atlas, [32.0; 2])
+                            .uv0([u1, v1])
+                            .uv1([u2, v2])
+                            .build(ui);
+                        ui.same_line();
+                    }
+                    let mut index = options.material.index();
+                    if ui.input_scalar("Material", &mut index).step(1).build()
+                        && let Some(material) = MpsMaterial::from_index(index)
+                    {
+                        options.material = material;
+                    }
+                    if let Some(range) = file.selected_range
+                        && ui.button("Use selected material")
+                    {
+                        options.material = file.file[range.start].materials.material;
+                    }
+                    if tool.0 == Tool::Fill {
+                        ui.text_disabled("Shortcut: Ctrl+B");
+                    }
+                }
+                Tool::Measure => {
+                    let position = |pos: MpsVec2| {
+                        let pos = flip_pos(pos);
+                        format!("({}, {})", pos.x, pos.y)
+                    };
+                    match (measurement.start, measurement.end) {
+                        (None, _) => ui.text_disabled("Click a tile to start measuring"),
+                        (Some(start), None) => {
+                            ui.text(format!("From {}", position(start)));
+                            ui.text_disabled("Click a second tile");
+                        }
+                        (Some(start), Some(end)) => {
+                            ui.text(format!("From {} to {}", position(start), position(end)));
+                            if let (Some(manhattan), Some(euclidean), Some(path_tiles)) = (
+                                measurement.manhattan(),
+                                measurement.euclidean(),
+                                measurement.path_tiles(),
+                            ) {
+                                ui.text(format!("Distance along rows and columns: {manhattan}"));
+                                ui.text(format!("Straight-line distance: {euclidean:.2}"));
+                                ui.text(format!("Tiles along path: {path_tiles}"));
+                            }
+                            ui.text_disabled("Click a third time to start over");
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    let mut open_item_picker = false;
+    let mut open_resize_dialog = false;
+    ui.window("Map settings").collapsible(true).build(|| {
+        ui.text("Starting tile");
+        ui.same_line();
+        if ui.button("Select") {
+            commands.trigger(SelectForEditing {
+                object: EditObject::StartingTile,
+                exclusive: true,
+            });
+        }
+        let mut starting_tile = flip_pos(file.file.starting_tile).as_array();
+        if ui
+            .input_scalar_n("##Starting Tile", &mut starting_tile)
+            .step(1)
+            .build()
+        {
+            let starting_tile = file.in_bounds(flip_pos(starting_tile.into()));
+            file.edit_map(&mut commands, MapEdit::StartingTile(starting_tile));
+        }
+
+        ui.spacing();
+
+        ui.text(format!(
+            "Map size: {}x{}",
+            file.file.data.cols(),
+            file.file.data.rows()
+        ));
+        ui.same_line();
+        if ui.button("Resize") {
+            state.resize_size = [file.file.data.cols() as i32, file.file.data.rows() as i32];
+            open_resize_dialog = true;
+        }
+        if ui.button("Edit map bounds") {
+            commands.trigger(SelectForEditing {
+                object: EditObject::MapSize(Direction::West),
+                exclusive: true,
+            });
+            commands.trigger(SelectForEditing {
+                object: EditObject::MapSize(Direction::East),
+                exclusive: false,
+            });
+            commands.trigger(SelectForEditing {
+                object: EditObject::MapSize(Direction::North),
+                exclusive: false,
             });
             commands.trigger(SelectForEditing {
                 object: EditObject::MapSize(Direction::South),
                 exclusive: false,
             });
         }
+        if let Some(_token) = ui.tree_node("Sides") {
+            ui.checkbox(
+                "Copy the edge when expanding",
+                &mut state.expand_duplicates_edge,
+            );
+            for &side in Direction::ALL_CLOCKWISE {
+                let _id = ui.push_id(side.as_ref());
+                let (rows, cols) = file.file.data.size();
+                let length = match side {
+                    Direction::West | Direction::East => cols,
+                    Direction::North | Direction::South => rows,
+                };
+                // The map can't be shrunk past a single row or column
+                ui.disabled(length < 2, || {
+                    if ui.small_button("-") {
+                        if file.shrink_loses_data(side, 1) {
+                            state.request_shrink(side, 1);
+                        } else {
+                            file.shrink_map(&mut commands, side, 1);
+                        }
+                    }
+                });
+                ui.same_line();
+                if ui.small_button("+") {
+                    file.expand_map(&mut commands, side, 1, state.expand_duplicates_edge);
+                }
+                ui.same_line();
+                ui.text(side.as_ref());
+            }
+        }
 
         ui.spacing();
 
@@ -470,6 +1675,14 @@ fn draw_imgui(
             "bmp", "gif", "hdr", "ico", "jpg", "jpeg", "ktx2", "png", "tif", "tiff", "webp",
         ];
 
+        let mut absolute_paths = file.absolute_texture_paths();
+        if ui.checkbox("Absolute texture paths", &mut absolute_paths) {
+            file.set_absolute_texture_paths(&mut commands, absolute_paths);
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Saves texture paths as they are instead of relative to the map file");
+        }
+
         if let Some(atlas) = state.atlas_texture
             && let Some(_token) = ui
                 .tree_node_config("Atlas")
@@ -480,7 +1693,7 @@ fn draw_imgui(
             if ui.button("Reload##Reload Atlas") {
                 let texture = &file.loaded_textures.atlas;
                 assets.reload(texture.path.clone());
-                commands.trigger(MapEdited(MapEdit::Atlas(texture.clone())));
+                commands.trigger(TextureReloaded::Atlas);
             }
             if ui.image_button("Select Atlas", atlas, [256.0; 2]) {
                 commands
@@ -517,7 +1730,7 @@ fn draw_imgui(
                 {
                     let texture = &file.loaded_textures.skybox[index];
                     assets.reload(texture.path.clone());
-                    commands.trigger(MapEdited(MapEdit::Skybox(index, texture.clone())));
+                    commands.trigger(TextureReloaded::Skybox(index));
                 }
                 ui.same_line();
                 ui.text(label);
@@ -602,13 +1815,13 @@ fn draw_imgui(
                     visible: state.preview_star_warp_tile,
                 });
             }
-            let mut star_warp_tile = file.file.star_warp_tile.as_array();
+            let mut star_warp_tile = flip_pos(file.file.star_warp_tile).as_array();
             if ui
                 .input_scalar_n("##Star warp tile", &mut star_warp_tile)
                 .step(1)
                 .build()
             {
-                let star_warp_tile = file.in_bounds(star_warp_tile.into());
+                let star_warp_tile = file.in_bounds(flip_pos(star_warp_tile.into()));
                 file.edit_map(&mut commands, MapEdit::StarWarpTile(star_warp_tile));
             }
 
@@ -649,13 +1862,13 @@ fn draw_imgui(
                         }
                     });
 
-                    let mut current_tile = shop_hop.as_array();
+                    let mut current_tile = flip_pos(shop_hop).as_array();
                     if ui
                         .input_scalar_n(format!("##Shop hop {index}"), &mut current_tile)
                         .step(1)
                         .build()
                     {
-                        let current_tile = file.in_bounds(current_tile.into());
+                        let current_tile = file.in_bounds(flip_pos(current_tile.into()));
                         edit = Some(MapEdit::ShopWarpTile(index, ListEdit::Set(current_tile)));
                     }
 
@@ -705,13 +1918,13 @@ fn draw_imgui(
                     visible: state.preview_podium,
                 });
             }
-            let mut podium_pos = file.file.podium_position.as_array();
+            let mut podium_pos = flip_pos(file.file.podium_position).as_array();
             if ui
                 .input_scalar_n("##Podium", &mut podium_pos)
                 .step(1)
                 .build()
             {
-                let podium_pos = file.in_bounds(podium_pos.into());
+                let podium_pos = file.in_bounds(flip_pos(podium_pos.into()));
                 file.edit_map(&mut commands, MapEdit::PodiumPosition(podium_pos));
             }
 
@@ -856,10 +2069,13 @@ fn draw_imgui(
 
         let single_tile = (range.start == range.end).then_some(range.start);
         if single_tile.is_some() {
-            ui.text(format!(
-                "Selected tile ({}, {})",
-                range.start.x, range.start.y
-            ));
+            let pos = flip_pos(range.start);
+            ui.text(format!("Selected tile ({}, {})", pos.x, pos.y));
+            ui.same_line();
+            ui.text_disabled(format!("#{}", file.file.tile_index(range.start)));
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Row-major tile index, as used by scripts");
+            }
         } else {
             ui.text(format!(
                 "Selected {} tiles",
@@ -867,13 +2083,93 @@ fn draw_imgui(
             ));
         }
 
-        macro_rules! simple_combo_box {
-            (
-                label: $label:expr,
-                getter: ($($getter:tt)+),
-                options: [$($option:expr),* $(,)?],
-                option_labels: {
-                    $($option_pat:pat => $option_display:expr,)*
+        ui.disabled(single_tile.is_none(), || {
+            let pos = file.in_bounds(range.start);
+            let mut edit = None;
+            if ui.button("Set as start") {
+                edit = Some(MapEdit::StartingTile(pos));
+            }
+            ui.same_line();
+            if ui.button("Add as shop warp") {
+                edit = Some(MapEdit::ShopWarpTile(
+                    file.file.shop_warp_tiles.len(),
+                    ListEdit::Insert(pos),
+                ));
+            }
+            ui.same_line();
+            if ui.button("Set as star warp") {
+                edit = Some(MapEdit::StarWarpTile(pos));
+            }
+            ui.same_line();
+            if ui.button("Set as podium") {
+                edit = Some(MapEdit::PodiumPosition(pos));
+            }
+            if let Some(edit) = edit {
+                file.edit_map(&mut commands, edit);
+            }
+        });
+
+        if ui.button("Mirror west/east") {
+            file.edit_map(
+                &mut commands,
+                MapEdit::TransformTiles(range, TileTransform::MirrorX),
+            );
+        }
+        ui.same_line();
+        if ui.button("Mirror north/south") {
+            file.edit_map(
+                &mut commands,
+                MapEdit::TransformTiles(range, TileTransform::MirrorY),
+            );
+        }
+        ui.disabled(!range.is_square(), || {
+            if ui.button("Rotate clockwise") {
+                file.edit_map(
+                    &mut commands,
+                    MapEdit::TransformTiles(range, TileTransform::RotateClockwise),
+                );
+            }
+            ui.same_line();
+            if ui.button("Rotate counter-clockwise") {
+                file.edit_map(
+                    &mut commands,
+                    MapEdit::TransformTiles(range, TileTransform::RotateCounterClockwise),
+                );
+            }
+        });
+        if !range.is_square() {
+            ui.text_disabled("Only square selections can be rotated");
+        }
+
+        let mut symmetry_index = Symmetry::VARIANTS
+            .iter()
+            .position(|&x| x == state.tile_edit_mode.symmetry)
+            .unwrap();
+        if ui.combo("Symmetry", &mut symmetry_index, Symmetry::VARIANTS, |&x| {
+            <Symmetry as Into<&str>>::into(x).into()
+        }) {
+            state.tile_edit_mode.symmetry = Symmetry::VARIANTS[symmetry_index];
+        }
+        ui.checkbox(
+            "Smart connections",
+            &mut state.tile_edit_mode.smart_connections,
+        );
+        ui.checkbox(
+            "Symmetric connections",
+            &mut state.tile_edit_mode.symmetric_connections,
+        );
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Also sets the facing side of the neighboring tiles");
+        }
+        let mode = state.tile_edit_mode;
+
+        macro_rules! simple_combo_box {
+            (
+                label: $label:expr,
+                getter: ($($getter:tt)+),
+                options: [$($option:expr),* $(,)?],
+                option_labels: {
+                    $($option_pat:pat => $option_display:expr,)*
                 },
                 editor: |$editor_var:ident| $editor:expr,
             ) => {{
@@ -919,7 +2215,7 @@ fn draw_imgui(
                 Some(TileRampDirection::Horizontal) => "West/East Ramp",
                 Some(TileRampDirection::Vertical) => "North/South Ramp",
             },
-            editor: |new_type| file.change_heights(&mut commands, range, |h| h.with_ramp_dir(new_type)),
+            editor: |new_type| file.change_heights(&mut commands, range, mode, |h| h.with_ramp_dir(new_type)),
         );
 
         let height_input = |label, mut value: Option<_>| {
@@ -953,7 +2249,7 @@ fn draw_imgui(
                     .all_equal_value()
                     .ok();
                 if let Some(height) = height_input("Height", height) {
-                    file.edit_map(
+                    file.edit_tiles(
                         &mut commands,
                         MapEdit::ChangeHeight(
                             range,
@@ -965,6 +2261,7 @@ fn draw_imgui(
                                 range.area()
                             ],
                         ),
+                        mode,
                     );
                 }
             }
@@ -984,17 +2281,172 @@ fn draw_imgui(
                     .all_equal_value()
                     .ok();
                 if let Some(height) = height_input(neg_label, neg_height) {
-                    file.change_heights(&mut commands, range, |h| h.with_neg_height(height));
+                    file.change_heights(&mut commands, range, mode, |h| h.with_neg_height(height));
                 }
                 if let Some(height) = height_input(pos_label, pos_height) {
-                    file.change_heights(&mut commands, range, |h| h.with_pos_height(height));
+                    file.change_heights(&mut commands, range, mode, |h| h.with_pos_height(height));
                 }
-                if ui.button("Flip") {
-                    file.change_heights(&mut commands, range, TileHeight::with_flipped_heights);
+            }
+        }
+
+        if range.into_iter().any(|x| file.file[x].ramp()) {
+            if ui.button("Flip ramps") {
+                edit_selected_ramps(&mut commands, &mut file, mode, TileHeight::with_flipped_heights);
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Swaps the high and low ends of each ramp (Alt+F)");
+            }
+            ui.same_line();
+            if ui.button("Turn ramps") {
+                edit_selected_ramps(&mut commands, &mut file, mode, TileHeight::with_turned_ramp);
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Switches each ramp between west/east and north/south (Alt+R)");
+            }
+            if ui.button("Snap ramps to neighbors") {
+                let count = file.snap_ramps(&mut commands, range, mode);
+                state.show_notice(format!(
+                    "Adjusted {count} ramp{}",
+                    if count == 1 { "" } else { "s" }
+                ));
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Moves the ends of each ramp to meet the tiles before and after it");
+            }
+        }
+
+        if let Some(_token) = ui
+            .tree_node_config("Set height")
+            .framed(true)
+            .tree_push_on_open(false)
+            .push()
+        {
+            let settings = &mut state.set_height;
+            ui.checkbox("Relative", &mut settings.relative);
+            if ui.is_item_hovered() {
+                ui.tooltip_text("Raises or lowers each tile instead of flattening them");
+            }
+            ui.input_scalar(
+                if settings.relative { "Change" } else { "Target height" },
+                &mut settings.height,
+            )
+            .step(0.25)
+            .step_fast(1.0)
+            .display_format("%.2f")
+            .build();
+            let min = if settings.relative {
+                -SET_HEIGHT_LIMIT
+            } else {
+                settings.height = settings.height.max(0.0);
+                0.0
+            };
+            let valid = (min..=SET_HEIGHT_LIMIT).contains(&settings.height);
+            if !valid {
+                ui.text_colored(
+                    [1.0, 0.3, 0.3, 1.0],
+                    format!("Must be between {min} and {SET_HEIGHT_LIMIT}"),
+                );
+            }
+            ui.disabled(!valid, || {
+                if ui.button("Apply to selection") {
+                    let edit = if settings.relative {
+                        MapEdit::AdjustHeight(range, settings.height)
+                    } else {
+                        MapEdit::ChangeHeight(
+                            range,
+                            vec![
+                                TileHeight::Flat {
+                                    ramp: MustBeBool,
+                                    height: settings.height,
+                                };
+                                range.area()
+                            ],
+                        )
+                    };
+                    file.edit_tiles(&mut commands, edit, mode);
                 }
+            });
+        }
+
+        let corridor = single_tile.is_none()
+            && (range.start.x == range.end.x || range.start.y == range.end.y);
+        if corridor
+            && let Some(_token) = ui
+                .tree_node_config("Ramp profile")
+                .framed(true)
+                .tree_push_on_open(false)
+                .push()
+        {
+            let profile = &mut state.ramp_profile;
+            ui.text(format!(
+                "From {:.2} to {:.2}",
+                file.file[range.start].height.neg_height(),
+                file.file[range.end].height.pos_height(),
+            ));
+            if ui.radio_button_bool("Smooth slope", !profile.staircase) {
+                profile.staircase = false;
+            }
+            ui.same_line();
+            if ui.radio_button_bool("Staircase", profile.staircase) {
+                profile.staircase = true;
+            }
+            if profile.staircase {
+                ui.input_scalar("Step size", &mut profile.step)
+                    .step(0.25)
+                    .display_format("%.2f")
+                    .build();
+                profile.step = profile.step.max(0.25);
+            }
+            if ui.button("Apply profile") {
+                let step = profile.staircase.then_some(profile.step);
+                file.apply_ramp_profile(&mut commands, range, step, mode);
             }
         }
 
+        if single_tile.is_none()
+            && let Some(_token) = ui
+                .tree_node_config("Shared slope")
+                .framed(true)
+                .tree_push_on_open(false)
+                .push()
+        {
+            let slope = &mut state.shared_slope;
+            let mut uphill_index = Direction::ALL_CLOCKWISE
+                .iter()
+                .position(|&x| x == slope.uphill)
+                .unwrap();
+            if ui.combo("Uphill", &mut uphill_index, Direction::ALL_CLOCKWISE, |x| {
+                <Direction as AsRef<str>>::as_ref(x).into()
+            }) {
+                slope.uphill = Direction::ALL_CLOCKWISE[uphill_index];
+            }
+            ui.input_scalar("Rise per tile", &mut slope.rise)
+                .step(0.25)
+                .display_format("%.2f")
+                .build();
+            ui.input_scalar("Start height", &mut slope.start_height)
+                .step(0.25)
+                .display_format("%.2f")
+                .build();
+            ui.text_disabled(format!(
+                "{:.1} degrees",
+                slope.rise.atan().to_degrees()
+            ));
+            // A flat slope would turn every tile into a degenerate ramp
+            ui.disabled(slope.rise <= 0.0, || {
+                if ui.button("Apply slope") {
+                    file.apply_shared_slope(
+                        &mut commands,
+                        range,
+                        slope.uphill,
+                        slope.rise,
+                        slope.start_height,
+                        mode,
+                    );
+                }
+            });
+        }
+
         ui.spacing();
 
         if let Some(atlas) = state.atlas_texture
@@ -1021,15 +2473,28 @@ fn draw_imgui(
                 } else {
                     ui.button_with_size(id, MATERIAL_PREVIEW_SIZE)
                 };
+                if let Some(material) = common_material
+                    && ui.is_item_hovered()
+                {
+                    ui.tooltip_text(format!("Material #{}", material.index()));
+                }
                 if clicked {
                     state.material_target = Some((range, location));
+                    state
+                        .material_picker
+                        .open(common_material.map_or(0, MpsMaterial::index));
                     open_material_picker = true;
                 }
+                common_material
             };
 
-            material_button(Cow::Borrowed("Top material"), None);
+            let top_material = material_button(Cow::Borrowed("Top material"), None);
             ui.same_line();
-            ui.text("Top material");
+            if let Some(material) = top_material {
+                ui.text(format!("Top material #{}", material.index()));
+            } else {
+                ui.text("Top material");
+            }
 
             let mut edit = None;
             for side in Direction::ALL_CLOCKWISE {
@@ -1113,6 +2578,7 @@ fn draw_imgui(
                 file.edit_map(&mut commands, edit);
                 if matches!(material, ListEdit::Insert(_)) {
                     state.material_target = Some((range, location));
+                    state.material_picker.open(MpsMaterial::default().index());
                     open_material_picker = true;
                 }
             }
@@ -1138,9 +2604,39 @@ fn draw_imgui(
                         Connection::Unconditional(true) => "Passable",
                         Connection::Conditional(ConnectionCondition::Lock) => "Locked gate",
                     },
-                    editor: |new_type| file.edit_map(
+                    editor: |new_type| file.edit_tiles(
                         &mut commands,
                         MapEdit::ChangeConnection(range, *direction, vec![new_type; range.area()]),
+                        mode,
+                    ),
+                );
+            }
+        }
+
+        if let Some(_token) = ui
+            .tree_node_config("Wall visibility")
+            .framed(true)
+            .tree_push_on_open(false)
+            .push()
+        {
+            for direction in Direction::ALL_CLOCKWISE {
+                simple_combo_box!(
+                    label: direction,
+                    getter: (.wall_visibility[*direction]),
+                    options: [
+                        WallVisibility::Automatic,
+                        WallVisibility::Hidden,
+                        WallVisibility::Shown,
+                    ],
+                    option_labels: {
+                        WallVisibility::Automatic => "Automatic",
+                        WallVisibility::Hidden => "Force hide",
+                        WallVisibility::Shown => "Force show",
+                    },
+                    editor: |new_visibility| file.edit_tiles(
+                        &mut commands,
+                        MapEdit::ChangeWallVisibility(range, *direction, vec![new_visibility; range.area()]),
+                        mode,
                     ),
                 );
             }
@@ -1167,7 +2663,7 @@ fn draw_imgui(
                 coins.is_some()
             };
             if coins_changed {
-                file.edit_map(&mut commands, MapEdit::ChangeCoins(range, vec![coins.unwrap(); range.area()]));
+                file.edit_tiles(&mut commands, MapEdit::ChangeCoins(range, vec![coins.unwrap(); range.area()]), mode);
             }
 
             let mut walk_over = range
@@ -1176,7 +2672,7 @@ fn draw_imgui(
                 .all_equal_value()
                 .ok();
             if ui.checkbox_tri_state("Popup triggered by walk over", &mut walk_over) {
-                file.edit_map(&mut commands, MapEdit::ChangeWalkOver(range, vec![walk_over.unwrap(); range.area()]));
+                file.edit_tiles(&mut commands, MapEdit::ChangeWalkOver(range, vec![walk_over.unwrap(); range.area()]), mode);
             }
 
             let mut silver_star_spawnable = range
@@ -1185,7 +2681,7 @@ fn draw_imgui(
                 .all_equal_value()
                 .ok();
             if ui.checkbox_tri_state("Spawn silver stars", &mut silver_star_spawnable) {
-                file.edit_map(&mut commands, MapEdit::ChangeSilverStarSpawnable(range, vec![silver_star_spawnable.unwrap(); range.area()]));
+                file.edit_tiles(&mut commands, MapEdit::ChangeSilverStarSpawnable(range, vec![silver_star_spawnable.unwrap(); range.area()]), mode);
             }
 
             let popup = simple_combo_box!(
@@ -1209,9 +2705,10 @@ fn draw_imgui(
                     Some(PopupType::StarSteal) => "Star steal",
                     Some(PopupType::Shop(shop)) => <ShopNumber as Into<&str>>::into(shop),
                 },
-                editor: |new_popup| file.edit_map(
+                editor: |new_popup| file.edit_tiles(
                     &mut commands,
-                    MapEdit::ChangePopupType(range, vec![new_popup; range.area()])
+                    MapEdit::ChangePopupType(range, vec![new_popup; range.area()]),
+                    mode,
                 ),
             );
 
@@ -1221,12 +2718,179 @@ fn draw_imgui(
         }
     });
 
+    ui.window("Map issues").collapsible(true).build(|| {
+        if issues.is_empty() {
+            ui.text("No issues found");
+            return;
+        }
+
+        if !issues.floating_tiles.is_empty()
+            && let Some(_token) = ui
+                .tree_node_config(format!(
+                    "Floating tiles ({})###Floating tiles",
+                    issues.floating_tiles.len()
+                ))
+                .framed(true)
+                .tree_push_on_open(false)
+                .default_open(true)
+                .push()
+        {
+            let delete = |file: &mut LoadedFile, commands: &mut Commands, pos: MpsVec2| {
+                file.edit_map(
+                    commands,
+                    MapEdit::ReplaceTiles(
+                        TileRange {
+                            start: pos,
+                            end: pos,
+                        },
+                        vec![TileData::default()],
+                    ),
+                );
+            };
+
+            if ui.button("Delete all") {
+                for &pos in &issues.floating_tiles {
+                    delete(&mut file, &mut commands, pos);
+                }
+            }
+            ui.same_line();
+            if ui.button("Keep all") {
+                let floating = mem::take(&mut issues.floating_tiles);
+                issues.kept_floating_tiles.extend(floating);
+            }
+
+            let mut kept = None;
+            for (index, &pos) in issues.floating_tiles.iter().enumerate() {
+                let shown = flip_pos(pos);
+                if ui
+                    .selectable_config(format!("({}, {})##Floating tile {index}", shown.x, shown.y))
+                    .size([100.0, 0.0])
+                    .build()
+                {
+                    commands.trigger(SelectForEditing {
+                        object: EditObject::Tile(pos),
+                        exclusive: true,
+                    });
+                }
+                ui.same_line();
+                if ui.small_button(format!("Delete##Floating tile {index}")) {
+                    delete(&mut file, &mut commands, pos);
+                }
+                ui.same_line();
+                if ui.small_button(format!("Keep##Floating tile {index}")) {
+                    kept = Some(index);
+                }
+            }
+            if let Some(index) = kept {
+                let pos = issues.floating_tiles.remove(index);
+                issues.kept_floating_tiles.push(pos);
+            }
+        }
+
+        if !issues.overlapping_positions.is_empty()
+            && let Some(_token) = ui
+                .tree_node_config(format!(
+                    "Overlapping special positions ({})###Overlapping special positions",
+                    issues.overlapping_positions.len()
+                ))
+                .framed(true)
+                .tree_push_on_open(false)
+                .default_open(true)
+                .push()
+        {
+            for (index, (pos, names)) in issues.overlapping_positions.iter().enumerate() {
+                let shown = flip_pos(*pos);
+                if ui.selectable(format!(
+                    "({}, {}): {}##Overlapping position {index}",
+                    shown.x,
+                    shown.y,
+                    names.join(", ")
+                )) {
+                    commands.trigger(SelectForEditing {
+                        object: EditObject::Tile(*pos),
+                        exclusive: true,
+                    });
+                }
+            }
+        }
+
+        if !issues.degenerate_ramps.is_empty()
+            && let Some(_token) = ui
+                .tree_node_config(format!(
+                    "Degenerate ramps ({})###Degenerate ramps",
+                    issues.degenerate_ramps.len()
+                ))
+                .framed(true)
+                .tree_push_on_open(false)
+                .default_open(true)
+                .push()
+        {
+            let flatten = |file: &mut LoadedFile, commands: &mut Commands, pos: MpsVec2| {
+                let height = file.file[pos].height.center_height();
+                file.edit_map(
+                    commands,
+                    MapEdit::ChangeHeight(
+                        TileRange {
+                            start: pos,
+                            end: pos,
+                        },
+                        vec![TileHeight::Flat {
+                            ramp: MustBeBool,
+                            height: if height.is_finite() { height } else { 0.0 },
+                        }],
+                    ),
+                );
+            };
+
+            if ui.button("Convert all to flat") {
+                for &pos in &issues.degenerate_ramps {
+                    flatten(&mut file, &mut commands, pos);
+                }
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(format!(
+                    "Ramps without a slope or rising more than {MAX_RAMP_RISE} per tile"
+                ));
+            }
+
+            for (index, &pos) in issues.degenerate_ramps.iter().enumerate() {
+                let shown = flip_pos(pos);
+                if ui
+                    .selectable_config(format!(
+                        "({}, {})##Degenerate ramp {index}",
+                        shown.x, shown.y
+                    ))
+                    .size([100.0, 0.0])
+                    .build()
+                {
+                    commands.trigger(SelectForEditing {
+                        object: EditObject::Tile(pos),
+                        exclusive: true,
+                    });
+                }
+                ui.same_line();
+                if ui.small_button(format!("Convert to flat##Degenerate ramp {index}")) {
+                    flatten(&mut file, &mut commands, pos);
+                }
+            }
+        }
+    });
+
     if open_item_picker {
         ui.open_popup("Item picker");
     }
+    if open_resize_dialog {
+        ui.open_popup("Resize map");
+    }
     if open_material_picker {
         ui.open_popup("Material picker");
     }
+    if mem::take(&mut state.open_go_to_tile) {
+        ui.open_popup("Go to tile");
+    }
+    if mem::take(&mut state.open_clear_map) {
+        ui.open_popup("Clear all tiles?");
+    }
 
     viewport_target.disable_input = false;
 
@@ -1256,6 +2920,12 @@ fn draw_imgui(
     {
         ui.popup("Material picker", || {
             viewport_target.disable_input = true;
+            let picked_with_keys = state.material_picker.handle_keys(ui);
+            ui.text_disabled(if state.material_picker.typed_index.is_empty() {
+                "Arrow keys to move, Enter to pick, or type an index".to_string()
+            } else {
+                format!("Material #{}", state.material_picker.typed_index)
+            });
             let _style = ui.push_style_var(StyleVar::ItemSpacing([0.0, 0.0]));
             let _style = ui.push_style_var(StyleVar::FramePadding([0.0, 0.0]));
             for index in 0..MpsMaterial::TEXTURES_COUNT {
@@ -1265,19 +2935,27 @@ fn draw_imgui(
                 let material = MpsMaterial::from_index(index)
                     .expect("MpsMaterial::from_index out of sync with TEXTURES_COUNT");
                 let (u1, v1, u2, v2) = material.to_uv_coords();
-                if ui
+                let clicked = ui
                     .image_button_config(format!("Material {index}"), atlas, [32.0; 2])
                     .uv0([u1, v1])
                     .uv1([u2, v2])
-                    .build()
-                {
-                    file.edit_map(
+                    .build();
+                let focused = index == state.material_picker.focus;
+                if focused {
+                    ui.get_window_draw_list()
+                        .add_rect(ui.item_rect_min(), ui.item_rect_max(), [1.0, 1.0, 0.0, 1.0])
+                        .thickness(2.0)
+                        .build();
+                }
+                if clicked || (focused && picked_with_keys) {
+                    file.edit_tiles(
                         &mut commands,
                         MapEdit::ChangeMaterial(
                             target_range,
                             target_location,
                             vec![ListEdit::Set(material); target_range.area()],
                         ),
+                        state.tile_edit_mode,
                     );
                     ui.close_current_popup();
                 }
@@ -1285,6 +2963,80 @@ fn draw_imgui(
         });
     }
 
+    if export_tasks.is_running() {
+        ui.open_popup("Exporting");
+    }
+    ui.modal_popup("Exporting", || {
+        viewport_target.disable_input = true;
+        if !export_tasks.is_running() {
+            ui.close_current_popup();
+            return;
+        }
+        for path in export_tasks.paths() {
+            ui.text(format!("Writing {}...", path.display()));
+        }
+    });
+
+    if state.save_summary.is_some() {
+        ui.open_popup("Save changes");
+    }
+    ui.modal_popup("Save changes", || {
+        viewport_target.disable_input = true;
+        let Some(summary) = &state.save_summary else {
+            ui.close_current_popup();
+            return;
+        };
+        ui.text(format!(
+            "{} edit{} since the last save:",
+            summary.edits,
+            if summary.edits == 1 { "" } else { "s" }
+        ));
+        if summary.tiles_modified > 0 {
+            ui.bullet_text(format!(
+                "{} tile{} modified",
+                summary.tiles_modified,
+                if summary.tiles_modified == 1 { "" } else { "s" }
+            ));
+        }
+        if summary.old_size != summary.new_size {
+            ui.bullet_text(format!(
+                "Resized from {}x{} to {}x{}",
+                summary.old_size.x, summary.old_size.y, summary.new_size.x, summary.new_size.y
+            ));
+        }
+        if !summary.moved_positions.is_empty() {
+            ui.bullet_text(format!("Moved {}", summary.moved_positions.join(", ")));
+        }
+        if !summary.other_changes.is_empty() {
+            ui.bullet_text(format!("Changed {}", summary.other_changes.join(", ")));
+        }
+
+        ui.spacing();
+        let mut show_summary = settings.save.show_summary;
+        if ui.checkbox("Show this before saving", &mut show_summary) {
+            settings.save.show_summary = show_summary;
+        }
+
+        let mut close = false;
+        if ui.button("Cancel") {
+            close = true;
+        }
+        ui.same_line();
+        if ui.button("Save") {
+            save_file(
+                &mut commands,
+                &mut file,
+                &settings.save,
+                settings.grid_origin,
+            );
+            close = true;
+        }
+        if close {
+            state.save_summary = None;
+            ui.close_current_popup();
+        }
+    });
+
     match mem::take(&mut state.pending_close_state) {
         PendingCloseState::PendingUi(action) if file.dirty => {
             ui.open_popup("Are you sure?");
@@ -1302,6 +3054,7 @@ fn draw_imgui(
         let mut close = false;
         if ui.button("Cancel") {
             state.pending_close_state = PendingCloseState::None;
+            state.show_notice("Cancelled, nothing was closed".to_string());
             close = true;
         }
         ui.same_line();
@@ -1311,7 +3064,17 @@ fn draw_imgui(
         }
         ui.same_line();
         if ui.button("Save") {
-            save_file(&mut commands, &mut file);
+            if let PendingCloseState::PendingUserInput(action) =
+                mem::take(&mut state.pending_close_state)
+            {
+                state.pending_close_state = PendingCloseState::PendingSave(action);
+            }
+            save_file(
+                &mut commands,
+                &mut file,
+                &settings.save,
+                settings.grid_origin,
+            );
             close = true;
         }
 
@@ -1325,6 +3088,161 @@ fn draw_imgui(
             ui.close_current_popup();
         }
     });
+
+    ui.modal_popup("Go to tile", || {
+        viewport_target.disable_input = true;
+        ui.input_scalar_n("X, Y", &mut state.go_to_tile)
+            .step(1)
+            .build();
+        let map_range = TileRange {
+            start: MpsVec2::ZERO,
+            end: map_size - MpsVec2::ONE,
+        };
+        let pos = flip_pos(state.go_to_tile.into());
+        let valid = map_range.contains(pos);
+        if !valid {
+            ui.text_colored(
+                [1.0, 0.3, 0.3, 1.0],
+                format!(
+                    "The map only goes up to ({}, {})",
+                    map_size.x - 1,
+                    map_size.y - 1
+                ),
+            );
+        }
+
+        if ui.button("Cancel") {
+            ui.close_current_popup();
+        }
+        ui.same_line();
+        ui.disabled(!valid, || {
+            if ui.button("Go") {
+                commands.trigger(SelectForEditing {
+                    object: EditObject::Tile(pos),
+                    exclusive: true,
+                });
+                commands.trigger(PresetView::Selection);
+                ui.close_current_popup();
+            }
+        });
+    });
+
+    ui.modal_popup("Resize map", || {
+        viewport_target.disable_input = true;
+        ui.text(format!(
+            "Current size: {}x{}",
+            file.file.data.cols(),
+            file.file.data.rows()
+        ));
+        ui.input_scalar_n("Width, height", &mut state.resize_size)
+            .step(1)
+            .build();
+
+        // Sides that grow or shrink when the matching corner is kept in place
+        const ANCHORS: [(&str, Direction, Direction); 4] = [
+            ("North-west", Direction::East, Direction::South),
+            ("North-east", Direction::West, Direction::South),
+            ("South-west", Direction::East, Direction::North),
+            ("South-east", Direction::West, Direction::North),
+        ];
+        ui.combo(
+            "Keep corner",
+            &mut state.resize_anchor,
+            &ANCHORS,
+            |anchor| anchor.0.into(),
+        );
+        let (_, horizontal, vertical) = ANCHORS[state.resize_anchor];
+        ui.checkbox(
+            "Copy the edge when expanding",
+            &mut state.expand_duplicates_edge,
+        );
+
+        let size = MpsVec2::from(state.resize_size);
+        let valid = size.x >= 1 && size.y >= 1;
+        if !valid {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], "The map must be at least 1x1");
+        } else {
+            let (rows, cols) = file.file.data.size();
+            let loses_data = (cols > size.x as usize
+                && file.shrink_loses_data(horizontal, cols - size.x as usize))
+                || (rows > size.y as usize
+                    && file.shrink_loses_data(vertical, rows - size.y as usize));
+            if loses_data {
+                ui.text_colored(
+                    [1.0, 0.8, 0.3, 1.0],
+                    "This will delete built tiles or special positions",
+                );
+            }
+        }
+
+        if ui.button("Cancel") {
+            ui.close_current_popup();
+        }
+        ui.same_line();
+        ui.disabled(!valid, || {
+            if ui.button("Apply") {
+                file.resize_map(
+                    &mut commands,
+                    size,
+                    horizontal,
+                    vertical,
+                    state.expand_duplicates_edge,
+                );
+                ui.close_current_popup();
+            }
+        });
+    });
+
+    ui.modal_popup("Clear all tiles?", || {
+        viewport_target.disable_input = true;
+        ui.text(
+            "Every tile will be reset to blank. The map size, special positions, shops, and \
+            textures are kept.",
+        );
+        ui.text("Are you sure?");
+
+        if ui.button("Cancel") {
+            ui.close_current_popup();
+        }
+        ui.same_line();
+        if ui.button("Clear") {
+            file.clear_tiles(&mut commands);
+            ui.close_current_popup();
+        }
+    });
+
+    if let PendingShrinkState::PendingUi(side, count) = state.pending_shrink_state {
+        ui.open_popup("Shrink map?");
+        state.pending_shrink_state = PendingShrinkState::PendingUserInput(side, count);
+    }
+    ui.modal_popup("Shrink map?", || {
+        viewport_target.disable_input = true;
+        let PendingShrinkState::PendingUserInput(side, count) = state.pending_shrink_state else {
+            ui.close_current_popup();
+            return;
+        };
+        ui.text(format!(
+            "Removing {count} {} from the {side} edge will delete built tiles or special positions.",
+            match (side, count) {
+                (Direction::West | Direction::East, 1) => "column",
+                (Direction::West | Direction::East, _) => "columns",
+                (Direction::North | Direction::South, 1) => "row",
+                (Direction::North | Direction::South, _) => "rows",
+            }
+        ));
+        ui.text("Are you sure?");
+
+        if ui.button("Cancel") {
+            state.pending_shrink_state = PendingShrinkState::None;
+            ui.close_current_popup();
+        }
+        ui.same_line();
+        if ui.button("Shrink") {
+            file.shrink_map(&mut commands, side, count);
+            state.pending_shrink_state = PendingShrinkState::None;
+            ui.close_current_popup();
+        }
+    });
 }
 
 fn shop_editor(
@@ -1409,28 +3327,104 @@ fn shop_editor(
     open_item_picker
 }
 
+#[allow(clippy::too_many_arguments)]
 fn keyboard_handler(
     keys: Res<ButtonInput<KeyCode>>,
     mut commands: Commands,
     mut ui_state: ResMut<UiState>,
     mut current_open_file: ResMut<LoadedFile>,
+    mut tabs: ResMut<OpenTabs>,
+    recent_files: Res<RecentFiles>,
+    assets: Res<AssetServer>,
+    settings: Res<EditorSettings>,
+    viewport_target: Res<ViewportTarget>,
 ) {
     if shortcut_pressed!(keys, Ctrl + KeyN) {
         new_file(&mut ui_state);
     }
+    if shortcut_pressed!(keys, Ctrl + KeyT) {
+        tabs.new_tab(&mut commands, &mut current_open_file);
+    }
+    if shortcut_pressed!(keys, Ctrl + Tab)
+        && let Some(path) = recent_files.previous()
+    {
+        if let Some(index) = tabs.find(path, &current_open_file) {
+            tabs.switch_to(&mut commands, &mut current_open_file, index);
+        } else {
+            open_file_path(
+                &mut ui_state,
+                path.to_owned(),
+                assets.clone(),
+                settings.load,
+            );
+        }
+    }
     if shortcut_pressed!(keys, Ctrl + KeyO) {
         open_file(&mut ui_state);
     }
+    if shortcut_pressed!(keys, Ctrl + KeyG) {
+        ui_state.open_go_to_tile = true;
+    }
     if shortcut_pressed!(keys, Ctrl + Shift + KeyS) {
-        save_file_as(&mut commands);
+        save_file_as(
+            &mut commands,
+            &mut current_open_file,
+            &settings.save,
+            settings.grid_origin,
+        );
     } else if shortcut_pressed!(keys, Ctrl + KeyS) {
-        save_file(&mut commands, &mut current_open_file);
+        ui_state.request_save(&mut commands, &mut current_open_file, &settings);
+    }
+
+    // Text fields have their own undo
+    if !ui_state.typing {
+        if shortcut_pressed!(keys, Ctrl + Shift + KeyZ) || shortcut_pressed!(keys, Ctrl + KeyY) {
+            current_open_file.redo(&mut commands);
+        } else if shortcut_pressed!(keys, Ctrl + KeyZ) {
+            current_open_file.undo(&mut commands);
+        }
     }
 
-    if shortcut_pressed!(keys, Ctrl + Shift + KeyZ) {
-        current_open_file.redo(&mut commands);
-    } else if shortcut_pressed!(keys, Ctrl + KeyZ) {
-        current_open_file.undo(&mut commands);
+    if shortcut_pressed!(keys, Alt + KeyF) {
+        edit_selected_ramps(
+            &mut commands,
+            &mut current_open_file,
+            ui_state.tile_edit_mode(),
+            TileHeight::with_flipped_heights,
+        );
+    }
+    if shortcut_pressed!(keys, Alt + KeyR) {
+        edit_selected_ramps(
+            &mut commands,
+            &mut current_open_file,
+            ui_state.tile_edit_mode(),
+            TileHeight::with_turned_ramp,
+        );
+    }
+
+    // Alt+arrow keys go through the selection history instead
+    if !ui_state.typing
+        && !viewport_target.disable_input
+        && !keys.any_pressed(modifier_key!(Alt))
+        && !keys.any_pressed(modifier_key!(Ctrl))
+    {
+        let extend = keys.any_pressed(modifier_key!(Shift));
+        for (key, dir) in [
+            (KeyCode::ArrowLeft, Direction::West),
+            (KeyCode::ArrowRight, Direction::East),
+            (KeyCode::ArrowUp, Direction::North),
+            (KeyCode::ArrowDown, Direction::South),
+        ] {
+            if keys.just_pressed(key) {
+                move_selection(
+                    &mut commands,
+                    &mut ui_state,
+                    &current_open_file,
+                    dir,
+                    extend,
+                );
+            }
+        }
     }
 
     if shortcut_pressed!(keys, NumpadDecimal) {
@@ -1441,24 +3435,108 @@ fn keyboard_handler(
     }
 }
 
+/// Moves the selected tiles one tile toward `dir`, keeping them within the map. With `extend`,
+/// only the corner opposite the anchor is moved, growing or shrinking the selection.
+fn move_selection(
+    commands: &mut Commands,
+    state: &mut UiState,
+    file: &LoadedFile,
+    dir: Direction,
+    extend: bool,
+) {
+    let Some(range) = file.selected_range else {
+        return;
+    };
+    let offset = dir.offset();
+    let (anchor, cursor) = if extend {
+        // Selections made with the mouse start out anchored at their first corner
+        let anchor = state
+            .selection_anchor
+            .filter(|anchor| {
+                (anchor.x == range.start.x || anchor.x == range.end.x)
+                    && (anchor.y == range.start.y || anchor.y == range.end.y)
+            })
+            .unwrap_or(range.start);
+        let corner = MpsVec2::new(
+            if anchor.x == range.start.x {
+                range.end.x
+            } else {
+                range.start.x
+            },
+            if anchor.y == range.start.y {
+                range.end.y
+            } else {
+                range.start.y
+            },
+        );
+        (anchor, file.in_bounds(corner + offset))
+    } else {
+        let start = file.in_bounds(range.start + offset);
+        let end = file.in_bounds(range.end + offset);
+        // The selection stops at the edge of the map instead of shrinking
+        if end - start != range.end - range.start {
+            return;
+        }
+        (start, end)
+    };
+    state.selection_anchor = Some(anchor);
+
+    commands.trigger(SelectForEditing {
+        object: EditObject::Tile(anchor),
+        exclusive: true,
+    });
+    if cursor != anchor {
+        commands.trigger(SelectForEditing {
+            object: EditObject::Tile(cursor),
+            exclusive: false,
+        });
+    }
+}
+
+/// Changes the height of every selected ramp, leaving flat tiles untouched
+fn edit_selected_ramps(
+    commands: &mut Commands,
+    file: &mut LoadedFile,
+    mode: TileEditMode,
+    editor: fn(TileHeight) -> TileHeight,
+) -> bool {
+    let Some(range) = file.selected_range else {
+        return false;
+    };
+    file.change_heights(commands, range, mode, editor)
+}
+
 fn close_handler(
     mut commands: Commands,
     mut close_requested: MessageReader<WindowCloseRequested>,
+    mut save_abandoned: MessageReader<SaveAbandoned>,
     mut ui_state: ResMut<UiState>,
     mut open_file: ResMut<LoadedFile>,
 ) {
-    for event in close_requested.read() {
-        let window = event.window;
-        ui_state.request_close_file(move |commands, _| {
-            commands.entity(window).despawn();
+    if close_requested.read().count() > 0 {
+        ui_state.request_close_file(|commands, _| {
+            commands.trigger(CloseTab { close_window: true });
         });
     }
 
+    // Otherwise the close would still happen the next time the file is saved
+    if save_abandoned.read().count() > 0
+        && matches!(
+            ui_state.pending_close_state,
+            PendingCloseState::PendingSave(_)
+        )
+    {
+        ui_state.pending_close_state = PendingCloseState::None;
+        ui_state.show_notice("The file wasn't saved, so nothing was closed".to_string());
+    }
+
     match mem::take(&mut ui_state.pending_close_state) {
         PendingCloseState::Confirmed(action) => {
             action(&mut commands, &mut open_file);
         }
-        PendingCloseState::PendingUi(action) | PendingCloseState::PendingUserInput(action)
+        PendingCloseState::PendingUi(action)
+        | PendingCloseState::PendingUserInput(action)
+        | PendingCloseState::PendingSave(action)
             if !open_file.dirty =>
         {
             action(&mut commands, &mut open_file);
@@ -1468,3 +3546,42 @@ fn close_handler(
         }
     }
 }
+
+/// Closes the active tab once the user has agreed to lose its changes
+#[derive(Event)]
+struct CloseTab {
+    /// Whether to keep closing tabs until the window can be closed, asking about each tab with
+    /// unsaved changes along the way
+    close_window: bool,
+}
+
+fn on_close_tab(
+    on: On<CloseTab>,
+    mut commands: Commands,
+    mut ui_state: ResMut<UiState>,
+    mut tabs: ResMut<OpenTabs>,
+    mut file: ResMut<LoadedFile>,
+    window_query: Query<Entity, With<PrimaryWindow>>,
+) {
+    let close_window = |commands: &mut Commands| {
+        if let Ok(window) = window_query.single() {
+            commands.entity(window).despawn();
+        }
+    };
+    if tabs.count() == 1 {
+        close_window(&mut commands);
+        return;
+    }
+    tabs.close_active(&mut commands, &mut file);
+    if !on.close_window {
+        return;
+    }
+    let Some(index) = tabs.dirty_tab() else {
+        close_window(&mut commands);
+        return;
+    };
+    tabs.switch_to(&mut commands, &mut file, index);
+    ui_state.request_close_file(|commands, _| {
+        commands.trigger(CloseTab { close_window: true });
+    });
+}
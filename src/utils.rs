@@ -67,13 +67,14 @@ pub mod grid_as_vec_vec {
         let columns = direct.first().map_or(0, |x| x.len());
         let flattened: Vec<_> = direct
             .into_iter()
-            .map(|x| {
+            .enumerate()
+            .map(|(index, x)| {
                 if x.len() == columns {
                     Ok(x)
                 } else {
                     Err(Error::invalid_length(
                         x.len(),
-                        &columns.to_string().as_str(),
+                        &format!("{columns} elements in row {index}, like the first row").as_str(),
                     ))
                 }
             })
@@ -82,3 +83,110 @@ pub mod grid_as_vec_vec {
         Ok(Grid::from_vec(flattened, columns))
     }
 }
+
+/// Reads a [`CubeMap`](crate::schema::CubeMap) from either its six faces or, as older maps have
+/// it, a single value used for every face. It's always written as all six faces.
+pub mod one_or_cube_map {
+    use serde::{Deserialize, Deserializer};
+    use std::array;
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<[T; 6], D::Error>
+    where
+        T: Deserialize<'de> + Clone,
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum OneOrSix<T> {
+            Six([T; 6]),
+            One(T),
+        }
+
+        Ok(match OneOrSix::deserialize(deserializer)? {
+            OneOrSix::Six(faces) => faces,
+            OneOrSix::One(face) => array::from_fn(|_| face.clone()),
+        })
+    }
+}
+
+/// Removes the comments and trailing commas that hand-edited JSON often has, so that it can be
+/// parsed by `serde_json`. Returns [`None`] if there was nothing to remove.
+pub fn strip_json_extensions(data: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut changed = false;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < data.len() && data[i] != b'"' {
+                    if data[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(data.len());
+                result.extend_from_slice(&data[start..i]);
+                continue;
+            }
+            b'/' if data.get(i + 1) == Some(&b'/') => {
+                changed = true;
+                while i < data.len() && data[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if data.get(i + 1) == Some(&b'*') => {
+                changed = true;
+                i = data[i + 2..]
+                    .windows(2)
+                    .position(|x| x == b"*/")
+                    .map_or(data.len(), |end| i + 2 + end + 2);
+                result.push(b' ');
+                continue;
+            }
+            b'}' | b']' => {
+                if let Some(last) = result.iter().rposition(|x| !x.is_ascii_whitespace())
+                    && result[last] == b','
+                {
+                    changed = true;
+                    result.remove(last);
+                }
+            }
+            _ => {}
+        }
+        result.push(data[i]);
+        i += 1;
+    }
+    changed.then_some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_json_extensions;
+
+    fn strip(data: &str) -> Option<String> {
+        strip_json_extensions(data.as_bytes()).map(|x| String::from_utf8(x).unwrap())
+    }
+
+    #[test]
+    fn test_strip_json_extensions() {
+        assert_eq!(strip(r#"{"a": [1, 2]}"#), None);
+        assert_eq!(strip(r#"{"a": "// not a comment,]"}"#), None);
+        assert_eq!(strip(r#"{"a": "\"/*"}"#), None);
+        assert_eq!(
+            strip("{\n  // comment\n  \"a\": 1\n}").as_deref(),
+            Some("{\n  \n  \"a\": 1\n}")
+        );
+        assert_eq!(
+            strip(r#"{"a": /* comment */ 1}"#).as_deref(),
+            Some(r#"{"a":   1}"#)
+        );
+        assert_eq!(
+            strip("{\"a\": [1, 2,], \"b\": 3,\n}").as_deref(),
+            Some("{\"a\": [1, 2], \"b\": 3\n}")
+        );
+        assert_eq!(strip("[1, // trailing\n]").as_deref(), Some("[1 \n]"));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::load_file::{FileLoaded, LoadedFile};
+use crate::schema::{Connection, ConnectionCondition, MapFile, MpsVec2, TileData, TileHeight};
+use crate::settings::{EditorSettings, GridOrigin};
+use crate::sync::{Direction, MapEdited};
+use bevy::prelude::*;
+use itertools::Itertools;
+
+pub struct ValidationPlugin;
+
+impl Plugin for ValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MapIssues>()
+            .add_observer(on_file_load)
+            .add_observer(on_map_edited)
+            .add_systems(
+                Update,
+                (
+                    update_issues,
+                    (draw_issue_highlights, draw_locked_connections),
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct MapIssues {
+    outdated: bool,
+    /// Raised tiles whose neighbors are all empty, often left behind by a brush overshooting
+    pub floating_tiles: Vec<MpsVec2>,
+    /// Floating tiles the user chose to keep, which are no longer reported
+    pub kept_floating_tiles: Vec<MpsVec2>,
+    /// Tiles shared by multiple special positions, along with the names of those positions
+    pub overlapping_positions: Vec<(MpsVec2, Vec<String>)>,
+    /// Ramps that are flat or close to vertical, which don't mesh properly
+    pub degenerate_ramps: Vec<MpsVec2>,
+    /// Not an issue by itself, but kept up to date here for the lock overlay
+    pub locked_connections: Vec<LockedConnection>,
+}
+
+impl MapIssues {
+    pub fn is_empty(&self) -> bool {
+        self.floating_tiles.is_empty()
+            && self.overlapping_positions.is_empty()
+            && self.degenerate_ramps.is_empty()
+    }
+}
+
+/// Ramps rising more than this over a single tile are reported as degenerate
+pub const MAX_RAMP_RISE: f64 = 8.0;
+
+pub fn floating_tiles(file: &MapFile) -> Vec<MpsVec2> {
+    let Some(map_size) = file.map_size() else {
+        return vec![];
+    };
+    let empty = TileData::default();
+    let in_map = |pos: MpsVec2| pos.clamp(MpsVec2::ZERO, map_size - MpsVec2::ONE) == pos;
+    file.data
+        .indexed_iter()
+        .filter(|(_, tile)| tile.height.max_height() > 0.0)
+        .map(|((y, x), _)| MpsVec2::new(x as i32, y as i32))
+        .filter(|&pos| {
+            Direction::ALL_CLOCKWISE.iter().all(|dir| {
+                let neighbor = pos + dir.offset();
+                !in_map(neighbor) || file[neighbor] == empty
+            })
+        })
+        .collect()
+}
+
+pub fn overlapping_positions(file: &MapFile) -> Vec<(MpsVec2, Vec<String>)> {
+    file.special_positions()
+        .into_iter()
+        .sorted_by_key(|(_, pos)| *pos)
+        .chunk_by(|(_, pos)| *pos)
+        .into_iter()
+        .map(|(pos, positions)| (pos, positions.map(|(name, _)| name).collect_vec()))
+        .filter(|(_, names)| names.len() > 1)
+        .collect()
+}
+
+pub fn degenerate_ramps(file: &MapFile) -> Vec<MpsVec2> {
+    file.data
+        .indexed_iter()
+        .filter(|(_, tile)| match tile.height {
+            TileHeight::Flat { .. } => false,
+            TileHeight::Ramp { height, .. } => {
+                let rise = (height.pos - height.neg).abs();
+                !rise.is_finite() || rise == 0.0 || rise > MAX_RAMP_RISE
+            }
+        })
+        .map(|((y, x), _)| MpsVec2::new(x as i32, y as i32))
+        .collect()
+}
+
+/// A locked connection out of a tile towards a neighbor on the map
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LockedConnection {
+    pub pos: MpsVec2,
+    pub dir: Direction,
+    /// Whether the neighbor locks the connection back, which is needed for the key gate to be built
+    pub paired: bool,
+}
+
+/// Every locked connection on the map. Paired locks are listed once from each side.
+pub fn locked_connections(file: &MapFile) -> Vec<LockedConnection> {
+    let map_size = file.map_size().unwrap_or_default();
+    let in_map = |pos: MpsVec2| pos.clamp(MpsVec2::ZERO, map_size - MpsVec2::ONE) == pos;
+    const LOCKED: Connection = Connection::Conditional(ConnectionCondition::Lock);
+    let mut result = vec![];
+    for ((y, x), tile) in file.data.indexed_iter() {
+        let pos = MpsVec2::new(x as i32, y as i32);
+        for &dir in Direction::ALL_CLOCKWISE {
+            let neighbor = pos + dir.offset();
+            if tile.connections[dir] == LOCKED && in_map(neighbor) {
+                result.push(LockedConnection {
+                    pos,
+                    dir,
+                    paired: file[neighbor].connections[dir.opposite()] == LOCKED,
+                });
+            }
+        }
+    }
+    result
+}
+
+/// A mistake that makes a map misbehave in game, checked for before saving
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// A special position, named as in [`MapFile::special_positions`], that's off the map
+    OutOfBounds(String, MpsVec2),
+    /// A tile with a locked connection towards a neighbor that doesn't lock it back, so the key
+    /// gate between them is never built
+    UnpairedLock(MpsVec2, Direction),
+}
+
+impl ValidationIssue {
+    /// Describes the issue, with positions counted from `origin` like in the rest of the UI
+    pub fn describe(&self, origin: GridOrigin, map_size: MpsVec2) -> String {
+        match self {
+            Self::OutOfBounds(name, pos) => {
+                let pos = origin.flip(*pos, map_size);
+                format!("{name} ({}, {}) is outside the map", pos.x, pos.y)
+            }
+            Self::UnpairedLock(pos, dir) => {
+                let pos = origin.flip(*pos, map_size);
+                format!(
+                    "Tile ({}, {}) is locked towards the {} but its neighbor isn't",
+                    pos.x,
+                    pos.y,
+                    dir.as_ref().to_lowercase()
+                )
+            }
+        }
+    }
+}
+
+/// Checks for mistakes the editor lets through but the game doesn't cope with. Materials aren't
+/// checked, as every index an [`MpsMaterial`](crate::schema::MpsMaterial) can hold is in the atlas.
+pub fn validate(file: &MapFile) -> Vec<ValidationIssue> {
+    let map_size = file.map_size().unwrap_or_default();
+    let in_map = |pos: MpsVec2| pos.clamp(MpsVec2::ZERO, map_size - MpsVec2::ONE) == pos;
+    let mut issues = file
+        .special_positions()
+        .into_iter()
+        .filter(|&(_, pos)| !in_map(pos))
+        .map(|(name, pos)| ValidationIssue::OutOfBounds(name, pos))
+        .collect_vec();
+
+    issues.extend(
+        locked_connections(file)
+            .into_iter()
+            .filter(|lock| !lock.paired)
+            .map(|lock| ValidationIssue::UnpairedLock(lock.pos, lock.dir)),
+    );
+    issues
+}
+
+fn on_file_load(_: On<FileLoaded>, mut issues: ResMut<MapIssues>) {
+    issues.kept_floating_tiles.clear();
+    issues.outdated = true;
+}
+
+fn on_map_edited(_: On<MapEdited>, mut issues: ResMut<MapIssues>) {
+    issues.outdated = true;
+}
+
+fn update_issues(mut issues: ResMut<MapIssues>, file: Res<LoadedFile>) {
+    if !issues.outdated {
+        return;
+    }
+    issues.outdated = false;
+
+    let mut floating = floating_tiles(&file.file);
+    floating.retain(|pos| !issues.kept_floating_tiles.contains(pos));
+    issues.floating_tiles = floating;
+    issues.overlapping_positions = overlapping_positions(&file.file);
+    issues.degenerate_ramps = degenerate_ramps(&file.file);
+    issues.locked_connections = locked_connections(&file.file);
+}
+
+fn draw_issue_highlights(mut gizmos: Gizmos, issues: Res<MapIssues>, file: Res<LoadedFile>) {
+    for &pos in &issues.floating_tiles {
+        let height = file.file[pos].height.max_height() as f32;
+        gizmos.cuboid(
+            Transform::from_xyz(pos.x as f32, height / 2.0, pos.y as f32).with_scale(Vec3::new(
+                1.05,
+                height + 0.05,
+                1.05,
+            )),
+            Srgba::rgb_u8(255, 120, 0),
+        );
+    }
+    for (pos, _) in &issues.overlapping_positions {
+        let height = file.file[*pos].height.max_height() as f32;
+        gizmos.cuboid(
+            Transform::from_xyz(pos.x as f32, height + 0.5, pos.y as f32),
+            Srgba::rgb_u8(255, 0, 80),
+        );
+    }
+}
+
+/// Marks each side of a locked connection, green if the neighbor locks it back and red otherwise
+fn draw_locked_connections(
+    mut gizmos: Gizmos,
+    issues: Res<MapIssues>,
+    file: Res<LoadedFile>,
+    settings: Res<EditorSettings>,
+) {
+    if !settings.show_locked_connections {
+        return;
+    }
+    for lock in &issues.locked_connections {
+        let height = file.file[lock.pos].height.center_height() as f32;
+        let offset = lock.dir.offset();
+        let position = Vec3::new(
+            lock.pos.x as f32 + offset.x as f32 * 0.35,
+            height + 0.25,
+            lock.pos.y as f32 + offset.y as f32 * 0.35,
+        );
+        gizmos.cuboid(
+            Transform::from_translation(position).with_scale(Vec3::splat(0.2)),
+            if lock.paired {
+                Srgba::rgb_u8(0, 200, 80)
+            } else {
+                Srgba::rgb_u8(230, 30, 30)
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockedConnection, ValidationIssue, locked_connections, validate};
+    use crate::schema::{Connection, ConnectionCondition, MapFile, MpsVec2, TileData};
+    use crate::settings::GridOrigin;
+    use crate::sync::Direction;
+    use grid::Grid;
+
+    #[test]
+    fn test_validate() {
+        let mut file = MapFile {
+            data: Grid::init(2, 2, TileData::default()),
+            star_warp_tile: MpsVec2::new(2, 0),
+            ..Default::default()
+        };
+        let locked = Connection::Conditional(ConnectionCondition::Lock);
+        file.data[(0, 0)].connections.east = locked;
+        file.data[(0, 1)].connections.west = locked;
+        file.data[(1, 0)].connections.north = locked;
+
+        assert_eq!(
+            validate(&file),
+            [
+                ValidationIssue::OutOfBounds("Star warp tile".to_string(), MpsVec2::new(2, 0)),
+                ValidationIssue::UnpairedLock(MpsVec2::new(0, 1), Direction::North),
+            ]
+        );
+        assert_eq!(
+            validate(&file)[1].describe(GridOrigin::SouthEast, MpsVec2::new(2, 2)),
+            "Tile (1, 0) is locked towards the north but its neighbor isn't"
+        );
+    }
+
+    #[test]
+    fn test_locked_connections() {
+        let mut file = MapFile {
+            data: Grid::init(1, 3, TileData::default()),
+            ..Default::default()
+        };
+        let locked = Connection::Conditional(ConnectionCondition::Lock);
+        file.data[(0, 0)].connections.east = locked;
+        file.data[(0, 1)].connections.west = locked;
+        file.data[(0, 1)].connections.east = locked;
+        // Off the map, so there's no neighbor to pair with
+        file.data[(0, 2)].connections.south = locked;
+
+        let lock = |x, dir, paired| LockedConnection {
+            pos: MpsVec2::new(x, 0),
+            dir,
+            paired,
+        };
+        assert_eq!(
+            locked_connections(&file),
+            [
+                lock(0, Direction::East, true),
+                lock(1, Direction::East, false),
+                lock(1, Direction::West, true),
+            ]
+        );
+    }
+}
@@ -3,24 +3,35 @@ use crate::assets::{
     missing_skybox, player, podium, shop_hop_box,
 };
 use crate::culling::CullingPlugin;
-use crate::load_file::{FileLoaded, LoadedFile};
-use crate::mesh::{MapMeshMarker, mesh_map, mesh_top_highlights};
-use crate::schema::{MpsVec2, MpsVec3};
+use crate::load_file::{FileLoaded, LoadedFile, TextureReloaded};
+use crate::mesh::{
+    AtlasMaterial, AtlasTiling, MapChunk, MapMeshMarker, MapMeshRoot, MapMeshStats, TileMeshCache,
+    mesh_map, mesh_top_highlights, remesh_chunk,
+};
+use crate::overlay::{
+    OverlayAssets, OverlayPlugin, TileOverlaysMarker, has_overlay, tile_overlays,
+};
+use crate::schema::{MapFile, MpsVec2, MpsVec3};
+use crate::settings::{EditorSettings, MeshSettings};
 use crate::sync::{
     CameraId, Direction, EditObject, ListEdit, MapEdit, MapEdited, PresetView, PreviewObject,
     PreviewResultsAnimation, SelectForEditing, TogglePreviewVisibility,
 };
 use crate::tile_range::TileRange;
+use crate::tools::{ActiveTool, PaintStroke, PickingOptions, Tool, ToolOptions, UseTool};
+use crate::ui::UiState;
 use crate::{modifier_key, shortcut_pressed};
 use bevy::asset::io::embedded::GetAssetServer;
 use bevy::asset::{LoadState, RenderAssetUsages};
-use bevy::camera::NormalizedRenderTarget;
 use bevy::camera::primitives::{Aabb, MeshAabb};
 use bevy::camera::visibility::NoFrustumCulling;
+use bevy::camera::{NormalizedRenderTarget, ScalingMode};
 use bevy::core_pipeline::Skybox;
 use bevy::input::ButtonState;
 use bevy::input::mouse::MouseWheel;
+use bevy::pbr::wireframe::{Wireframe, WireframePlugin};
 use bevy::picking::PickingSystems;
+use bevy::picking::backend::HitData;
 use bevy::picking::pointer::{Location, PointerAction, PointerId, PointerInput};
 use bevy::prelude::Rect;
 use bevy::prelude::*;
@@ -28,6 +39,7 @@ use bevy::render::render_resource::{
     Extent3d, TextureDataOrder, TextureDimension, TextureFormat, TextureViewDescriptor,
     TextureViewDimension,
 };
+use bevy::transform::TransformSystems;
 use bevy::window::WindowEvent;
 use bevy_easings::{CustomComponentEase, EaseFunction, EasingType};
 use bevy_map_camera::controller::{CameraControllerButtons, ControlMessage};
@@ -37,6 +49,8 @@ use image::imageops::FilterType;
 use image::{DynamicImage, GenericImageView, RgbaImage};
 use itertools::Itertools;
 use std::f32::consts::PI;
+use std::fmt::Debug;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use transform_gizmo_bevy::GizmoHotkeys;
 use transform_gizmo_bevy::config::TransformPivotPoint;
@@ -62,16 +76,21 @@ impl Plugin for ViewportPlugin {
                     1,
                     TextureFormat::Rgba8UnormSrgb,
                 ));
+        // Added early so the atlas material can be created below
+        app.add_plugins(MaterialPlugin::<AtlasMaterial>::default());
         let missing_skybox = missing_skybox(app.get_asset_server());
         let missing_atlas = missing_atlas(app.get_asset_server());
-        let atlas_material = app
-            .world_mut()
-            .resource_mut::<Assets<StandardMaterial>>()
-            .add(StandardMaterial {
-                base_color_texture: Some(missing_atlas.clone()),
-                perceptual_roughness: 1.0,
-                ..Default::default()
-            });
+        let atlas_material =
+            app.world_mut()
+                .resource_mut::<Assets<AtlasMaterial>>()
+                .add(AtlasMaterial {
+                    base: StandardMaterial {
+                        base_color_texture: Some(missing_atlas.clone()),
+                        perceptual_roughness: 1.0,
+                        ..Default::default()
+                    },
+                    extension: AtlasTiling::default(),
+                });
 
         app.insert_resource(ViewportTarget {
             texture: render_texture,
@@ -79,6 +98,12 @@ impl Plugin for ViewportPlugin {
             size: Vec2::new(1.0, 1.0),
             disable_input: false,
         })
+        .init_resource::<TileMeshCache>()
+        .init_resource::<MapMeshStats>()
+        .init_resource::<DirtyTiles>()
+        .init_resource::<TextureLoadErrors>()
+        .init_resource::<HistoryHighlight>()
+        .init_resource::<ShowWireframe>()
         .insert_resource(ViewportState {
             skybox: ViewportTextureSet::new(missing_skybox),
             atlas: ViewportTextureSet::new(missing_atlas),
@@ -89,6 +114,8 @@ impl Plugin for ViewportPlugin {
             MeshPickingPlugin,
             TransformGizmoPlugin,
             CullingPlugin,
+            OverlayPlugin,
+            WireframePlugin::default(),
         ))
         .add_systems(
             First,
@@ -97,9 +124,13 @@ impl Plugin for ViewportPlugin {
         .add_systems(Startup, setup_viewport)
         .add_observer(on_file_load)
         .add_observer(on_map_edited)
+        .add_observer(on_texture_reloaded)
         .add_observer(on_remesh_map)
         .add_observer(on_select_for_editing)
         .add_observer(on_pointer_click)
+        .add_observer(on_pointer_press)
+        .add_observer(on_pointer_move)
+        .add_observer(on_pointer_scroll)
         .add_observer(on_preset_view)
         .add_observer(on_toggle_preview_visibility)
         .add_observer(on_preview_results_animation)
@@ -108,13 +139,24 @@ impl Plugin for ViewportPlugin {
             (
                 keyboard_handler,
                 ensure_camera_up,
+                suspend_camera_controls,
+                update_orthographic_scale,
                 update_gizmos,
                 sync_from_gizmos,
                 update_lights,
                 update_textures,
                 update_results_preview,
                 update_results_cameras,
+                remesh_on_settings_change,
+                update_history_highlight,
+                update_background,
             ),
+        )
+        .add_systems(
+            PostUpdate,
+            (remesh_dirty_tiles, update_wireframe)
+                .chain()
+                .before(TransformSystems::Propagate),
         );
     }
 }
@@ -123,7 +165,23 @@ impl Plugin for ViewportPlugin {
 struct ViewportState {
     skybox: ViewportTextureSet,
     atlas: ViewportTextureSet,
-    atlas_material: Handle<StandardMaterial>,
+    atlas_material: Handle<AtlasMaterial>,
+}
+
+/// Tiles the undo or redo step the user is hovering in the UI would change
+#[derive(Resource, Default)]
+pub struct HistoryHighlight(pub Option<TileRange>);
+
+/// Whether the map mesh is drawn with its edges over it, for tracking down seams. This is kept
+/// outside the settings so that it starts off every session.
+#[derive(Resource, Default)]
+pub struct ShowWireframe(pub bool);
+
+/// Textures from the map that failed to load, so the user can tell why the map looks wrong
+#[derive(Resource, Default)]
+pub struct TextureLoadErrors {
+    /// The atlas path and the error it failed with
+    pub atlas: Option<(PathBuf, String)>,
 }
 
 struct ViewportTextureSet {
@@ -142,6 +200,10 @@ impl ViewportTextureSet {
     }
 }
 
+const CAMERA_FOV: f32 = 60_f32.to_radians();
+/// In nits
+const SKYBOX_BRIGHTNESS: f32 = 400.0;
+
 fn setup_viewport(
     mut commands: Commands,
     viewport_target: Res<ViewportTarget>,
@@ -153,9 +215,9 @@ fn setup_viewport(
         touch_enabled: false, // XXX: touch pick events are not implemented, so touch wouldn't work anyway. Maybe I should fix this.
         minimum_pitch: 0.0,
         buttons: CameraControllerButtons {
-            pan: vec![MouseButton::Middle.into(), KeyCode::ShiftLeft.into()],
+            pan: vec![MouseButton::Middle.into()],
             pan_alt: None,
-            rotate: vec![MouseButton::Middle.into()],
+            rotate: vec![MouseButton::Right.into()],
             rotate_alt: None,
         },
         ..Default::default()
@@ -178,16 +240,18 @@ fn setup_viewport(
             ..Default::default()
         },
         Projection::Perspective(PerspectiveProjection {
-            fov: 60_f32.to_radians(),
+            fov: CAMERA_FOV,
             ..Default::default()
         }),
         Skybox {
             image: textures.skybox.current.clone(),
-            brightness: 400.0, // Nits
+            brightness: SKYBOX_BRIGHTNESS,
             rotation: Quat::IDENTITY,
         },
         MapCamera,
         GizmoCamera,
+        // The labels of the tile overlays are drawn over the viewport
+        IsDefaultUiCamera,
     ));
 
     commands.spawn((
@@ -225,24 +289,62 @@ struct TilesGizmoMesh(TileRange);
 struct ResultsAnimationPreview(Timer);
 #[derive(Component)]
 struct ResultsCameraMarker;
+#[derive(Component)]
+struct HistoryHighlightMesh;
 
 #[derive(Event)]
 struct RemeshMap;
 
+/// Tiles changed since the last remesh. Only the chunks of the map mesh around them are meshed
+/// again, while edits that resize the map still trigger a full [`RemeshMap`].
+#[derive(Resource, Default)]
+struct DirtyTiles {
+    ranges: Vec<TileRange>,
+    /// Whether the selected tiles need their highlight meshed again, even if no tiles changed
+    highlight: bool,
+    /// Whether the popup, coin, and silver star overlays need rebuilding. They're also rebuilt if
+    /// any of the dirty tiles shows something in them, since it may have moved.
+    overlays: bool,
+}
+
+impl DirtyTiles {
+    fn mark(&mut self, range: TileRange) {
+        self.ranges.push(range);
+    }
+
+    fn mark_edit(&mut self, edit: &MapEdit) {
+        if let Some(range) = edit.tile_range() {
+            self.mark(range);
+        }
+    }
+
+    fn refresh_highlight(&mut self) {
+        self.highlight = true;
+    }
+
+    fn refresh_overlays(&mut self) {
+        self.overlays = true;
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn on_file_load(
     _: On<FileLoaded>,
     mut commands: Commands,
-    objects: Query<Entity, Or<(With<ViewportObject>, With<MapMeshMarker>)>>,
-    camera_query: Query<(&mut LookTransform, &mut Skybox), With<Camera>>,
+    objects: Query<Entity, Or<(With<ViewportObject>, With<MapMeshRoot>)>>,
+    camera_query: CameraViews,
     mut state: ResMut<ViewportState>,
     assets: Res<AssetServer>,
     file: Res<LoadedFile>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<AtlasMaterial>>,
+    mut mesh_cache: ResMut<TileMeshCache>,
+    mut load_errors: ResMut<TextureLoadErrors>,
 ) {
     for existing in objects {
         commands.entity(existing).despawn();
     }
+    mesh_cache.invalidate();
+    load_errors.atlas = None;
 
     state.skybox.current = state.skybox.missing.clone();
     state.skybox.outdated = true;
@@ -251,6 +353,7 @@ fn on_file_load(
     materials
         .get_mut(&state.atlas_material)
         .expect("atlas_material should've been inserted")
+        .base
         .base_color_texture = Some(state.atlas.current.clone());
 
     let player_pos = get_player_pos(&file, file.file.starting_tile);
@@ -262,10 +365,7 @@ fn on_file_load(
             old_rot: None,
         },
     ));
-    for (mut camera, mut skybox) in camera_query {
-        *camera = get_player_cam_transform(player_pos);
-        skybox.image = state.skybox.current.clone();
-    }
+    reset_camera_views(camera_query, player_pos, &state.skybox.current);
 
     for (index, &shop_hop) in file.file.shop_warp_tiles.iter().enumerate() {
         create_shop_hop_box(&mut commands, &file, &assets, shop_hop, index);
@@ -434,6 +534,7 @@ fn on_map_edited(
     camera_gizmo: Query<(&mut Transform, &mut ViewportObject), With<CameraId>>,
     mut state: ResMut<ViewportState>,
     assets: Res<AssetServer>,
+    mut dirty_tiles: ResMut<DirtyTiles>,
 ) {
     let mut change_player_pos = false;
     let mut change_gold_pipe_pos = false;
@@ -445,7 +546,8 @@ fn on_map_edited(
             change_player_pos = true;
         }
         MapEdit::ShopWarpTile(index, edit) => {
-            let mut boxes = shop_hop_boxes.iter_mut()
+            let mut boxes = shop_hop_boxes
+                .iter_mut()
                 .sort_by_key::<&ViewportObject, _>(|obj| obj.editor.get_index_param())
                 .collect_vec();
             match *edit {
@@ -484,14 +586,17 @@ fn on_map_edited(
             change_podium_pos = true;
             let cam_target = get_podium_pos(&file, *pos) + Vec3::Y;
             for (_, mut transform, mut object) in results_cameras {
-                let target_pos = Vec3::from(file.file.results_anim_cam_poses[object.editor.get_index_param()]) + cam_target;
+                let target_pos =
+                    Vec3::from(file.file.results_anim_cam_poses[object.editor.get_index_param()])
+                        + cam_target;
                 transform.translation = target_pos;
                 object.old_pos = target_pos;
             }
         }
         MapEdit::ResultsCamera(index, edit) => {
             let cam_target = get_podium_pos(&file, file.file.podium_position) + Vec3::Y;
-            let mut cameras = results_cameras.iter_mut()
+            let mut cameras = results_cameras
+                .iter_mut()
                 .sort_by_key::<&ViewportObject, _>(|obj| obj.editor.get_index_param())
                 .collect_vec();
             match *edit {
@@ -555,8 +660,18 @@ fn on_map_edited(
             }
         }
         MapEdit::EditShop(_, _, _) => {}
-        MapEdit::AdjustHeight(_, _) | MapEdit::ChangeHeight(_, _) => {
-            commands.trigger(RemeshMap);
+        MapEdit::AdjustHeight(_, _)
+        | MapEdit::ChangeHeight(_, _)
+        | MapEdit::ReplaceTiles(_, _)
+        | MapEdit::TransformTiles(_, _) => {
+            dirty_tiles.mark_edit(&on.0);
+            // These can take the overlays off of tiles, which the dirty tiles won't show anymore
+            if matches!(
+                on.0,
+                MapEdit::ReplaceTiles(_, _) | MapEdit::TransformTiles(_, _)
+            ) {
+                dirty_tiles.refresh_overlays();
+            }
             change_player_pos = true;
             change_gold_pipe_pos = true;
             change_tiles_gizmos = true;
@@ -566,14 +681,17 @@ fn on_map_edited(
                 viewport_obj.old_pos = shop_hop.translation;
             }
         }
-        MapEdit::ChangeConnection(_, _, _) | MapEdit::ChangeMaterial(_, _, _) => {
-            commands.trigger(RemeshMap);
+        MapEdit::ChangeConnection(_, _, _)
+        | MapEdit::ChangeMaterial(_, _, _)
+        | MapEdit::ChangeWallVisibility(_, _, _) => {
+            dirty_tiles.mark_edit(&on.0);
         }
         MapEdit::ChangePopupType(_, _)
         | MapEdit::ChangeCoins(_, _)
-        | MapEdit::ChangeWalkOver(_, _)
-        // Potentially make silver stars render on map when they get implemented fully
-        | MapEdit::ChangeSilverStarSpawnable(_, _) => {}
+        | MapEdit::ChangeSilverStarSpawnable(_, _) => {
+            dirty_tiles.refresh_overlays();
+        }
+        MapEdit::ChangeWalkOver(_, _) => {}
     }
 
     if change_player_pos {
@@ -606,39 +724,234 @@ fn on_map_edited(
     }
 }
 
+fn on_texture_reloaded(on: On<TextureReloaded>, mut state: ResMut<ViewportState>) {
+    match *on {
+        TextureReloaded::Skybox(_) => state.skybox.outdated = true,
+        TextureReloaded::Atlas => state.atlas.outdated = true,
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn on_remesh_map(
     _: On<RemeshMap>,
     mut commands: Commands,
-    old: Query<Entity, With<MapMeshMarker>>,
+    old: Query<Entity, Or<(With<MapMeshRoot>, With<TileOverlaysMarker>)>>,
     file: Res<LoadedFile>,
     state: Res<ViewportState>,
+    settings: Res<EditorSettings>,
     assets: Res<AssetServer>,
-    mut highlighted: Query<(Entity, &TilesGizmoMesh)>,
+    overlay_assets: Res<OverlayAssets>,
+    highlighted: Query<(Entity, &TilesGizmoMesh)>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut mesh_cache: ResMut<TileMeshCache>,
+    mut mesh_stats: ResMut<MapMeshStats>,
+    mut dirty_tiles: ResMut<DirtyTiles>,
 ) {
     let start = Instant::now();
-    commands.spawn(mesh_map(
+    let (map_mesh, stats) = mesh_map(
         &file.file.data,
+        &settings.mesh,
+        &mut mesh_cache,
         state.atlas_material.clone(),
         &assets,
         &mut materials,
         &mut meshes,
-    ));
-    if let Ok((highlighted, marker)) = highlighted.single_mut() {
-        commands.entity(highlighted).insert(mesh_top_highlights(
+    );
+    commands.spawn(map_mesh);
+    *mesh_stats = stats;
+    let (overlays, labels) = tile_overlays(
+        &file.file.data,
+        &overlay_assets,
+        &mut materials,
+        &mut meshes,
+    );
+    commands.spawn(overlays);
+    commands.spawn_batch(labels);
+    highlight_selected_tiles(
+        &mut commands,
+        &file,
+        highlighted,
+        &mut materials,
+        &mut meshes,
+    );
+    *dirty_tiles = DirtyTiles::default();
+    debug!("Meshed in {:?}", start.elapsed());
+
+    for old in old {
+        commands.entity(old).despawn();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn remesh_dirty_tiles(
+    mut commands: Commands,
+    root: Query<(Entity, &MapMeshRoot)>,
+    chunks: Query<(Entity, &MapChunk)>,
+    old_overlays: Query<Entity, With<TileOverlaysMarker>>,
+    file: Res<LoadedFile>,
+    settings: Res<EditorSettings>,
+    assets: Res<AssetServer>,
+    overlay_assets: Res<OverlayAssets>,
+    highlighted: Query<(Entity, &TilesGizmoMesh)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut mesh_cache: ResMut<TileMeshCache>,
+    mut mesh_stats: ResMut<MapMeshStats>,
+    mut dirty_tiles: ResMut<DirtyTiles>,
+) {
+    if dirty_tiles.ranges.is_empty() && !dirty_tiles.highlight && !dirty_tiles.overlays {
+        return;
+    }
+    let dirty_tiles = std::mem::take(&mut *dirty_tiles);
+    if !dirty_tiles.ranges.is_empty()
+        && let Ok((root_entity, root)) = root.single()
+        && let Some(map_size) = file.file.map_size()
+    {
+        let start = Instant::now();
+        // Walls depend on the heights of neighboring tiles, so those are meshed again too
+        let dirty = dirty_tiles
+            .ranges
+            .iter()
+            .map(|range| range.grown(1, map_size))
+            .collect_vec();
+        let mut remeshed = 0;
+        for (old_entity, chunk) in chunks {
+            if !dirty
+                .iter()
+                .any(|range| range.intersection(chunk.range).is_some())
+            {
+                continue;
+            }
+            let (new_chunk, stats) = remesh_chunk(
+                &file.file.data,
+                &settings.mesh,
+                &mut mesh_cache,
+                root,
+                chunk.range,
+                &assets,
+                &mut meshes,
+            );
+            *mesh_stats -= chunk.stats;
+            *mesh_stats += stats;
+            commands.entity(old_entity).despawn();
+            commands.spawn((new_chunk, ChildOf(root_entity)));
+            remeshed += 1;
+        }
+        debug!("Remeshed {remeshed} chunks in {:?}", start.elapsed());
+    }
+
+    if dirty_tiles.overlays
+        || dirty_tiles
+            .ranges
+            .iter()
+            .any(|&range| range.into_iter().any(|pos| has_overlay(&file.file[pos])))
+    {
+        for old in old_overlays {
+            commands.entity(old).despawn();
+        }
+        let (overlays, labels) = tile_overlays(
             &file.file.data,
-            marker.0,
+            &overlay_assets,
             &mut materials,
             &mut meshes,
+        );
+        commands.spawn(overlays);
+        commands.spawn_batch(labels);
+    }
+    highlight_selected_tiles(
+        &mut commands,
+        &file,
+        highlighted,
+        &mut materials,
+        &mut meshes,
+    );
+}
+
+fn highlight_selected_tiles(
+    commands: &mut Commands,
+    file: &LoadedFile,
+    highlighted: Query<(Entity, &TilesGizmoMesh)>,
+    materials: &mut Assets<StandardMaterial>,
+    meshes: &mut Assets<Mesh>,
+) {
+    if let Ok((highlighted, marker)) = highlighted.single() {
+        commands.entity(highlighted).insert(mesh_top_highlights(
+            &file.file.data,
+            marker.0,
+            Srgba::rgba_u8(0x54, 0xAF, 0xE7, 0x80),
+            materials,
+            meshes,
         ));
     }
-    debug!("Meshed in {:?}", start.elapsed());
+}
 
+fn update_history_highlight(
+    mut commands: Commands,
+    highlight: Res<HistoryHighlight>,
+    old: Query<Entity, With<HistoryHighlightMesh>>,
+    file: Res<LoadedFile>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if !highlight.is_changed() {
+        return;
+    }
     for old in old {
         commands.entity(old).despawn();
     }
+    if let Some(range) = highlight.0 {
+        commands.spawn((
+            mesh_top_highlights(
+                &file.file.data,
+                range,
+                Srgba::rgba_u8(0xE7, 0x9A, 0x54, 0x80),
+                &mut materials,
+                &mut meshes,
+            ),
+            HistoryHighlightMesh,
+            NoFrustumCulling,
+        ));
+    }
+}
+
+fn update_background(
+    mut commands: Commands,
+    settings: Res<EditorSettings>,
+    state: Res<ViewportState>,
+    camera: Query<(Entity, &mut Camera, Has<Skybox>), With<GizmoCamera>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    let background = &settings.background;
+    for (entity, mut camera, has_skybox) in camera {
+        let [r, g, b] = background.color;
+        camera.clear_color = ClearColorConfig::Custom(Srgba::rgb_u8(r, g, b).into());
+        if background.show_skybox && !has_skybox {
+            commands.entity(entity).insert(Skybox {
+                image: state.skybox.current.clone(),
+                brightness: SKYBOX_BRIGHTNESS,
+                rotation: Quat::IDENTITY,
+            });
+        } else if !background.show_skybox && has_skybox {
+            commands.entity(entity).remove::<Skybox>();
+        }
+    }
+}
+
+fn remesh_on_settings_change(
+    mut commands: Commands,
+    settings: Res<EditorSettings>,
+    mut last: Local<Option<MeshSettings>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    if last.as_ref().is_some_and(|last| *last != settings.mesh) {
+        commands.trigger(RemeshMap);
+    }
+    *last = Some(settings.mesh.clone());
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -664,6 +977,7 @@ fn on_select_for_editing(
     mut tiles_gizmo_children: Query<(&mut Transform, &mut TilesGizmoMesh)>,
     cameras: Query<(Entity, &CameraId)>,
     mut file: ResMut<LoadedFile>,
+    mut dirty_tiles: ResMut<DirtyTiles>,
 ) {
     if on.exclusive {
         if current_gizmos
@@ -802,7 +1116,7 @@ fn on_select_for_editing(
                     )],
                 ));
             }
-            commands.trigger(RemeshMap);
+            dirty_tiles.refresh_highlight();
         }
         EditObject::None => {}
     }
@@ -852,26 +1166,40 @@ fn get_bounds_gizmo_location(file: &LoadedFile, side: Direction) -> Vec3 {
     }) - Vec3::new(0.5, 0.0, 0.5)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn on_pointer_click(
     on: On<Pointer<Click>>,
     objects: Query<&ViewportObject>,
     current_gizmos: Query<&ViewportObject, With<GizmoTarget>>,
     meshes: Query<(), With<MapMeshMarker>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
     keys: Res<ButtonInput<KeyCode>>,
+    file: Res<LoadedFile>,
+    tool: Res<ActiveTool>,
+    tool_options: Res<ToolOptions>,
     mut commands: Commands,
 ) {
     if on.button != PointerButton::Primary {
         return;
     }
+    if tool.0 != Tool::Select {
+        // Other tools only work on tiles
+        if meshes.contains(on.entity)
+            && let Some(tile) = hit_tile(&on, &cameras, &file, &tool_options.picking)
+        {
+            commands.trigger(UseTool { tile });
+        }
+        return;
+    }
     let editor = if let Ok(object) = objects.get(on.entity) {
         if !object.editor.directly_usable() {
             return;
         }
         object.editor
-    } else if meshes.contains(on.entity) {
-        let coord_vec = on.hit.position.unwrap() - on.hit.normal.unwrap() * 0.001;
-        let coord = MpsVec2::new(coord_vec.x.round() as i32, coord_vec.z.round() as i32);
-        EditObject::Tile(coord)
+    } else if meshes.contains(on.entity)
+        && let Some(tile) = hit_tile(&on, &cameras, &file, &tool_options.picking)
+    {
+        EditObject::Tile(tile)
     } else {
         return;
     };
@@ -882,6 +1210,131 @@ fn on_pointer_click(
     });
 }
 
+/// Starts a paint stroke, which only counts if it starts on the map
+fn on_pointer_press(
+    on: On<Pointer<Press>>,
+    meshes: Query<(), With<MapMeshMarker>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    file: Res<LoadedFile>,
+    tool: Res<ActiveTool>,
+    tool_options: Res<ToolOptions>,
+    mut stroke: ResMut<PaintStroke>,
+) {
+    if on.button != PointerButton::Primary || tool.0 != Tool::Paint || !meshes.contains(on.entity) {
+        return;
+    }
+    if let Some(tile) = hit_tile(&on, &cameras, &file, &tool_options.picking) {
+        stroke.start(tile);
+    }
+}
+
+fn on_pointer_move(
+    on: On<Pointer<Move>>,
+    meshes: Query<(), With<MapMeshMarker>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    file: Res<LoadedFile>,
+    tool_options: Res<ToolOptions>,
+    mut stroke: ResMut<PaintStroke>,
+) {
+    if !stroke.is_active() || !meshes.contains(on.entity) {
+        return;
+    }
+    if let Some(tile) = hit_tile(&on, &cameras, &file, &tool_options.picking) {
+        stroke.add(tile);
+    }
+}
+
+/// Scrolling over the selected tiles raises or lowers them by the gizmo's snap distance, or by a
+/// whole unit while holding Shift. Quick scrolls are coalesced into one undo step by the history.
+/// The camera doesn't zoom for those scrolls, so zooming only works away from the selection.
+#[allow(clippy::too_many_arguments)]
+fn on_pointer_scroll(
+    on: On<Pointer<Scroll>>,
+    meshes: Query<(), With<MapMeshMarker>>,
+    tiles_gizmo: Query<&TilesGizmo>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut file: ResMut<LoadedFile>,
+    tool_options: Res<ToolOptions>,
+    ui_state: Res<UiState>,
+    gizmo_options: Res<GizmoOptions>,
+    mut wheel: ResMut<Messages<MouseWheel>>,
+    mut commands: Commands,
+) {
+    let Ok(&TilesGizmo(range)) = tiles_gizmo.single() else {
+        return;
+    };
+    if on.y == 0.0
+        || !meshes.contains(on.entity)
+        || !hit_tile(&on, &cameras, &file, &tool_options.picking)
+            .is_some_and(|tile| range.contains(tile))
+    {
+        return;
+    }
+    // Picking gets the scroll from the window events, so this only hides it from the camera
+    wheel.clear();
+
+    let step = if keys.any_pressed(modifier_key!(Shift)) {
+        1.0
+    } else {
+        gizmo_options.snap_scale.max(0.5)
+    };
+    // Empty tiles are at zero, so tiles aren't lowered past it
+    let lowest = range
+        .into_iter()
+        .map(|pos| file.file[pos].height.min_height())
+        .fold(f64::INFINITY, f64::min);
+    let change = (step * on.y.signum() as f64).max(-lowest);
+    if change == 0.0 {
+        return;
+    }
+    file.edit_tiles(
+        &mut commands,
+        MapEdit::AdjustHeight(range, change),
+        ui_state.tile_edit_mode(),
+    );
+}
+
+fn hit_tile<E: Debug + Clone + Reflect>(
+    event: &Pointer<E>,
+    cameras: &Query<(&Camera, &GlobalTransform)>,
+    file: &LoadedFile,
+    picking: &PickingOptions,
+) -> Option<MpsVec2> {
+    let hit = &event.hit;
+    let camera = cameras.get(hit.camera).ok();
+    if picking.use_plane {
+        let (camera, camera_transform) = camera?;
+        let ray = camera
+            .viewport_to_world(camera_transform, event.pointer_location.position)
+            .ok()?;
+        let distance = ray.intersect_plane(
+            Vec3::Y * picking.plane_height,
+            InfinitePlane3d::new(Vec3::Y),
+        )?;
+        let point = ray.get_point(distance);
+        let tile = MpsVec2::new(point.x.round() as i32, point.z.round() as i32);
+        // Unlike a mesh hit, the plane carries on past the edges of the map
+        return (file.in_bounds(tile) == tile).then_some(tile);
+    }
+
+    let position = hit.position?;
+    // Stepping past the surface along the view ray always lands inside the clicked tile,
+    // whereas the hit normal of a ramp or wall can point away from it. Without either, the tile
+    // is whichever grid cell the hit is in.
+    let inside = if let Some((_, camera_transform)) = camera {
+        position + (position - camera_transform.translation()).normalize_or_zero() * 0.01
+    } else if let Some(normal) = hit.normal {
+        position - normal * 0.001
+    } else {
+        position
+    };
+    Some(file.in_bounds(MpsVec2::new(
+        inside.x.round() as i32,
+        inside.z.round() as i32,
+    )))
+}
+
 #[allow(clippy::too_many_arguments)]
 fn on_preset_view(
     on: On<PresetView>,
@@ -894,6 +1347,19 @@ fn on_preset_view(
     meshes: Res<Assets<Mesh>>,
 ) {
     for (camera, transform, projection) in camera {
+        // The top-down view is orthographic, and every other view switches back to perspective
+        let perspective = match projection {
+            Projection::Perspective(perspective) => perspective.clone(),
+            Projection::Orthographic(orthographic) => PerspectiveProjection {
+                fov: CAMERA_FOV,
+                aspect_ratio: orthographic.area.width() / orthographic.area.height(),
+                ..Default::default()
+            },
+            Projection::Custom(_) => return,
+        };
+        // Framing something from the top-down view keeps looking straight down at it
+        let keep_orthographic = matches!(projection, Projection::Orthographic(_))
+            && matches!(on.event(), PresetView::Center | PresetView::Selection);
         let new_transform = match on.event() {
             PresetView::Player => {
                 let Ok(player_pos) = player_pos.single_inner() else {
@@ -902,24 +1368,14 @@ fn on_preset_view(
                 get_player_cam_transform(player_pos.translation)
             }
             PresetView::Center => {
-                let data = &file.file.data;
-                let target = Vec3::new(
-                    data.cols() as f32 / 2.0 - 0.5,
-                    data[(data.rows() / 2, data.cols() / 2)]
-                        .height
-                        .center_height() as f32,
-                    data.rows() as f32 / 2.0 - 0.5,
-                );
-                compute_grounded_look_transform(LookTransform {
-                    eye: target + Vec3::new(-10.0, 10.0, 10.0),
-                    target,
-                    up: Vec3::Y,
-                })
+                let direction = if keep_orthographic {
+                    Vec3::NEG_Y
+                } else {
+                    Vec3::new(1.0, -1.0, -1.0).normalize()
+                };
+                frame_aabb(map_aabb(&file.file), direction, &perspective)
             }
             PresetView::Selection => {
-                let Projection::Perspective(perspective) = projection else {
-                    return;
-                };
                 let mut aabb: Option<Aabb3d> = None;
                 for entity in selection {
                     let Some(new_aabb) = get_selected_entity_aabb(entity, world, &meshes) else {
@@ -931,36 +1387,19 @@ fn on_preset_view(
                         aabb = Some(new_aabb);
                     }
                 }
-                let Some(aabb) = aabb else {
-                    return;
-                };
-                let aabb = Aabb::from_min_max(aabb.min.into(), aabb.max.into());
-
-                let radius = aabb.half_extents.length();
-                let aspect = perspective.aspect_ratio;
-                let fov_y = perspective.fov;
-                let fov_x = ((fov_y / 2.0).tan() * aspect).atan() * 2.0;
-                let min_fov = fov_x.min(fov_y);
-                let distance = radius / (min_fov / 2.0).sin();
-
-                let mut current_unit = (transform.target - transform.eye).normalize();
-                if current_unit.y > 0.0 {
-                    current_unit.y = -current_unit.y;
+                // Without a selection, the whole map is framed instead
+                let aabb = aabb.unwrap_or_else(|| map_aabb(&file.file));
+
+                let mut direction = (transform.target - transform.eye).normalize();
+                if direction.y > 0.0 {
+                    direction.y = -direction.y;
                 }
-                compute_grounded_look_transform(LookTransform {
-                    eye: Vec3::from(aabb.center) - current_unit * distance,
-                    target: aabb.center.into(),
-                    up: if current_unit.abs_diff_eq(Vec3::NEG_Y, 0.001) {
-                        Vec3::NEG_Z
-                    } else {
-                        Vec3::Y
-                    },
-                })
+                if keep_orthographic {
+                    direction = Vec3::NEG_Y;
+                }
+                frame_aabb(aabb, direction, &perspective)
             }
             PresetView::TopDown => {
-                let Projection::Perspective(perspective) = projection else {
-                    return;
-                };
                 let data = &file.file.data;
                 let fov_tan = (perspective.fov / 2.0).tan();
                 let w_distance =
@@ -998,6 +1437,20 @@ fn on_preset_view(
                 }
             }
         };
+        if matches!(on.event(), PresetView::TopDown) {
+            commands
+                .entity(camera)
+                .insert(Projection::Orthographic(OrthographicProjection {
+                    scaling_mode: ScalingMode::FixedVertical {
+                        viewport_height: orthographic_height(&new_transform),
+                    },
+                    ..OrthographicProjection::default_3d()
+                }));
+        } else if !keep_orthographic && !matches!(projection, Projection::Perspective(_)) {
+            commands
+                .entity(camera)
+                .insert(Projection::Perspective(perspective));
+        }
         commands.entity(camera).insert(transform.ease_to(
             new_transform,
             EaseFunction::QuinticInOut,
@@ -1008,6 +1461,66 @@ fn on_preset_view(
     }
 }
 
+/// The tiles of the map, from the floor up to the highest tile
+fn map_aabb(file: &MapFile) -> Aabb3d {
+    let size = file.map_size().unwrap_or(MpsVec2::ONE);
+    let max_height = file
+        .data
+        .iter()
+        .map(|x| x.height.max_height() as f32)
+        .reduce(f32::max)
+        .unwrap_or_default();
+    Aabb3d {
+        min: Vec3A::new(-0.5, 0.0, -0.5),
+        max: Vec3A::new(size.x as f32 - 0.5, max_height, size.y as f32 - 0.5),
+    }
+}
+
+/// A view looking along `direction` from far enough away to see all of `aabb`. The orthographic
+/// view is sized from the distance to the target, so this fits it too.
+fn frame_aabb(aabb: Aabb3d, direction: Vec3, perspective: &PerspectiveProjection) -> LookTransform {
+    let aabb = Aabb::from_min_max(aabb.min.into(), aabb.max.into());
+
+    let radius = aabb.half_extents.length();
+    let aspect = perspective.aspect_ratio;
+    let fov_y = perspective.fov;
+    let fov_x = ((fov_y / 2.0).tan() * aspect).atan() * 2.0;
+    let min_fov = fov_x.min(fov_y);
+    let distance = radius / (min_fov / 2.0).sin();
+
+    compute_grounded_look_transform(LookTransform {
+        eye: Vec3::from(aabb.center) - direction * distance,
+        target: aabb.center.into(),
+        up: if direction.abs_diff_eq(Vec3::NEG_Y, 0.001) {
+            Vec3::NEG_Z
+        } else {
+            Vec3::Y
+        },
+    })
+}
+
+/// How much of the ground the orthographic view shows top to bottom. This matches what the
+/// perspective view shows around the target from the same distance, so zooming still works.
+fn orthographic_height(transform: &LookTransform) -> f32 {
+    2.0 * transform.eye.distance(transform.target) * (CAMERA_FOV / 2.0).tan()
+}
+
+fn update_orthographic_scale(camera: Query<(&LookTransform, &mut Projection), With<GizmoCamera>>) {
+    for (transform, mut projection) in camera {
+        let Projection::Orthographic(orthographic) = projection.bypass_change_detection() else {
+            continue;
+        };
+        let viewport_height = orthographic_height(transform);
+        if !matches!(
+            orthographic.scaling_mode,
+            ScalingMode::FixedVertical { viewport_height: current } if current == viewport_height
+        ) {
+            orthographic.scaling_mode = ScalingMode::FixedVertical { viewport_height };
+            projection.set_changed();
+        }
+    }
+}
+
 fn on_toggle_preview_visibility(
     on: On<TogglePreviewVisibility>,
     mut commands: Commands,
@@ -1076,6 +1589,20 @@ fn get_selected_entity_aabb(
     }
 }
 
+/// The cameras' views, and their skyboxes unless the skybox is hidden in the settings
+type CameraViews<'w, 's> =
+    Query<'w, 's, (&'static mut LookTransform, Option<&'static mut Skybox>), With<Camera>>;
+
+/// Points every camera at the player, such as when a map is opened
+fn reset_camera_views(cameras: CameraViews, player_pos: Vec3, skybox_image: &Handle<Image>) {
+    for (mut camera, skybox) in cameras {
+        *camera = get_player_cam_transform(player_pos);
+        if let Some(mut skybox) = skybox {
+            skybox.image = skybox_image.clone();
+        }
+    }
+}
+
 fn get_player_cam_transform(player_pos: Vec3) -> LookTransform {
     compute_grounded_look_transform(LookTransform {
         eye: player_pos + Vec3::new(0.0, 3.0, 6.0),
@@ -1101,13 +1628,65 @@ fn compute_grounded_look_transform(transform: LookTransform) -> LookTransform {
     }
 }
 
-fn keyboard_handler(keys: Res<ButtonInput<KeyCode>>, mut commands: Commands) {
+fn keyboard_handler(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+    mut wireframe: ResMut<ShowWireframe>,
+) {
     if shortcut_pressed!(keys, Alt + KeyA) {
         commands.trigger(SelectForEditing {
             object: EditObject::None,
             exclusive: true,
         })
     }
+    if shortcut_pressed!(keys, Alt + KeyW) {
+        wireframe.0 = !wireframe.0;
+    }
+}
+
+/// Adds or removes [`Wireframe`] on every mesh in the map mesh. Remeshes and key gate scenes
+/// spawn new meshes, which are caught by checking for added meshes.
+fn update_wireframe(
+    mut commands: Commands,
+    wireframe: Res<ShowWireframe>,
+    roots: Query<Entity, With<MapMeshRoot>>,
+    children: Query<&Children>,
+    meshes: Query<(), With<Mesh3d>>,
+    added_meshes: Query<(), Added<Mesh3d>>,
+) {
+    if !wireframe.is_changed() && (!wireframe.0 || added_meshes.is_empty()) {
+        return;
+    }
+    for root in roots {
+        for entity in children.iter_descendants(root) {
+            if !meshes.contains(entity) {
+                continue;
+            }
+            if wireframe.0 {
+                commands.entity(entity).insert(Wireframe);
+            } else {
+                commands.entity(entity).remove::<Wireframe>();
+            }
+        }
+    }
+}
+
+/// Keeps the camera still while a gizmo is being dragged, so that pressing another mouse button
+/// partway through a drag doesn't move the view out from under it. Anything else looking for the
+/// viewport camera should go by [`GizmoCamera`], which stays on it.
+fn suspend_camera_controls(
+    mut commands: Commands,
+    targets: Query<&GizmoTarget>,
+    cameras: Query<(Entity, Has<MapCamera>), With<GizmoCamera>>,
+) {
+    let dragging = targets.iter().any(GizmoTarget::is_active);
+    for (camera, controlled) in cameras {
+        if dragging && controlled {
+            commands.entity(camera).remove::<MapCamera>();
+        } else if !dragging && !controlled {
+            commands.entity(camera).insert(MapCamera);
+        }
+    }
 }
 
 fn ensure_camera_up(camera: Query<(&mut LookTransform, &Transform), With<Camera>>) {
@@ -1125,6 +1704,7 @@ fn update_gizmos(mut options: ResMut<GizmoOptions>, viewport: Res<ViewportTarget
     ));
 }
 
+#[allow(clippy::too_many_arguments)]
 fn sync_from_gizmos(
     mut commands: Commands,
     mut file: ResMut<LoadedFile>,
@@ -1138,6 +1718,12 @@ fn sync_from_gizmos(
         Without<TilesGizmoMesh>,
     >,
     mut selected_mesh_gizmo: Query<&mut Transform, With<TilesGizmoMesh>>,
+    mut ui_state: ResMut<UiState>,
+    settings: Res<EditorSettings>,
+    mut snap_indicator: Gizmos,
+    mut dirty_tiles: ResMut<DirtyTiles>,
+    // How far the tiles have been moved so far by a height drag that's still going
+    mut applied_change: Local<f64>,
 ) {
     for (mut transform, mut object, gizmo, tiles) in gizmos {
         match object.editor {
@@ -1215,66 +1801,26 @@ fn sync_from_gizmos(
                 if x_change == 0 && y_change == 0 {
                     continue;
                 }
-                let expand = MapEdit::ExpandMap(side, None);
-                let shrink = MapEdit::ShrinkMap(side);
-                match side {
-                    Direction::West | Direction::North => {
-                        let axis = if side == Direction::West {
-                            x_change
-                        } else {
-                            y_change
-                        };
-                        if axis < 0 {
-                            for _ in 0..axis.abs() {
-                                file.edit_map(&mut commands, expand.clone());
-                            }
-                        } else if axis > 0 {
-                            for _ in 0..axis {
-                                file.edit_map(&mut commands, shrink.clone());
-                            }
-                        }
-                        transform.translation = object.old_pos;
-                    }
-                    Direction::East | Direction::South => {
-                        let axis = if side == Direction::East {
-                            x_change
-                        } else {
-                            y_change
-                        };
-                        let mut changed = false;
-                        if axis > 0 {
-                            for _ in 0..axis {
-                                changed |= file.edit_map(&mut commands, expand.clone());
-                            }
-                        } else if axis < 0 {
-                            for _ in 0..axis.abs() {
-                                changed |= file.edit_map(&mut commands, shrink.clone());
-                            }
-                        }
-                        if changed {
-                            object.old_pos = transform.translation;
-                        } else {
-                            transform.translation = object.old_pos;
-                        }
-                    }
-                }
-
-                let player_pos = file.in_bounds(file.file.starting_tile);
-                file.edit_map(&mut commands, MapEdit::StartingTile(player_pos));
-
-                for index in 0..file.file.shop_warp_tiles.len() {
-                    let tile = file.in_bounds(file.file.shop_warp_tiles[index]);
-                    file.edit_map(
-                        &mut commands,
-                        MapEdit::ShopWarpTile(index, ListEdit::Set(tile)),
-                    );
+                let growth = match side {
+                    Direction::West => -x_change,
+                    Direction::East => x_change,
+                    Direction::North => -y_change,
+                    Direction::South => y_change,
+                };
+                let count = growth.unsigned_abs() as usize;
+                let changed = if growth > 0 {
+                    file.expand_map(&mut commands, side, count, ui_state.expand_duplicates_edge)
+                } else if file.shrink_loses_data(side, count) {
+                    ui_state.request_shrink(side, count);
+                    false
+                } else {
+                    file.shrink_map(&mut commands, side, count)
+                };
+                if changed && matches!(side, Direction::East | Direction::South) {
+                    object.old_pos = transform.translation;
+                } else {
+                    transform.translation = object.old_pos;
                 }
-
-                let gold_pipe_pos = file.in_bounds(file.file.star_warp_tile);
-                file.edit_map(&mut commands, MapEdit::StarWarpTile(gold_pipe_pos));
-
-                let podium_pos = file.in_bounds(file.file.podium_position);
-                file.edit_map(&mut commands, MapEdit::PodiumPosition(podium_pos));
             }
             EditObject::Camera(camera) => {
                 if !gizmo.is_active() {
@@ -1292,28 +1838,92 @@ fn sync_from_gizmos(
             }
             EditObject::Tile(_) => {
                 let range = tiles.unwrap().0;
+                if !gizmo.is_active() && transform.translation == object.old_pos {
+                    continue;
+                }
+                let change = (transform.translation.y - object.old_pos.y) as f64;
+                let mut change = (change * 4.0) as i32 as f64 / 4.0;
+                let mut snapped_height = None;
+                if settings.gizmo.snap_to_neighbors {
+                    let center = MpsVec2::new(
+                        (range.start.x + range.end.x) / 2,
+                        (range.start.y + range.end.y) / 2,
+                    );
+                    let start_height = file.file[center].height.max_height() - *applied_change;
+                    snapped_height = neighbor_snap_height(
+                        &file.file,
+                        range,
+                        start_height + change,
+                        settings.gizmo.snap_threshold,
+                    );
+                    if let Some(height) = snapped_height {
+                        change = height - start_height;
+                    }
+                }
+
                 if gizmo.is_active() {
-                    let Some(GizmoResult::Translation { delta, .. }) = gizmo.latest_result() else {
-                        return;
-                    };
-                    let delta = (delta.y * 4.0) as i32 as f64 / 4.0;
-                    file.file.adjust_height(range, delta);
-                    selected_mesh_gizmo.single_mut().unwrap().translation.y -= delta as f32;
-                    commands.trigger(RemeshMap);
-                } else if transform.translation != object.old_pos {
-                    let change = (transform.translation.y - object.old_pos.y) as f64;
-                    let change = (change * 4.0) as i32 as f64 / 4.0;
-                    file.file.adjust_height(range, -change);
-                    selected_mesh_gizmo.single_mut().unwrap().translation.y += change as f32;
-                    file.edit_map(&mut commands, MapEdit::AdjustHeight(range, change));
+                    if change != *applied_change {
+                        file.file.adjust_height(range, change - *applied_change);
+                        *applied_change = change;
+                        dirty_tiles.mark(range);
+                    }
+                    if let Some(height) = snapped_height {
+                        snap_indicator.cuboid(
+                            Transform::from_xyz(
+                                (range.start.x + range.end.x) as f32 / 2.0,
+                                height as f32,
+                                (range.start.y + range.end.y) as f32 / 2.0,
+                            )
+                            .with_scale(Vec3::new(
+                                range.width() as f32 + 0.1,
+                                0.02,
+                                (range.end.y - range.start.y + 1) as f32 + 0.1,
+                            )),
+                            Srgba::rgb_u8(80, 255, 120),
+                        );
+                    }
+                } else {
+                    file.file.adjust_height(range, -*applied_change);
+                    *applied_change = 0.0;
+                    file.edit_tiles(
+                        &mut commands,
+                        MapEdit::AdjustHeight(range, change),
+                        ui_state.tile_edit_mode(),
+                    );
                     object.old_pos = transform.translation;
                 }
+                // The highlight is meshed from the tiles, so it shouldn't follow the gizmo
+                selected_mesh_gizmo.single_mut().unwrap().translation.y = -transform.translation.y;
             }
             EditObject::None => {}
         }
     }
 }
 
+/// The height of the tile next to `range` closest to `height`, if any is within `threshold`
+fn neighbor_snap_height(
+    file: &MapFile,
+    range: TileRange,
+    height: f64,
+    threshold: f64,
+) -> Option<f64> {
+    let map_range = TileRange {
+        start: MpsVec2::ZERO,
+        end: file.map_size()? - MpsVec2::ONE,
+    };
+    let around = TileRange {
+        start: range.start - MpsVec2::ONE,
+        end: range.end + MpsVec2::ONE,
+    }
+    .intersection(map_range)?;
+    around
+        .into_iter()
+        .filter(|&pos| !range.contains(pos))
+        .map(|pos| file[pos].height.max_height())
+        .filter(|neighbor| (neighbor - height).abs() <= threshold)
+        .min_by(|a, b| (a - height).abs().total_cmp(&(b - height).abs()))
+}
+
 fn update_lights(
     mut light: Query<&mut LookTransform, With<DirectionalLight>>,
     loaded_file: Res<LoadedFile>,
@@ -1339,7 +1949,8 @@ fn update_textures(
     file: Res<LoadedFile>,
     assets: Res<AssetServer>,
     images: Res<Assets<Image>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<AtlasMaterial>>,
+    mut load_errors: ResMut<TextureLoadErrors>,
 ) {
     if textures.skybox.outdated
         && let Some(fallback) = images.get(&textures.skybox.missing)
@@ -1432,8 +2043,24 @@ fn update_textures(
         materials
             .get_mut(&textures.atlas_material)
             .expect("atlas_material should exist")
+            .base
             .base_color_texture = Some(textures.atlas.current.clone());
         textures.atlas.outdated = false;
+        load_errors.atlas = None;
+    }
+
+    let atlas = &file.loaded_textures.atlas;
+    if textures.atlas.current == atlas.image
+        && let LoadState::Failed(err) = assets.load_state(&atlas.image)
+    {
+        warn!("Atlas failed to load: {}: {err}", atlas.path.display());
+        load_errors.atlas = Some((atlas.path.clone(), err.to_string()));
+        textures.atlas.current = textures.atlas.missing.clone();
+        materials
+            .get_mut(&textures.atlas_material)
+            .expect("atlas_material should exist")
+            .base
+            .base_color_texture = Some(textures.atlas.current.clone());
     }
 }
 
@@ -1582,3 +2209,30 @@ fn custom_mouse_pick_events(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CameraViews, get_player_cam_transform, reset_camera_views};
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::prelude::*;
+    use bevy_map_camera::LookTransform;
+
+    #[test]
+    fn test_reset_camera_without_skybox() {
+        let mut world = World::new();
+        // Hiding the skybox in the settings removes it from the camera
+        let camera = world
+            .spawn((Camera::default(), LookTransform::default()))
+            .id();
+        let player_pos = Vec3::new(4.0, 1.0, 2.0);
+        world
+            .run_system_once(move |cameras: CameraViews| {
+                reset_camera_views(cameras, player_pos, &Handle::default());
+            })
+            .unwrap();
+
+        let expected = get_player_cam_transform(player_pos);
+        let view = world.get::<LookTransform>(camera).unwrap();
+        assert_eq!((view.eye, view.target), (expected.eye, expected.target));
+    }
+}